@@ -2,10 +2,15 @@
 use std::{collections::BTreeMap, path::Path};
 
 use chrono::{DateTime, Duration, Utc};
-use log::debug;
+use log::{debug, warn};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 
-use crate::crates::Crate;
+use crate::{
+    crates::{Crate, Download, FetchOptions},
+    registry::Registry,
+    validator::ValidationError,
+};
 
 fn impl_save_to_path(path: &Path, data: &impl Serialize) -> Result<(), crate::error::Error> {
     debug!("Saving cache to: {}", path.to_string_lossy());
@@ -20,27 +25,26 @@ fn impl_save_to_path(path: &Path, data: &impl Serialize) -> Result<(), crate::er
         })?;
     };
 
-    let writer = std::fs::File::create(path).map_err(|e| crate::error::Error::FileSystemError {
-        path: path.to_string_lossy().to_string(),
-        error: e.kind(),
+    // Written to a temporary file renamed over the cache, an interrupted save keeps the previous cache
+    let content = serde_cbor::to_vec(data).map_err(|e| {
+        crate::error::Error::Other(
+            format!(
+                "Failed to serialize cache to {}: {}",
+                path.to_string_lossy(),
+                e
+            )
+            .into(),
+        )
+    })?;
+    crate::cargo::write_atomically(path, &content).map_err(|e| {
+        crate::error::Error::FileSystemError {
+            path: path.to_string_lossy().to_string(),
+            error: e.kind(),
+        }
     })?;
-    let writer = std::io::BufWriter::new(writer);
 
-    // Serialize the cache to CBOR format
-    serde_cbor::to_writer(writer, data)
-        .map_err(|e| {
-            crate::error::Error::Other(
-                format!(
-                    "Failed to serialize cache to {}: {}",
-                    path.to_string_lossy(),
-                    e
-                )
-                .into(),
-            )
-        })
-        .inspect(|_| {
-            debug!("Cache successfully saved to: {}", path.to_string_lossy());
-        })
+    debug!("Cache successfully saved to: {}", path.to_string_lossy());
+    Ok(())
 }
 
 fn impl_load_from_path<T: for<'de> Deserialize<'de> + Default>(
@@ -78,12 +82,21 @@ fn impl_load_from_path<T: for<'de> Deserialize<'de> + Default>(
 pub struct CrateCacheEntry {
     pub krate: Crate,
     pub last_fetched_at: DateTime<Utc>,
+    /// Whether the versions include their dependencies (see [`FetchOptions::full`])
+    #[serde(default)]
+    pub full: bool,
+    /// ETag of the response the metadata comes from, sent back as `If-None-Match` when refreshing it
+    #[serde(default)]
+    pub etag: Option<String>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
 /// In-memory representation of the cache file with convenience methods to read/write and query it.
 pub struct CrateCache {
     pub entries: BTreeMap<String, CrateCacheEntry>,
+    /// Crates found missing from their registry, keyed like `entries`, with the time of the failed fetch
+    #[serde(default)]
+    pub failures: BTreeMap<String, DateTime<Utc>>,
 }
 
 impl CrateCache {
@@ -95,18 +108,27 @@ impl CrateCache {
         impl_save_to_path(path, self)
     }
 
+    /// Retrieve the packages cached less than `cache_validity` ago. When `full` is set, entries lacking
+    /// dependency information are left out so that they get upgraded.
     pub fn retrieve_packages_no_fetch(
         &mut self,
         crate_names: &[&str],
         cache_validity: Duration,
+        registry: &Registry,
+        full: bool,
     ) -> BTreeMap<String, Crate> {
         let mut found_crates = BTreeMap::new();
         let now = Utc::now();
 
         for &name in crate_names {
-            if let Some(entry) = self.entries.get(name) {
+            if let Some(entry) = self.entries.get(&registry.cache_key(name)) {
                 let age = now.signed_duration_since(entry.last_fetched_at);
-                if age < cache_validity {
+                if full && !entry.full {
+                    debug!(
+                        "Cache entry for crate '{}' lacks dependencies, upgrading it",
+                        name
+                    );
+                } else if age < cache_validity {
                     debug!(
                         "Cache hit for crate '{}' (age: {} seconds)",
                         name,
@@ -126,46 +148,215 @@ impl CrateCache {
         found_crates
     }
 
+    /// Download `to_fetch` and record the outcome in the cache, returning the crates found. Stale
+    /// entries are only downloaded again when they changed, and used as they are when their download
+    /// fails. The batch fails when a crate that is not cached cannot be downloaded.
+    async fn fetch_batch(
+        &mut self,
+        to_fetch: &[&str],
+        fetch_opts: &FetchOptions,
+    ) -> Result<Vec<Crate>, crate::error::Error> {
+        let etags = to_fetch
+            .iter()
+            .filter_map(|&name| {
+                let entry = self.entries.get(&fetch_opts.registry.cache_key(name))?;
+                Some((name.to_string(), entry.etag.clone()?))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // Sparse indexes always list the dependencies of each version, the full crates.io
+        // metadata is gathered from several requests and never fetched conditionally
+        let (downloads, full) = match &fetch_opts.registry {
+            Registry::CratesIo if fetch_opts.full => (
+                crate::crates::download_full_crates(
+                    to_fetch,
+                    fetch_opts.concurrency,
+                    fetch_opts.retries,
+                    fetch_opts.strict,
+                    &fetch_opts.user_agent,
+                )
+                .await?,
+                true,
+            ),
+            Registry::CratesIo => (
+                crate::crates::download_crates(
+                    to_fetch,
+                    &etags,
+                    fetch_opts.concurrency,
+                    fetch_opts.retries,
+                    fetch_opts.strict,
+                    &fetch_opts.user_agent,
+                )
+                .await?,
+                false,
+            ),
+            Registry::Sparse { index_url } => (
+                crate::registry::download_sparse_crates(index_url, to_fetch, &etags, fetch_opts)
+                    .await?,
+                true,
+            ),
+        };
+
+        // Update the cache with fetched crates, unchanged ones are only marked as fresh again
+        let now = Utc::now();
+        let mut fetched_crates = Vec::with_capacity(downloads.len());
+        let mut failure = None;
+        for download in downloads {
+            match download {
+                Download::Fetched { krate, etag } => {
+                    self.entries.insert(
+                        fetch_opts.registry.cache_key(&krate.name),
+                        CrateCacheEntry {
+                            krate: krate.clone(),
+                            last_fetched_at: now,
+                            full,
+                            etag,
+                        },
+                    );
+                    fetched_crates.push(krate);
+                }
+                Download::NotModified(name) => {
+                    if let Some(entry) = self.entries.get_mut(&fetch_opts.registry.cache_key(&name))
+                    {
+                        debug!("Crate '{}' did not change since it was cached", name);
+                        entry.last_fetched_at = now;
+                        fetched_crates.push(entry.krate.clone());
+                    }
+                }
+                // A failed download falls back on the cached metadata, kept stale
+                Download::Failed { name, error } => {
+                    match self.entries.get(&fetch_opts.registry.cache_key(&name)) {
+                        Some(entry) if entry.full || !fetch_opts.full => {
+                            warn!(
+                                "Failed to fetch crate {}: {}. Using its metadata cached {} hours ago",
+                                name,
+                                error,
+                                now.signed_duration_since(entry.last_fetched_at).num_hours()
+                            );
+                            fetched_crates.push(entry.krate.clone());
+                        }
+                        _ => {
+                            failure.get_or_insert(error);
+                        }
+                    }
+                }
+            }
+        }
+        // The crates downloaded are cached even when the batch fails
+        if let Some(error) = failure {
+            return Err(error);
+        }
+
+        // Remember the crates that do not exist, missing crates are only left out when not strict
+        for &name in to_fetch {
+            let key = fetch_opts.registry.cache_key(name);
+            if fetched_crates.iter().any(|krate| krate.name == name) {
+                self.failures.remove(&key);
+            } else if !fetch_opts.negative_cache_age.is_zero() {
+                self.failures.insert(key, now);
+            }
+        }
+
+        Ok(fetched_crates)
+    }
+
     pub async fn retrieve_packages_fetch(
         &mut self,
         crate_names: &[&str],
         cache_validity: Duration,
+        fetch_opts: &FetchOptions,
     ) -> Result<BTreeMap<String, Crate>, crate::error::Error> {
-        let mut packages = self.retrieve_packages_no_fetch(crate_names, cache_validity);
+        if fetch_opts.offline {
+            return self.retrieve_packages_offline(crate_names, &fetch_opts.registry);
+        }
 
-        // Determine which crates need to be fetched
+        let mut packages = self.retrieve_packages_no_fetch(
+            crate_names,
+            cache_validity,
+            &fetch_opts.registry,
+            fetch_opts.full,
+        );
+
+        // Determine which crates need to be fetched, skipping those recently found missing
         let mut to_fetch = Vec::new();
+        let now = Utc::now();
         for &name in crate_names {
-            if !packages.contains_key(name) {
-                to_fetch.push(name);
+            if packages.contains_key(name) {
+                continue;
             }
-        }
 
-        // Fetch missing crates
-        if !to_fetch.is_empty() {
-            let fetched_crates = crate::crates::download_crates(&to_fetch).await?;
-
-            // Update the cache with fetched crates
-            let now = Utc::now();
-            for krate in fetched_crates.iter() {
-                self.entries.insert(
-                    krate.name.clone(),
-                    CrateCacheEntry {
-                        krate: krate.clone(),
-                        last_fetched_at: now,
-                    },
-                );
+            match self.failures.get(&fetch_opts.registry.cache_key(name)) {
+                Some(failed_at)
+                    if now.signed_duration_since(*failed_at) < fetch_opts.negative_cache_age =>
+                {
+                    if fetch_opts.strict {
+                        return Err(crate::error::Error::CrateNotFound(name.to_string()));
+                    }
+                    warn!(
+                        "Crate {} was not found {} minutes ago, skipping it",
+                        name,
+                        now.signed_duration_since(*failed_at).num_minutes()
+                    );
+                }
+                _ => to_fetch.push(name),
             }
+        }
 
-            // Combine previously found packages with newly fetched ones
-            for krate in fetched_crates {
+        // Fetch missing crates, in batches saved as soon as they complete when checkpointing
+        let batch_size = match &fetch_opts.cache_path {
+            Some(_) if fetch_opts.save_every > 0 => fetch_opts.save_every,
+            _ => to_fetch.len().max(1),
+        };
+        for batch in to_fetch.chunks(batch_size) {
+            for krate in self.fetch_batch(batch, fetch_opts).await? {
                 packages.insert(krate.name.clone(), krate);
             }
+
+            if fetch_opts.save_every > 0
+                && let Some(path) = &fetch_opts.cache_path
+                && let Err(e) = self.save_to_path(path)
+            {
+                warn!("Failed to save cache to {}: {}", path.display(), e);
+            }
         }
 
         Ok(packages)
     }
 
+    /// Retrieve packages from the cache regardless of their age, failing if any of them is missing.
+    pub fn retrieve_packages_offline(
+        &self,
+        crate_names: &[&str],
+        registry: &Registry,
+    ) -> Result<BTreeMap<String, Crate>, crate::error::Error> {
+        let mut found_crates = BTreeMap::new();
+        let mut missing = Vec::new();
+        let now = Utc::now();
+
+        for &name in crate_names {
+            match self.entries.get(&registry.cache_key(name)) {
+                Some(entry) => {
+                    debug!(
+                        "Offline cache hit for crate '{}' (age: {} seconds)",
+                        name,
+                        now.signed_duration_since(entry.last_fetched_at)
+                            .num_seconds()
+                    );
+                    found_crates.insert(name.to_string(), entry.krate.clone());
+                }
+                None => missing.push(name.to_string()),
+            }
+        }
+
+        if !missing.is_empty() {
+            missing.sort();
+            missing.dedup();
+            return Err(crate::error::Error::MissingFromCache(missing));
+        }
+
+        Ok(found_crates)
+    }
+
     pub fn size(&self) -> usize {
         self.entries.len()
     }
@@ -186,4 +377,196 @@ impl CrateCache {
             }
         });
     }
+
+    /// Remove the entries and fetch failures of the crates whose name matches `pattern`, from every
+    /// registry. Returns the number of entries removed.
+    pub fn remove_matching(&mut self, pattern: &glob::Pattern) -> usize {
+        let initial_count = self.entries.len();
+        self.entries.retain(|key, entry| {
+            let matches = pattern.matches(&entry.krate.name);
+            if matches {
+                debug!("Removing cache entry {}", key);
+            }
+            !matches
+        });
+        // Failures have no entry to read the name from, sparse registry keys end with it
+        self.failures
+            .retain(|key, _| !pattern.matches(key.rsplit('/').next().unwrap_or(key)));
+
+        initial_count - self.entries.len()
+    }
+
+    /// Forget the fetch failures recorded more than `negative_cache_age` ago.
+    pub fn filter_expired_failures(&mut self, negative_cache_age: Duration) {
+        let now = Utc::now();
+        self.failures
+            .retain(|_, failed_at| now.signed_duration_since(*failed_at) < negative_cache_age);
+    }
+
+    /// Merge the entries of `other` into this cache, keeping the most recently fetched entry of each
+    /// crate. Returns the number of entries added and updated.
+    pub fn merge(&mut self, other: CrateCache) -> (usize, usize) {
+        let mut added = 0;
+        let mut updated = 0;
+
+        for (name, entry) in other.entries {
+            match self.entries.get(&name) {
+                None => added += 1,
+                Some(existing) if existing.last_fetched_at < entry.last_fetched_at => updated += 1,
+                Some(_) => continue,
+            }
+            self.entries.insert(name, entry);
+        }
+
+        (added, updated)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Outcome of validating a single candidate version, `error` is `None` when the validation failed
+/// before any check could run (e.g. the candidate could not be added to the manifest).
+pub struct ValidationCacheEntry {
+    pub passed: bool,
+    pub error: Option<ValidationError>,
+    pub validated_at: DateTime<Utc>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+/// Persistent record of candidate validations, keyed by validation context, crate name and version.
+///
+/// The context is a hash of everything besides the candidate that influences the outcome (build
+/// and test options, target packages and baseline versions of the other dependencies), so that
+/// changing any of them naturally invalidates previous results.
+pub struct ValidationCache {
+    pub entries: BTreeMap<u64, BTreeMap<String, BTreeMap<Version, ValidationCacheEntry>>>,
+}
+
+impl ValidationCache {
+    pub fn load_from_path(path: &Path) -> Result<Self, crate::error::Error> {
+        impl_load_from_path(path)
+    }
+
+    pub fn save_to_path(&self, path: &Path) -> Result<(), crate::error::Error> {
+        impl_save_to_path(path, self)
+    }
+
+    /// Cached validations of `crate_name` within the given context
+    pub fn crate_entries(
+        &mut self,
+        context: u64,
+        crate_name: &str,
+    ) -> &mut BTreeMap<Version, ValidationCacheEntry> {
+        self.entries
+            .entry(context)
+            .or_default()
+            .entry(crate_name.to_string())
+            .or_default()
+    }
+
+    pub fn size(&self) -> usize {
+        self.entries
+            .values()
+            .flat_map(|crates| crates.values())
+            .map(|versions| versions.len())
+            .sum()
+    }
+
+    pub fn filter_expired_entries(&mut self, cache_validity: Duration) {
+        let now = Utc::now();
+        for crates in self.entries.values_mut() {
+            for versions in crates.values_mut() {
+                versions.retain(|_, entry| {
+                    now.signed_duration_since(entry.validated_at) < cache_validity
+                });
+            }
+            crates.retain(|_, versions| !versions.is_empty());
+        }
+        self.entries.retain(|_, crates| !crates.is_empty());
+    }
+}
+
+/// Stable (FNV-1a) hash of a serializable validation context, used as [`ValidationCache`] key.
+pub fn validation_context_hash(context: &impl Serialize) -> Result<u64, crate::error::Error> {
+    let bytes = serde_cbor::to_vec(context).map_err(|e| {
+        crate::error::Error::Other(format!("Failed to serialize validation context: {}", e).into())
+    })?;
+    Ok(bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_validation_entries_are_dropped() {
+        let entry = |age: Duration| ValidationCacheEntry {
+            passed: true,
+            error: None,
+            validated_at: Utc::now() - age,
+        };
+        let mut cache = ValidationCache::default();
+        let versions = cache.crate_entries(validation_context_hash(&"context").unwrap(), "foo");
+        versions.insert(Version::new(1, 0, 0), entry(Duration::hours(72)));
+        versions.insert(Version::new(1, 1, 0), entry(Duration::hours(1)));
+        cache
+            .crate_entries(validation_context_hash(&"other").unwrap(), "bar")
+            .insert(Version::new(0, 1, 0), entry(Duration::hours(49)));
+
+        cache.filter_expired_entries(Duration::hours(48));
+        assert_eq!(cache.size(), 1);
+        let context = validation_context_hash(&"context").unwrap();
+        assert!(cache.entries[&context]["foo"].contains_key(&Version::new(1, 1, 0)));
+        assert!(
+            !cache
+                .entries
+                .contains_key(&validation_context_hash(&"other").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_download_falls_back_on_the_cached_entry() {
+        // Nothing listens on port 1, every request fails without reaching a registry
+        let fetch_opts = FetchOptions {
+            registry: Registry::from_index_url("sparse+http://127.0.0.1:1/").unwrap(),
+            retries: 0,
+            ..Default::default()
+        };
+        let mut cache = CrateCache::default();
+        cache.entries.insert(
+            fetch_opts.registry.cache_key("foo"),
+            CrateCacheEntry {
+                krate: Crate {
+                    name: "foo".to_string(),
+                    description: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                    versions: vec![],
+                },
+                last_fetched_at: Utc::now() - Duration::days(7),
+                full: true,
+                etag: None,
+            },
+        );
+
+        let packages = cache
+            .retrieve_packages_fetch(&["foo"], Duration::hours(48), &fetch_opts)
+            .await
+            .unwrap();
+        assert!(packages.contains_key("foo"));
+
+        let error = cache
+            .retrieve_packages_fetch(&["foo", "bar"], Duration::hours(48), &fetch_opts)
+            .await;
+        assert!(matches!(
+            error,
+            Err(crate::error::Error::RegistryError { .. })
+        ));
+        assert!(
+            !cache
+                .failures
+                .contains_key(&fetch_opts.registry.cache_key("bar"))
+        );
+    }
 }
@@ -1,14 +1,21 @@
 //! Helpers for reading Cargo.toml manifests and Cargo.lock files, and modeling packages.
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
-use cargo_util_schemas::manifest::{InheritableField, TomlManifest, TomlWorkspace};
+use cargo_util_schemas::manifest::{InheritableField, RustVersion, TomlManifest, TomlWorkspace};
 use glob::Pattern;
-use log::{debug, error, warn};
-use semver::Version;
+use log::{debug, error, info, warn};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize, de::Error};
-use toml::Table;
+use toml::{
+    Table,
+    de::{DeTable, DeValue},
+};
 
-use crate::crates::Dependency;
+use crate::crates::{Dependency, DependencyKind};
 
 pub fn read_cargo_manifest(path: &Path) -> Result<TomlManifest, crate::error::Error> {
     let mut path = path.to_path_buf();
@@ -33,18 +40,224 @@ pub fn read_cargo_manifest(path: &Path) -> Result<TomlManifest, crate::error::Er
     })
 }
 
+/// Keys a member may set on a dependency inherited from `[workspace.dependencies]`, cargo ignores
+/// the others.
+const INHERITED_DEPENDENCY_KEYS: [&str; 6] = [
+    "workspace",
+    "features",
+    "optional",
+    "default-features",
+    "default_features",
+    "public",
+];
+
+/// Keys of the inherited dependencies of the raw `manifest` that cargo ignores, with the name of
+/// their dependency.
+fn ignored_inherited_keys(manifest: &Table) -> Vec<(String, Vec<String>)> {
+    let platforms = manifest.get("target").and_then(toml::Value::as_table);
+    let tables = std::iter::once(manifest).chain(
+        platforms
+            .into_iter()
+            .flat_map(|platforms| platforms.values().filter_map(toml::Value::as_table)),
+    );
+
+    let mut ignored = vec![];
+    for table in tables {
+        for kind in [
+            "dependencies",
+            "dev-dependencies",
+            "dev_dependencies",
+            "build-dependencies",
+            "build_dependencies",
+        ] {
+            let dependencies = table.get(kind).and_then(toml::Value::as_table);
+            for (name, dependency) in dependencies.into_iter().flatten() {
+                if let Some(dependency) = dependency.as_table()
+                    && dependency.get("workspace").and_then(toml::Value::as_bool) == Some(true)
+                {
+                    let keys: Vec<String> = dependency
+                        .keys()
+                        .filter(|key| !INHERITED_DEPENDENCY_KEYS.contains(&key.as_str()))
+                        .cloned()
+                        .collect();
+                    if !keys.is_empty() {
+                        ignored.push((name.clone(), keys));
+                    }
+                }
+            }
+        }
+    }
+    ignored
+}
+
+/// Whether the package in `package_dir` is a member of `workspace`, rooted at `root_dir`.
+fn is_workspace_member(workspace: &TomlWorkspace, root_dir: &Path, package_dir: &Path) -> bool {
+    let Ok(relative_path) = package_dir.strip_prefix(root_dir) else {
+        return false;
+    };
+    let relative_path = relative_path.to_string_lossy();
+    let matches = |patterns: &Option<Vec<String>>| {
+        patterns
+            .iter()
+            .flatten()
+            .filter_map(|pattern| Pattern::new(pattern).ok())
+            .any(|pattern| pattern.matches(&relative_path))
+    };
+
+    matches(&workspace.members) && !matches(&workspace.exclude)
+}
+
+/// Root directory of the workspace the package at `path` (a manifest or its directory) is a member
+/// of, found like cargo does by walking up the parent directories to the first manifest with a
+/// `[workspace]` table. `None` when `path` is a workspace root or a package outside any workspace.
+pub fn find_workspace_root(path: &Path) -> Option<PathBuf> {
+    let manifest_path = if path.is_dir() {
+        path.join("Cargo.toml")
+    } else {
+        path.to_path_buf()
+    };
+    let manifest = read_cargo_manifest(&manifest_path).ok()?;
+    if manifest.workspace.is_some() || manifest.package.is_none() {
+        return None;
+    }
+
+    let package_dir = manifest_path.canonicalize().ok()?.parent()?.to_path_buf();
+    for dir in package_dir.ancestors().skip(1) {
+        let root_manifest_path = dir.join("Cargo.toml");
+        if !root_manifest_path.is_file() {
+            continue;
+        }
+        let Some(workspace) = read_cargo_manifest(&root_manifest_path)
+            .ok()
+            .and_then(|root_manifest| root_manifest.workspace)
+        else {
+            continue;
+        };
+
+        // Cargo stops at the first workspace found, whether the package is a member of it or not
+        if !is_workspace_member(&workspace, dir, &package_dir) {
+            debug!(
+                "Package at {} is not a member of the workspace at {}",
+                package_dir.to_string_lossy(),
+                dir.to_string_lossy()
+            );
+            return None;
+        }
+        return Some(dir.to_path_buf());
+    }
+
+    None
+}
+
+/// Names of the crates overridden by the `[patch.<source>]` and `[replace]` tables of a manifest.
+pub fn overridden_crates(manifest: &TomlManifest) -> BTreeSet<String> {
+    let patched = manifest
+        .patch
+        .iter()
+        .flat_map(|sources| sources.values())
+        .flat_map(|crates| crates.keys())
+        .map(|name| name.to_string());
+    // Replacements are keyed by package id specs such as `foo:1.0.0`
+    let replaced = manifest
+        .replace
+        .iter()
+        .flat_map(|specs| specs.keys())
+        .map(|spec| spec.split([':', '@']).next().unwrap_or(spec).to_string());
+
+    patched.chain(replaced).collect()
+}
+
 /// A normalized view of a Cargo package with resolved dependencies.
 #[derive(Debug, Clone)]
 pub struct CargoPackage {
     pub manifest_path: PathBuf,
     pub version: Version,
     pub name: String,
+    /// The `rust-version` (MSRV) declared by the package, if any
+    pub rust_version: Option<RustVersion>,
     pub dependencies: Vec<Dependency>,
     pub build_dependencies: Vec<Dependency>,
     pub dev_dependencies: Vec<Dependency>,
+    /// The `[features]` table, mapping each feature to the features and dependencies it enables
+    pub features: BTreeMap<String, Vec<String>>,
 }
 
 impl CargoPackage {
+    /// Tables of the manifest declaring `crate_name`, in `[dependencies]`, `[dev-dependencies]` then
+    /// `[build-dependencies]` order.
+    pub fn dependency_kinds(&self, crate_name: &str) -> Vec<DependencyKind> {
+        [
+            (DependencyKind::Normal, &self.dependencies),
+            (DependencyKind::Dev, &self.dev_dependencies),
+            (DependencyKind::Build, &self.build_dependencies),
+        ]
+        .into_iter()
+        .filter(|(_, dependencies)| dependencies.iter().any(|dep| dep.crate_name == crate_name))
+        .map(|(kind, _)| kind)
+        .collect()
+    }
+
+    /// Optional dependencies enabled when building with `features`, as given to `--features` (comma
+    /// separated, possibly prefixed by the package name), and the default features unless
+    /// `default_features` is unset, following the `[features]` table.
+    pub fn enabled_optional_dependencies(
+        &self,
+        features: &[String],
+        default_features: bool,
+    ) -> BTreeSet<String> {
+        let optional = self
+            .dependencies
+            .iter()
+            .chain(&self.build_dependencies)
+            .filter(|dep| dep.optional)
+            .map(|dep| dep.crate_name.as_str())
+            .collect::<BTreeSet<_>>();
+        let package_prefix = format!("{}/", self.name);
+
+        let mut pending = Some("default")
+            .filter(|_| default_features)
+            .into_iter()
+            .chain(features.iter().flat_map(|f| f.split([',', ' '])))
+            .filter(|value| !value.is_empty())
+            .map(|value| value.strip_prefix(&package_prefix).unwrap_or(value))
+            .collect::<Vec<_>>();
+        let mut visited = BTreeSet::new();
+        let mut enabled = BTreeSet::new();
+        while let Some(value) = pending.pop() {
+            if !visited.insert(value) {
+                continue;
+            }
+
+            if let Some(name) = value.strip_prefix("dep:") {
+                enabled.insert(name.to_string());
+            } else if let Some((dependency, _)) = value.split_once('/') {
+                // `dep/feature` enables the dependency, `dep?/feature` only if it already is
+                if !dependency.ends_with('?') {
+                    pending.push(dependency);
+                }
+            } else if let Some(values) = self.features.get(value) {
+                pending.extend(values.iter().map(String::as_str));
+            } else if optional.contains(value) {
+                // Optional dependencies not referenced with `dep:` have an implicit feature
+                enabled.insert(value.to_string());
+            }
+        }
+
+        enabled
+    }
+
+    /// Flag the dependencies overridden by `[patch]` or `[replace]`, they are built from their override.
+    fn mark_overridden(&mut self, overridden: &BTreeSet<String>) {
+        for dependency in self
+            .dependencies
+            .iter_mut()
+            .chain(self.build_dependencies.iter_mut())
+            .chain(self.dev_dependencies.iter_mut())
+        {
+            dependency.patched = overridden.contains(&dependency.crate_name);
+        }
+    }
+
     pub fn from_target(
         manifest_path: &Path,
         manifest: TomlManifest,
@@ -60,49 +273,132 @@ impl CargoPackage {
         }
 
         let package_name = package.name.unwrap().to_string();
+
+        // The schema types do not expose the keys they do not know, look for them in the raw manifest
+        let manifest_file = if manifest_path.is_dir() {
+            manifest_path.join("Cargo.toml")
+        } else {
+            manifest_path.to_path_buf()
+        };
+        if let Ok(raw) = std::fs::read_to_string(&manifest_file)
+            && let Ok(raw) = raw.parse::<Table>()
+        {
+            for (dependency, keys) in ignored_inherited_keys(&raw) {
+                warn!(
+                    "Ignoring {} of dependency {} in {}, an inherited dependency only accepts features, optional and default-features",
+                    keys.iter()
+                        .map(|key| format!("'{}'", key))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    dependency,
+                    manifest_file.to_string_lossy()
+                );
+            }
+        }
         let version = package.version.map(|v| match v {
             InheritableField::Value(v) => Ok(v),
             InheritableField::Inherit(_) => {
-                if workspace.is_none() {
+                let Some(workspace) = workspace else {
                     error!(
                         "Package {} is trying to inherit version from workspace, but no workspace is defined",
                         package_name
                     );
                     return Err(crate::error::Error::Other("Cannot inherit version from workspace".into()));
-                }
+                };
+
+                let Some(workspace_package) = workspace.package.as_ref() else {
+                    error!(
+                        "Package {} is trying to inherit version from workspace, but the workspace has no [workspace.package] table",
+                        package_name
+                    );
+                    return Err(crate::error::Error::Other(
+                        format!("Cannot inherit version for package {}: missing [workspace.package] table", package_name).into(),
+                    ));
+                };
 
-                todo!()
+                workspace_package.version.clone().ok_or_else(|| {
+                    error!(
+                        "Package {} is trying to inherit version from workspace, but [workspace.package] does not define a version",
+                        package_name
+                    );
+                    crate::error::Error::Other(
+                        format!("Cannot inherit version for package {}: [workspace.package] has no version", package_name).into(),
+                    )
+                })
             }
         }).unwrap_or(Ok(Version::new(0, 1, 0)))?;
 
-        let dependencies = manifest
+        let rust_version = match package.rust_version {
+            Some(InheritableField::Value(rust_version)) => Some(rust_version),
+            Some(InheritableField::Inherit(_)) => Some(
+                workspace
+                    .and_then(|workspace| workspace.package.as_ref())
+                    .and_then(|workspace_package| workspace_package.rust_version.clone())
+                    .ok_or_else(|| {
+                        crate::error::Error::Other(
+                            format!(
+                                "Cannot inherit rust-version for package {}: [workspace.package] has no rust-version",
+                                package_name
+                            )
+                            .into(),
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+
+        let features = manifest
+            .features
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, values)| (name.to_string(), values))
+            .collect();
+
+        let mut dependencies = manifest
             .dependencies
             .unwrap_or_default()
             .iter()
-            .map(|(name, dep)| Dependency::from_cargo_toml(name, dep, workspace))
+            .map(|(name, dep)| Dependency::from_cargo_toml(manifest_path, name, dep, workspace))
             .collect::<Result<Vec<_>, _>>()?;
 
-        let build_dependencies = manifest
+        let mut build_dependencies = manifest
             .build_dependencies
             .unwrap_or_default()
             .iter()
-            .map(|(name, dep)| Dependency::from_cargo_toml(name, dep, workspace))
+            .map(|(name, dep)| Dependency::from_cargo_toml(manifest_path, name, dep, workspace))
             .collect::<Result<Vec<_>, _>>()?;
 
-        let dev_dependencies = manifest
+        let mut dev_dependencies = manifest
             .dev_dependencies
             .unwrap_or_default()
             .iter()
-            .map(|(name, dep)| Dependency::from_cargo_toml(name, dep, workspace))
+            .map(|(name, dep)| Dependency::from_cargo_toml(manifest_path, name, dep, workspace))
             .collect::<Result<Vec<_>, _>>()?;
 
+        // Platform-specific dependencies, tagged with the platform of their table
+        for (platform, tables) in manifest.target.unwrap_or_default() {
+            for (deps, target_deps) in [
+                (&mut dependencies, tables.dependencies.as_ref()),
+                (&mut build_dependencies, tables.build_dependencies()),
+                (&mut dev_dependencies, tables.dev_dependencies()),
+            ] {
+                for (name, dep) in target_deps.into_iter().flatten() {
+                    let mut dep = Dependency::from_cargo_toml(manifest_path, name, dep, workspace)?;
+                    dep.target = Some(platform.clone());
+                    deps.push(dep);
+                }
+            }
+        }
+
         Ok(Some(Self {
             manifest_path: manifest_path.to_path_buf(),
             version,
             name: package_name,
+            rust_version,
             dependencies,
             build_dependencies,
             dev_dependencies,
+            features,
         }))
     }
 }
@@ -110,7 +406,7 @@ impl CargoPackage {
 /// Either a single package or a collection of packages from a workspace.
 #[derive(Debug, Clone)]
 pub enum Cargo {
-    Single(CargoPackage),
+    Single(Box<CargoPackage>),
     Workspace(Vec<CargoPackage>),
 }
 
@@ -119,6 +415,11 @@ impl Cargo {
         let path = path.to_path_buf();
         let main_manifest = read_cargo_manifest(&path)?;
 
+        let overridden = overridden_crates(&main_manifest);
+        if !overridden.is_empty() {
+            debug!("Crates overridden by [patch]/[replace]: {:?}", overridden);
+        }
+
         if main_manifest.workspace.is_none() {
             let package = CargoPackage::from_target(&path, main_manifest, None)?;
 
@@ -130,24 +431,34 @@ impl Cargo {
                 return Err("No package found in Cargo manifest".into());
             }
 
-            return Ok(Cargo::Single(package.unwrap()));
+            let mut package = package.unwrap();
+            package.mark_overridden(&overridden);
+            return Ok(Cargo::Single(Box::new(package)));
         }
 
         // It's a workspace, read all member manifests
         let workspace = main_manifest.workspace.as_ref().unwrap();
-        let positive_matchers = workspace
-            .members
-            .iter()
-            .flat_map(|x| x.iter())
-            .map(|s| Pattern::new(s).unwrap())
-            .collect::<Vec<_>>();
-        let negative_matchers = workspace
-            .exclude
-            .iter()
-            .flat_map(|x| x.iter())
-            .map(|s| Pattern::new(s).unwrap())
-            .collect::<Vec<_>>();
+        let workspace_patterns = |patterns: Option<&Vec<String>>| {
+            patterns
+                .into_iter()
+                .flatten()
+                .map(|pattern| {
+                    Pattern::new(pattern).map_err(|error| {
+                        crate::error::Error::InvalidWorkspacePattern {
+                            manifest: path.join("Cargo.toml").to_string_lossy().to_string(),
+                            pattern: pattern.clone(),
+                            error,
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let positive_matchers = workspace_patterns(workspace.members.as_ref())?;
+        let negative_matchers = workspace_patterns(workspace.exclude.as_ref())?;
         let mut packages = vec![];
+        // Directories holding a manifest, and the members patterns matching at least one of them
+        let mut available = vec![];
+        let mut matched_patterns = BTreeSet::new();
 
         debug!(
             "Workspace positive matchers: {:?}, negative matchers: {:?}",
@@ -163,11 +474,21 @@ impl Cargo {
 
         // Read all Cargo.toml files in the workspace
         let pattern = path.join("**/Cargo.toml");
-        for entry in glob::glob(pattern.to_str().unwrap()).unwrap() {
-            if entry.is_err() {
-                warn!("Failed to read entry in workspace: {:?}", entry);
+        let entries = glob::glob(&pattern.to_string_lossy()).map_err(|error| {
+            crate::error::Error::InvalidWorkspacePattern {
+                manifest: path.join("Cargo.toml").to_string_lossy().to_string(),
+                pattern: pattern.to_string_lossy().to_string(),
+                error,
             }
-            let entry_path = entry.unwrap();
+        })?;
+        for entry in entries {
+            let entry_path = match entry {
+                Ok(entry_path) => entry_path,
+                Err(e) => {
+                    warn!("Failed to read entry in workspace: {}", e);
+                    continue;
+                }
+            };
 
             // Skip the manifest of the main workspace
             if entry_path == path.join("Cargo.toml") {
@@ -177,6 +498,13 @@ impl Cargo {
             // Determine the relative path without the last Cargo.toml component
             let relative_path = entry_path.strip_prefix(&path).unwrap().parent().unwrap();
             let relative_path_str = relative_path.to_string_lossy();
+            available.push(relative_path_str.to_string());
+            matched_patterns.extend(
+                positive_matchers
+                    .iter()
+                    .filter(|p| p.matches(&relative_path_str))
+                    .map(|p| p.as_str().to_string()),
+            );
             let is_included = positive_matchers
                 .iter()
                 .any(|p| p.matches(&relative_path_str));
@@ -210,20 +538,464 @@ impl Cargo {
                     continue;
                 }
 
-                // Add the package to the workspace
-                packages.push(package.unwrap());
+                // Add the package to the workspace, overrides are defined by the workspace root
+                let mut package = package.unwrap();
+                package.mark_overridden(&overridden);
+                packages.push(package);
             }
         }
 
+        // A pattern matching no directory is most likely a typo in the members
+        for pattern in positive_matchers
+            .iter()
+            .filter(|p| !matched_patterns.contains(p.as_str()))
+        {
+            warn!(
+                "Workspace member pattern '{}' matches no package directory. Available: {:?}",
+                pattern.as_str(),
+                available
+            );
+        }
+
         Ok(Cargo::Workspace(packages))
     }
 }
 
+/// Glob patterns of `includes`.
+fn include_patterns(includes: &[String]) -> Result<Vec<Pattern>, crate::error::Error> {
+    includes
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|error| crate::error::Error::InvalidIncludePattern {
+                pattern: pattern.clone(),
+                error,
+            })
+        })
+        .collect()
+}
+
+/// Keep the dependencies of `cargo_package` whose name matches one of the `includes` patterns, all of
+/// them when there is none.
+fn retain_included_dependencies(
+    cargo_package: &mut CargoPackage,
+    includes: &[String],
+) -> Result<(), crate::error::Error> {
+    if includes.is_empty() {
+        return Ok(());
+    }
+
+    let include_patterns = include_patterns(includes)?;
+    let is_included = |dep: &Dependency| {
+        include_patterns
+            .iter()
+            .any(|pat| pat.matches(&dep.crate_name))
+    };
+
+    cargo_package.dependencies.retain(is_included);
+    cargo_package.build_dependencies.retain(is_included);
+    cargo_package.dev_dependencies.retain(is_included);
+
+    if cargo_package.dependencies.is_empty()
+        && cargo_package.build_dependencies.is_empty()
+        && cargo_package.dev_dependencies.is_empty()
+    {
+        warn!(
+            "No dependency of package {} matched the provided include patterns: {:?}",
+            cargo_package.name, includes
+        );
+    }
+
+    Ok(())
+}
+
+/// Read the package at `member_path` within the workspace rooted at `root`, so that it inherits from
+/// the workspace. `includes` filter its dependencies as for a single package.
+fn read_workspace_member(
+    root: &Path,
+    member_path: &Path,
+    includes: &[String],
+) -> Result<Vec<CargoPackage>, crate::error::Error> {
+    let member_manifest = if member_path.is_dir() {
+        member_path.join("Cargo.toml")
+    } else {
+        member_path.to_path_buf()
+    };
+    let member_manifest = member_manifest.canonicalize().unwrap_or(member_manifest);
+    info!(
+        "{} is a member of the workspace at {}, resolving it within the workspace",
+        member_manifest.display(),
+        root.display()
+    );
+
+    let packages = match Cargo::from_path(root)? {
+        Cargo::Workspace(packages) => packages,
+        Cargo::Single(package) => vec![*package],
+    };
+    let mut cargo_package = packages
+        .into_iter()
+        .find(|package| {
+            package
+                .manifest_path
+                .canonicalize()
+                .is_ok_and(|path| path == member_manifest)
+        })
+        .ok_or_else(|| crate::error::Error::NotAWorkspaceMember {
+            member: member_manifest.to_string_lossy().to_string(),
+            root: root.to_string_lossy().to_string(),
+        })?;
+
+    retain_included_dependencies(&mut cargo_package, includes)?;
+    Ok(vec![cargo_package])
+}
+
+/// Packages to resolve at `path`: a single package, whose dependencies are filtered by the `includes`
+/// patterns, or the members of a workspace matching them. A workspace member is read within its
+/// workspace.
+pub fn read_targets(
+    path: &Path,
+    includes: &[String],
+) -> Result<Vec<CargoPackage>, crate::error::Error> {
+    // Run from a workspace member, the workspace provides its inherited settings
+    if let Some(root) = find_workspace_root(path) {
+        return read_workspace_member(&root, path, includes);
+    }
+
+    // Match include patterns: dependency names for a single package, member names for a workspace
+    match Cargo::from_path(path)? {
+        Cargo::Single(mut cargo_package) => {
+            retain_included_dependencies(&mut cargo_package, includes)?;
+            Ok(vec![*cargo_package])
+        }
+        Cargo::Workspace(cargo_packages) => {
+            if includes.is_empty() {
+                return Err(crate::error::Error::MissingIncludePatterns);
+            }
+
+            let include_patterns = include_patterns(includes)?;
+            let targets = cargo_packages
+                .iter()
+                .filter(|pkg| {
+                    include_patterns
+                        .iter()
+                        .any(|pat| pat.matches(pkg.name.as_ref()))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if targets.is_empty() {
+                return Err(crate::error::Error::NoMatchingPackages {
+                    patterns: includes.to_vec(),
+                    available: cargo_packages.iter().map(|p| p.name.clone()).collect(),
+                });
+            }
+
+            Ok(targets)
+        }
+    }
+}
+
+/// Contents of manifest and lock files captured before they get modified, so they can be restored.
+#[derive(Debug, Clone)]
+pub struct ManifestSnapshot {
+    /// Captured files, `None` when the file did not exist at capture time
+    files: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl ManifestSnapshot {
+    /// Capture the current contents of `paths`, directories are resolved to their `Cargo.toml`.
+    pub fn capture(paths: impl IntoIterator<Item = PathBuf>) -> Result<Self, crate::error::Error> {
+        let mut files: Vec<(PathBuf, Option<Vec<u8>>)> = vec![];
+
+        for path in paths {
+            let path = if path.is_dir() {
+                path.join("Cargo.toml")
+            } else {
+                path
+            };
+            if files.iter().any(|(p, _)| *p == path) {
+                continue;
+            }
+
+            let content = match std::fs::read(&path) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(crate::error::Error::FileSystemError {
+                        path: path.to_string_lossy().to_string(),
+                        error: e.kind(),
+                    });
+                }
+            };
+
+            debug!("Captured snapshot of {}", path.to_string_lossy());
+            files.push((path, content));
+        }
+
+        Ok(Self { files })
+    }
+
+    /// Captured files and their contents, `None` for files that did not exist.
+    pub fn files(&self) -> &[(PathBuf, Option<Vec<u8>>)] {
+        &self.files
+    }
+
+    /// Write the captured contents back, removing files that did not exist at capture time.
+    ///
+    /// Each file is written to a temporary file next to it which is then renamed over it, so that an
+    /// interrupted restore never leaves a truncated file behind.
+    pub fn restore(&self) -> Result<(), crate::error::Error> {
+        for (path, content) in &self.files {
+            let result = match content {
+                Some(content) => write_atomically(path, content),
+                None if path.exists() => std::fs::remove_file(path),
+                None => Ok(()),
+            };
+
+            result.map_err(|e| crate::error::Error::FileSystemError {
+                path: path.to_string_lossy().to_string(),
+                error: e.kind(),
+            })?;
+            debug!("Restored {}", path.to_string_lossy());
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace the contents of `path` through a temporary file renamed over it. A symbolic link is
+/// followed, so that the file it points to is replaced rather than the link, and the permissions of
+/// the replaced file are kept.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let path = match std::fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".cargo-compat.tmp");
+    let temporary = PathBuf::from(temporary);
+    let permissions = std::fs::metadata(&path)
+        .map(|metadata| metadata.permissions())
+        .ok();
+
+    std::fs::write(&temporary, content)
+        .and_then(|_| match permissions {
+            Some(permissions) => std::fs::set_permissions(&temporary, permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| std::fs::rename(&temporary, &path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temporary);
+        })
+}
+
+/// Restores a [`ManifestSnapshot`] when dropped, unless the modifications were explicitly committed.
+#[derive(Debug)]
+pub struct ManifestGuard {
+    snapshot: ManifestSnapshot,
+    committed: bool,
+}
+
+impl ManifestGuard {
+    pub fn new(snapshot: ManifestSnapshot) -> Self {
+        Self {
+            snapshot,
+            committed: false,
+        }
+    }
+
+    /// Restore the original contents right away.
+    pub fn restore(&self) -> Result<(), crate::error::Error> {
+        self.snapshot.restore()
+    }
+
+    /// The original contents
+    pub fn snapshot(&self) -> &ManifestSnapshot {
+        &self.snapshot
+    }
+
+    /// Keep the current contents, the snapshot will not be restored on drop.
+    pub fn commit(&mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for ManifestGuard {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        debug!("Restoring original manifests");
+        if let Err(e) = self.snapshot.restore() {
+            error!("Failed to restore original manifests: {}", e);
+        }
+    }
+}
+
+/// Spans of the version requirements of `name` in the dependency tables nested in `table` (whose
+/// keys from the document root are `path`) selected by `is_dependency_table`: the string of a
+/// `name = "1"` entry, or the `version` of a `name = { .. }` inline table, a `[<table>.name]` table
+/// or a `name.version` dotted key. `None` for an entry without a version, such as a path dependency.
+fn dependency_req_spans(
+    table: &DeTable<'_>,
+    path: &mut Vec<String>,
+    name: &str,
+    is_dependency_table: &impl Fn(&[String]) -> bool,
+    spans: &mut Vec<Option<Range<usize>>>,
+) {
+    let in_dependency_table = is_dependency_table(path);
+    for (key, value) in table.iter() {
+        if in_dependency_table && key.get_ref() == name {
+            let version = match value.get_ref() {
+                DeValue::String(_) => Some(value),
+                DeValue::Table(entry) => entry
+                    .iter()
+                    .find(|(key, _)| key.get_ref() == "version")
+                    .map(|(_, version)| version)
+                    .filter(|version| version.get_ref().is_str()),
+                _ => None,
+            };
+            spans.push(version.map(|version| version.span()));
+        } else if let Some(subtable) = value.get_ref().as_table() {
+            path.push(key.get_ref().to_string());
+            dependency_req_spans(subtable, path, name, is_dependency_table, spans);
+            path.pop();
+        }
+    }
+}
+
+/// Rewrite the version requirement of `name` in the dependency tables of the manifest at
+/// `manifest_path` selected by `is_dependency_table`, described as `tables` in errors.
+///
+/// The manifest is parsed to locate each string holding the requirement, which is then replaced in
+/// the original text (keeping its quoting style), leaving the formatting, comments and other keys of
+/// the manifest untouched. Every form Cargo accepts is supported: `name = "1"`,
+/// `name = { version = "1" }` (possibly spanning several lines through its arrays), the dotted
+/// `name.version = "1"` and a `[<table>.name]` table.
+fn rewrite_dependency_req(
+    manifest_path: &Path,
+    name: &str,
+    version_req: &VersionReq,
+    is_dependency_table: impl Fn(&[String]) -> bool,
+    tables: &str,
+) -> Result<(), crate::error::Error> {
+    let file_system_error = |e: std::io::Error| crate::error::Error::FileSystemError {
+        path: manifest_path.to_string_lossy().to_string(),
+        error: e.kind(),
+    };
+    let content = std::fs::read_to_string(manifest_path).map_err(file_system_error)?;
+    let document =
+        DeTable::parse(&content).map_err(|error| crate::error::Error::CargoManifestParseError {
+            path: manifest_path.to_string_lossy().to_string(),
+            error,
+        })?;
+    let requirement = version_req.to_string();
+
+    let mut spans = vec![];
+    dependency_req_spans(
+        document.get_ref(),
+        &mut vec![],
+        name,
+        &is_dependency_table,
+        &mut spans,
+    );
+    if spans.is_empty() {
+        return Err(crate::error::Error::Other(
+            format!(
+                "Dependency {} not found in {} of {}",
+                name,
+                tables,
+                manifest_path.to_string_lossy()
+            )
+            .into(),
+        ));
+    }
+    let Some(mut spans) = spans.into_iter().collect::<Option<Vec<_>>>() else {
+        return Err(crate::error::Error::Other(
+            format!(
+                "Dependency {} in {} of {} has no version requirement to update",
+                name,
+                tables,
+                manifest_path.to_string_lossy()
+            )
+            .into(),
+        ));
+    };
+
+    // Replace from the end of the file, so that the spans left to replace remain valid
+    spans.sort_by_key(|span| std::cmp::Reverse(span.start));
+    let mut rewritten = content.clone();
+    for span in spans {
+        let quote = if content[span.clone()].starts_with('\'') {
+            '\''
+        } else {
+            '"'
+        };
+        rewritten.replace_range(span, &format!("{quote}{requirement}{quote}"));
+    }
+
+    debug!(
+        "Set dependency {} to {} in {} of {}",
+        name,
+        requirement,
+        tables,
+        manifest_path.to_string_lossy()
+    );
+    std::fs::write(manifest_path, rewritten).map_err(file_system_error)
+}
+
+/// Rewrite the version requirement of `name` in the `[workspace.dependencies]` table of the manifest at
+/// `manifest_path`, in place since cargo only edits the dependency tables of packages.
+pub fn set_workspace_dependency_req(
+    manifest_path: &Path,
+    name: &str,
+    version_req: &VersionReq,
+) -> Result<(), crate::error::Error> {
+    rewrite_dependency_req(
+        manifest_path,
+        name,
+        version_req,
+        |table| table == ["workspace", "dependencies"],
+        "[workspace.dependencies]",
+    )
+}
+
+/// Rewrite the version requirement of `name` in place in the `kind` dependency tables of the package
+/// manifest at `manifest_path`, including the platform-specific ones (`[target.'cfg(..)'.dependencies]`),
+/// rather than through `cargo add` which reformats the entry.
+pub fn set_package_dependency_req(
+    manifest_path: &Path,
+    name: &str,
+    version_req: &VersionReq,
+    kind: DependencyKind,
+) -> Result<(), crate::error::Error> {
+    let table_name = match kind {
+        DependencyKind::Normal => "dependencies",
+        DependencyKind::Dev => "dev-dependencies",
+        DependencyKind::Build => "build-dependencies",
+    };
+    rewrite_dependency_req(
+        manifest_path,
+        name,
+        version_req,
+        |table| match table {
+            [table] => table == table_name,
+            [target, _, table] => target == "target" && table == table_name,
+            _ => false,
+        },
+        &format!("[{}]", table_name),
+    )
+}
+
 /// Package entries parsed from Cargo.lock
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CargoLockPackages {
     pub name: String,
     pub version: Version,
+    /// Checksum of the downloaded archive, recorded for registry packages only
+    #[serde(default)]
+    pub checksum: Option<String>,
 }
 
 /// Minimal representation of a Cargo.lock file containing the packages array.
@@ -284,6 +1056,10 @@ impl CargoLockFile {
             let cargo_lock_package = CargoLockPackages {
                 name: name.to_string(),
                 version,
+                checksum: package
+                    .get("checksum")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
             };
             packages.push(cargo_lock_package);
         }
@@ -293,3 +1069,638 @@ impl CargoLockFile {
 }
 
 // pub fn read_cargo(path: &Path)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `files` (relative path and content) into a fresh directory named after `test` and
+    /// return it.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("cargo-compat-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    /// Write `content` to a manifest in a fresh directory named after `test` and return its path.
+    fn write_manifest(test: &str, content: &str) -> PathBuf {
+        write_files(test, &[("Cargo.toml", content)]).join("Cargo.toml")
+    }
+
+    /// Members of the workspace at `dir`, sorted by name.
+    fn read_members(dir: &Path) -> Vec<CargoPackage> {
+        let Cargo::Workspace(mut members) = Cargo::from_path(dir).unwrap() else {
+            panic!("{} is not a workspace", dir.display());
+        };
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        members
+    }
+
+    /// Set the requirement of `foo` in the `kind` tables of `content`, returning the new manifest.
+    fn set_foo_req(test: &str, content: &str, kind: DependencyKind) -> Result<String, String> {
+        let manifest_path = write_manifest(test, content);
+        let result = set_package_dependency_req(
+            &manifest_path,
+            "foo",
+            &VersionReq::parse(">=1.2, <2").unwrap(),
+            kind,
+        );
+        let written = std::fs::read_to_string(&manifest_path).unwrap();
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        result.map(|_| written).map_err(|e| e.to_string())
+    }
+
+    const PACKAGE: &str = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n\n";
+
+    #[test]
+    fn rewrites_plain_string_requirement() {
+        let manifest = format!("{PACKAGE}[dependencies]\nfoo = \"1\" # pinned\nbar = \"1\"\n");
+        assert_eq!(
+            set_foo_req("plain", &manifest, DependencyKind::Normal).unwrap(),
+            format!("{PACKAGE}[dependencies]\nfoo = \">=1.2, <2\" # pinned\nbar = \"1\"\n")
+        );
+    }
+
+    #[test]
+    fn rewrites_only_the_version_of_an_inline_table() {
+        let manifest =
+            format!("{PACKAGE}[dependencies]\nfoo = {{ features = [\"x\"], version = \"1\" }}\n");
+        assert_eq!(
+            set_foo_req("inline", &manifest, DependencyKind::Normal).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies]\nfoo = {{ features = [\"x\"], version = \">=1.2, <2\" }}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_single_quotes() {
+        let manifest =
+            format!("{PACKAGE}[dependencies]\nfoo = {{ version = '1', features = [\"x\"] }}\n");
+        assert_eq!(
+            set_foo_req("single-quoted", &manifest, DependencyKind::Normal).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies]\nfoo = {{ version = '>=1.2, <2', features = [\"x\"] }}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_dotted_version_key() {
+        let manifest =
+            format!("{PACKAGE}[dependencies]\nfoo.version = \"1\"\nfoo.features = [\"x\"]\n");
+        assert_eq!(
+            set_foo_req("dotted", &manifest, DependencyKind::Normal).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies]\nfoo.version = \">=1.2, <2\"\nfoo.features = [\"x\"]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_dependency_subtable() {
+        let manifest = format!(
+            "{PACKAGE}[dependencies.foo]\n# Keep in sync with bar\nversion = \"1\"\nfeatures = [\"x\"]\n"
+        );
+        assert_eq!(
+            set_foo_req("subtable", &manifest, DependencyKind::Normal).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies.foo]\n# Keep in sync with bar\nversion = \">=1.2, <2\"\nfeatures = [\"x\"]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_inline_table_spanning_several_lines() {
+        let manifest = format!(
+            "{PACKAGE}[dependencies]\nfoo = {{ version = \"1\", features = [\n    \"x\", # the x feature\n    \"y\",\n] }}\n"
+        );
+        assert_eq!(
+            set_foo_req("multi-line", &manifest, DependencyKind::Normal).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies]\nfoo = {{ version = \">=1.2, <2\", features = [\n    \"x\", # the x feature\n    \"y\",\n] }}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_platform_specific_table_of_the_kind_only() {
+        let manifest = format!(
+            "{PACKAGE}[dependencies]\nfoo = \"1\"\n\n[target.'cfg(unix)'.dev-dependencies]\nfoo = \"1.1\"\n"
+        );
+        assert_eq!(
+            set_foo_req("platform", &manifest, DependencyKind::Dev).unwrap(),
+            format!(
+                "{PACKAGE}[dependencies]\nfoo = \"1\"\n\n[target.'cfg(unix)'.dev-dependencies]\nfoo = \">=1.2, <2\"\n"
+            )
+        );
+    }
+
+    /// Set the requirement of `foo` in the `[workspace.dependencies]` of `content`.
+    fn set_workspace_foo_req(test: &str, content: &str) -> Result<String, String> {
+        let manifest_path = write_manifest(test, content);
+        let result = set_workspace_dependency_req(
+            &manifest_path,
+            "foo",
+            &VersionReq::parse("^1.2").unwrap(),
+        );
+        let written = std::fs::read_to_string(&manifest_path).unwrap();
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        result.map(|_| written).map_err(|e| e.to_string())
+    }
+
+    const WORKSPACE: &str = "[workspace]\nmembers = [\"a\", \"b\"]\n\n";
+
+    #[test]
+    fn rewrites_workspace_dependency_entries() {
+        for (test, entry, expected) in [
+            ("ws-plain", "foo = \"1\"", "foo = \"^1.2\""),
+            (
+                "ws-single-quoted",
+                "foo = { version = '1', features = [\"x\"] }",
+                "foo = { version = '^1.2', features = [\"x\"] }",
+            ),
+            (
+                "ws-dotted",
+                "foo.version = \"1\" # shared",
+                "foo.version = \"^1.2\" # shared",
+            ),
+        ] {
+            let manifest = format!("{WORKSPACE}[workspace.dependencies]\n{entry}\nbar = \"1\"\n");
+            assert_eq!(
+                set_workspace_foo_req(test, &manifest).unwrap(),
+                format!("{WORKSPACE}[workspace.dependencies]\n{expected}\nbar = \"1\"\n"),
+                "{test}"
+            );
+        }
+    }
+
+    #[test]
+    fn rewrites_workspace_dependency_subtable_only() {
+        let manifest = format!(
+            "{WORKSPACE}[workspace.dependencies.foo]\nversion = \"1\"\ndefault-features = false\n\n[dependencies]\nfoo = \"1\"\n"
+        );
+        assert_eq!(
+            set_workspace_foo_req("ws-subtable", &manifest).unwrap(),
+            format!(
+                "{WORKSPACE}[workspace.dependencies.foo]\nversion = \"^1.2\"\ndefault-features = false\n\n[dependencies]\nfoo = \"1\"\n"
+            )
+        );
+
+        let manifest = format!("{WORKSPACE}[workspace.dependencies]\nbar = \"1\"\n");
+        let error = set_workspace_foo_req("ws-missing", &manifest).unwrap_err();
+        assert!(
+            error.contains("not found in [workspace.dependencies]"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn members_inherit_workspace_dependencies() {
+        let dir = write_files(
+            "ws-inherit",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nfoo = \"1.2\"\n",
+                ),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo.workspace = true\n",
+                ),
+                (
+                    "b/Cargo.toml",
+                    "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo = { workspace = true }\nbar = \"1\"\n",
+                ),
+            ],
+        );
+        let members = read_members(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(members.len(), 2);
+        for member in &members {
+            let foo = member
+                .dependencies
+                .iter()
+                .find(|dep| dep.crate_name == "foo")
+                .unwrap();
+            assert!(foo.workspace, "{}", member.name);
+            assert_eq!(foo.required_version, VersionReq::parse("1.2").unwrap());
+        }
+        let bar = &members[1].dependencies;
+        assert!(
+            bar.iter()
+                .any(|dep| dep.crate_name == "bar" && !dep.workspace)
+        );
+    }
+
+    #[test]
+    fn members_add_features_to_inherited_dependencies() {
+        let dir = write_files(
+            "ws-inherit-features",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\nfoo = { version = \"1.2\", features = [\"x\"] }\n",
+                ),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo = { workspace = true, features = [\"y\", \"x\"], optional = true }\n",
+                ),
+            ],
+        );
+        let members = read_members(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let foo = &members[0].dependencies[0];
+        assert!(foo.workspace);
+        assert!(foo.optional);
+        assert_eq!(foo.features, vec!["x", "y"]);
+        assert_eq!(foo.required_version, VersionReq::parse("1.2").unwrap());
+    }
+
+    #[test]
+    fn member_run_from_its_directory_is_read_within_its_workspace() {
+        let dir = write_files(
+            "ws-nested-member",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/skip\"]\n\n[workspace.dependencies]\nfoo = \"1.2\"\n",
+                ),
+                (
+                    "crates/a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo.workspace = true\n",
+                ),
+                ("crates/b/Cargo.toml", PACKAGE),
+                ("crates/skip/Cargo.toml", PACKAGE),
+            ],
+        );
+        let root = dir.canonicalize().unwrap();
+        let member_dir = dir.join("crates/a");
+
+        assert_eq!(find_workspace_root(&member_dir), Some(root.clone()));
+        assert_eq!(
+            find_workspace_root(&member_dir.join("Cargo.toml")),
+            Some(root)
+        );
+        // Neither the root itself nor an excluded package belong to a workspace to run from
+        assert_eq!(find_workspace_root(&dir), None);
+        assert_eq!(find_workspace_root(&dir.join("crates/skip")), None);
+
+        // Only the member is read, with the dependency it inherits
+        let targets = read_targets(&member_dir, &[]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].name, "a");
+        let foo = &targets[0].dependencies[0];
+        assert!(foo.workspace);
+        assert_eq!(foo.required_version, VersionReq::parse("1.2").unwrap());
+    }
+
+    #[test]
+    fn inherited_dependencies_are_optional_only_when_the_member_says_so() {
+        let dir = write_files(
+            "ws-inherit-optional",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\nfoo = { version = \"1\", default-features = false }\nbar = \"1\"\n",
+                ),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[features]\nextra = [\"dep:foo\"]\n\n\
+                     [dependencies]\nfoo = { workspace = true, optional = true, default-features = true }\nbar.workspace = true\n",
+                ),
+            ],
+        );
+        let members = read_members(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dependency = |name: &str| {
+            members[0]
+                .dependencies
+                .iter()
+                .find(|dep| dep.crate_name == name)
+                .unwrap()
+        };
+        assert!(dependency("foo").optional);
+        // The member re-enables the default features the workspace disabled
+        assert_eq!(dependency("foo").default_features, Some(true));
+        assert!(!dependency("bar").optional);
+        assert_eq!(
+            members[0].enabled_optional_dependencies(&["extra".to_string()], true),
+            BTreeSet::from(["foo".to_string()])
+        );
+    }
+
+    #[test]
+    fn inheriting_a_dependency_missing_from_the_workspace_fails() {
+        let dir = write_files(
+            "ws-inherit-missing",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\nbar = \"1\"\n",
+                ),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo.workspace = true\n",
+                ),
+            ],
+        );
+        let result = Cargo::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::MissingWorkspaceDependency { dependency, .. }) if dependency == "foo"
+        ));
+    }
+
+    #[test]
+    fn finds_keys_ignored_on_inherited_dependencies() {
+        let manifest: Table = "[dependencies]\nfoo = { workspace = true, version = \"2\", features = [\"x\"] }\nbar = { version = \"1\", path = \"../bar\" }\n\n[target.'cfg(unix)'.dev-dependencies]\nbaz = { workspace = true, default-features = false, branch = \"main\" }\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            ignored_inherited_keys(&manifest),
+            vec![
+                ("foo".to_string(), vec!["version".to_string()]),
+                ("baz".to_string(), vec!["branch".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn dev_only_dependency_is_declared_in_dev_dependencies() {
+        let manifest_path = write_manifest(
+            "dev-only",
+            &format!(
+                "{PACKAGE}[dependencies]\nbar = \"1\"\n\n[dev-dependencies]\nfoo = \"1\"\nbar = \"1\"\n"
+            ),
+        );
+        let cargo = Cargo::from_path(&manifest_path);
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        let Cargo::Single(package) = cargo.unwrap() else {
+            panic!("not a single package");
+        };
+
+        assert_eq!(package.dependency_kinds("foo"), vec![DependencyKind::Dev]);
+        assert_eq!(
+            package.dependency_kinds("bar"),
+            vec![DependencyKind::Normal, DependencyKind::Dev]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_write_replaces_the_target_of_a_symlink_and_keeps_its_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = write_files("atomic-symlink", &[("shared/Cargo.toml", "before\n")]);
+        let target = dir.join("shared/Cargo.toml");
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.join("Cargo.toml");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomically(&link, b"after\n").unwrap();
+        let is_symlink = std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let content = std::fs::read_to_string(&target).unwrap();
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_symlink);
+        assert_eq!(content, "after\n");
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn optional_dependencies_follow_the_enabled_features() {
+        let manifest_path = write_manifest(
+            "optional-features",
+            &format!(
+                "{PACKAGE}[features]\ndefault = [\"fast\"]\nfast = [\"dep:foo\"]\nserde = [\"bar?/serde\"]\nfull = [\"baz/std\", \"serde\"]\n\n\
+                 [dependencies]\nfoo = {{ version = \"1\", optional = true }}\nbar = {{ version = \"1\", optional = true }}\nbaz = {{ version = \"1\", optional = true }}\nqux = {{ version = \"1\", optional = true }}\n"
+            ),
+        );
+        let cargo = Cargo::from_path(&manifest_path);
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        let Cargo::Single(package) = cargo.unwrap() else {
+            panic!("not a single package");
+        };
+        let enabled = |features: &[&str], default_features: bool| {
+            package
+                .enabled_optional_dependencies(
+                    &features.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+                    default_features,
+                )
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(enabled(&[], true), ["foo"]);
+        assert!(enabled(&[], false).is_empty());
+        // `bar?/serde` does not enable bar, `baz/std` does
+        assert_eq!(enabled(&["demo/full"], false), ["baz"]);
+        // The implicit feature of an optional dependency not referenced with `dep:`
+        assert_eq!(enabled(&["qux,serde"], false), ["qux"]);
+    }
+
+    #[test]
+    fn members_inherit_the_workspace_package_version() {
+        let member =
+            |name: &str| format!("[package]\nname = \"{name}\"\nversion.workspace = true\n");
+        let dir = write_files(
+            "ws-version",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.package]\nversion = \"2.3.1\"\n",
+                ),
+                ("a/Cargo.toml", &member("a")),
+                ("b/Cargo.toml", &member("b")),
+            ],
+        );
+        let members = read_members(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(members.len(), 2);
+        for member in &members {
+            assert_eq!(member.version, Version::new(2, 3, 1), "{}", member.name);
+        }
+
+        let dir = write_files(
+            "ws-version-missing",
+            &[
+                ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+                ("a/Cargo.toml", &member("a")),
+            ],
+        );
+        let result = Cargo::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let error = result.err().unwrap().to_string();
+        assert!(
+            error.contains("missing [workspace.package] table"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn declared_rust_version_is_read_or_inherited() {
+        let dir = write_files(
+            "ws-rust-version",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n\n[workspace.package]\nrust-version = \"1.70\"\n",
+                ),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\nrust-version = \"1.74.1\"\n",
+                ),
+                (
+                    "b/Cargo.toml",
+                    "[package]\nname = \"b\"\nversion = \"0.1.0\"\nrust-version.workspace = true\n",
+                ),
+                (
+                    "c/Cargo.toml",
+                    "[package]\nname = \"c\"\nversion = \"0.1.0\"\n",
+                ),
+            ],
+        );
+        let members = read_members(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rust_versions = members
+            .iter()
+            .map(|member| member.rust_version.as_ref().map(ToString::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rust_versions,
+            [Some("1.74.1".to_string()), Some("1.70".to_string()), None]
+        );
+
+        let dir = write_files(
+            "ws-rust-version-missing",
+            &[
+                ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+                (
+                    "a/Cargo.toml",
+                    "[package]\nname = \"a\"\nversion = \"0.1.0\"\nrust-version.workspace = true\n",
+                ),
+            ],
+        );
+        let result = Cargo::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let error = result.err().unwrap().to_string();
+        assert!(
+            error.contains("[workspace.package] has no rust-version"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn manifest_guard_restores_on_drop_unless_committed() {
+        let dir = write_files("guard", &[("Cargo.toml", "original\n")]);
+        let manifest = dir.join("Cargo.toml");
+        let lock = dir.join("Cargo.lock");
+        let capture = || ManifestSnapshot::capture([dir.clone(), lock.clone()]).unwrap();
+
+        // Modified contents are restored and files created since the capture removed
+        let guard = ManifestGuard::new(capture());
+        std::fs::write(&manifest, "modified\n").unwrap();
+        std::fs::write(&lock, "created\n").unwrap();
+        drop(guard);
+        assert_eq!(std::fs::read_to_string(&manifest).unwrap(), "original\n");
+        assert!(!lock.exists());
+
+        let mut guard = ManifestGuard::new(capture());
+        std::fs::write(&manifest, "resolved\n").unwrap();
+        guard.commit();
+        drop(guard);
+        let content = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, "resolved\n");
+    }
+
+    #[test]
+    fn default_features_setting_is_read() {
+        let manifest_path = write_manifest(
+            "default-features",
+            &format!(
+                "{PACKAGE}[dependencies]\nfoo = {{ version = \"1\", default-features = false }}\nbar = \"1\"\n"
+            ),
+        );
+        let cargo = Cargo::from_path(&manifest_path);
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        let Cargo::Single(package) = cargo.unwrap() else {
+            panic!("not a single package");
+        };
+
+        let default_features = |name: &str| {
+            package
+                .dependencies
+                .iter()
+                .find(|dep| dep.crate_name == name)
+                .unwrap()
+                .default_features
+        };
+        assert_eq!(default_features("foo"), Some(false));
+        assert_eq!(default_features("bar"), None);
+    }
+
+    #[test]
+    fn platform_specific_dependencies_keep_their_platform() {
+        let manifest_path = write_manifest(
+            "platform-deps",
+            &format!(
+                "{PACKAGE}[dependencies]\nbar = \"1\"\n\n[target.'cfg(unix)'.dependencies]\nfoo = \"1\"\n\n\
+                 [target.x86_64-pc-windows-msvc.dev-dependencies]\nbaz = \"1\"\n"
+            ),
+        );
+        let cargo = Cargo::from_path(&manifest_path);
+        std::fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+        let Cargo::Single(package) = cargo.unwrap() else {
+            panic!("not a single package");
+        };
+
+        let target = |dependencies: &[Dependency], name: &str| {
+            dependencies
+                .iter()
+                .find(|dep| dep.crate_name == name)
+                .unwrap()
+                .target
+                .clone()
+        };
+        assert_eq!(target(&package.dependencies, "bar"), None);
+        assert_eq!(
+            target(&package.dependencies, "foo").as_deref(),
+            Some("cfg(unix)")
+        );
+        assert_eq!(
+            target(&package.dev_dependencies, "baz").as_deref(),
+            Some("x86_64-pc-windows-msvc")
+        );
+    }
+
+    #[test]
+    fn rejects_entries_without_version() {
+        let manifest = format!("{PACKAGE}[dependencies]\nfoo = {{ path = \"../foo\" }}\n");
+        let error = set_foo_req("no-version", &manifest, DependencyKind::Normal).unwrap_err();
+        assert!(error.contains("has no version requirement"), "{error}");
+
+        let manifest = format!("{PACKAGE}[dev-dependencies]\nfoo = \"1\"\n");
+        let error = set_foo_req("not-found", &manifest, DependencyKind::Normal).unwrap_err();
+        assert!(error.contains("not found in [dependencies]"), "{error}");
+    }
+}
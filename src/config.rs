@@ -0,0 +1,355 @@
+//! Project configuration file (`cargo-compat.toml`) providing defaults for the resolve options.
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+use log::debug;
+use serde::Deserialize;
+
+use crate::{EditBackendArg, GranularityArg, PinStrategyArg, ResolveArgs, StrategyArg};
+
+/// Name of the configuration file looked up in the repository root.
+pub const CONFIG_FILE_NAME: &str = "cargo-compat.toml";
+
+/// Defaults for the options of the resolve-like commands, each key is named after its flag
+/// (e.g. `cargo-path` for `--cargo-path`). Flags given on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub include: Option<Vec<String>>,
+    pub cargo_path: Option<String>,
+    /// `precise` or `exact`
+    pub pin_strategy: Option<String>,
+    /// `toml-edit` or `cargo`
+    pub edit_backend: Option<String>,
+    /// `widen` or `verify`
+    pub strategy: Option<String>,
+    /// `major`, `minor` or `patch`
+    pub granularity: Option<String>,
+    pub release: Option<bool>,
+    pub no_test: Option<bool>,
+    pub all_targets: Option<bool>,
+    pub doc: Option<bool>,
+    pub check: Option<bool>,
+    pub confirm_with_tests: Option<bool>,
+    pub verify_checksums: Option<bool>,
+    pub check_command: Option<String>,
+    pub features: Option<Vec<String>>,
+    pub all_features: Option<bool>,
+    pub no_default_features: Option<bool>,
+    pub jobs: Option<u32>,
+    pub throttle_ms: Option<u32>,
+    pub minimize: Option<bool>,
+    pub linear_search: Option<bool>,
+    pub keep_going: Option<bool>,
+    pub allow_prerelease: Option<bool>,
+    pub use_docsrs_prefilter: Option<bool>,
+    pub no_clean: Option<bool>,
+    pub max_comparisons: Option<usize>,
+    pub lock_only: Option<bool>,
+    pub build_timeout: Option<u64>,
+    pub max_duration: Option<u64>,
+    pub toolchain: Option<String>,
+    pub msrv: Option<bool>,
+    pub frozen: Option<bool>,
+    pub target: Option<String>,
+    pub target_dir: Option<PathBuf>,
+    pub parallel: Option<usize>,
+    pub only: Option<Vec<String>>,
+    pub strict: Option<bool>,
+    pub summary_only: Option<bool>,
+}
+
+/// Replace `target` by the configured value unless the flag was given on the command line.
+fn merge<T>(matches: &ArgMatches, id: &str, target: &mut T, value: Option<T>) {
+    if matches.value_source(id) != Some(ValueSource::CommandLine)
+        && let Some(value) = value
+    {
+        *target = value;
+    }
+}
+
+impl Config {
+    pub fn load_from_path(path: &Path) -> Result<Self, cargo_compat::error::Error> {
+        debug!("Reading configuration at: {}", path.to_string_lossy());
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            cargo_compat::error::Error::FileSystemError {
+                path: path.to_string_lossy().to_string(),
+                error: e.kind(),
+            }
+        })?;
+
+        toml::from_str(&content).map_err(|e| {
+            cargo_compat::error::Error::Other(
+                format!(
+                    "Failed to parse configuration at {}: {}",
+                    path.to_string_lossy(),
+                    e
+                )
+                .into(),
+            )
+        })
+    }
+
+    /// Load the configuration at `explicit_path` when given, otherwise the `cargo-compat.toml` of
+    /// `repository_dir` if there is one.
+    pub fn discover(
+        explicit_path: Option<&Path>,
+        repository_dir: &Path,
+    ) -> Result<Option<(PathBuf, Self)>, cargo_compat::error::Error> {
+        let path = match explicit_path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = repository_dir.join(CONFIG_FILE_NAME);
+                if !path.is_file() {
+                    debug!("No configuration file at: {}", path.to_string_lossy());
+                    return Ok(None);
+                }
+                path
+            }
+        };
+
+        let config = Self::load_from_path(&path)?;
+        Ok(Some((path, config)))
+    }
+
+    /// Fill the options of `resolve_args` that were not given on the command line, `matches` being
+    /// the matches of the subcommand they were parsed from.
+    pub fn apply(
+        self,
+        resolve_args: &mut ResolveArgs,
+        matches: &ArgMatches,
+    ) -> Result<(), cargo_compat::error::Error> {
+        let pin_strategy = self
+            .pin_strategy
+            .map(|strategy| {
+                PinStrategyArg::from_str(&strategy, true).map_err(|_| {
+                    cargo_compat::error::Error::Other(
+                        format!(
+                            "Invalid pin-strategy '{}' in configuration, expected 'precise' or 'exact'",
+                            strategy
+                        )
+                        .into(),
+                    )
+                })
+            })
+            .transpose()?;
+        let strategy = self
+            .strategy
+            .map(|strategy| {
+                StrategyArg::from_str(&strategy, true).map_err(|_| {
+                    cargo_compat::error::Error::Other(
+                        format!(
+                            "Invalid strategy '{}' in configuration, expected 'widen' or 'verify'",
+                            strategy
+                        )
+                        .into(),
+                    )
+                })
+            })
+            .transpose()?;
+        let edit_backend = self
+            .edit_backend
+            .map(|backend| {
+                EditBackendArg::from_str(&backend, true).map_err(|_| {
+                    cargo_compat::error::Error::Other(
+                        format!(
+                            "Invalid edit-backend '{}' in configuration, expected 'toml-edit' or 'cargo'",
+                            backend
+                        )
+                        .into(),
+                    )
+                })
+            })
+            .transpose()?;
+        let granularity = self
+            .granularity
+            .map(|granularity| {
+                GranularityArg::from_str(&granularity, true).map_err(|_| {
+                    cargo_compat::error::Error::Other(
+                        format!(
+                            "Invalid granularity '{}' in configuration, expected 'major', 'minor' or 'patch'",
+                            granularity
+                        )
+                        .into(),
+                    )
+                })
+            })
+            .transpose()?;
+
+        merge(matches, "include", &mut resolve_args.include, self.include);
+        merge(
+            matches,
+            "cargo_path",
+            &mut resolve_args.cargo_path,
+            self.cargo_path,
+        );
+        merge(
+            matches,
+            "pin_strategy",
+            &mut resolve_args.pin_strategy,
+            pin_strategy,
+        );
+        merge(
+            matches,
+            "edit_backend",
+            &mut resolve_args.edit_backend,
+            edit_backend,
+        );
+        merge(matches, "strategy", &mut resolve_args.strategy, strategy);
+        merge(
+            matches,
+            "granularity",
+            &mut resolve_args.granularity,
+            granularity,
+        );
+        merge(matches, "release", &mut resolve_args.release, self.release);
+        merge(matches, "no_test", &mut resolve_args.no_test, self.no_test);
+        merge(
+            matches,
+            "all_targets",
+            &mut resolve_args.all_targets,
+            self.all_targets,
+        );
+        merge(matches, "doc", &mut resolve_args.doc, self.doc);
+        merge(matches, "check", &mut resolve_args.check, self.check);
+        merge(
+            matches,
+            "confirm_with_tests",
+            &mut resolve_args.confirm_with_tests,
+            self.confirm_with_tests,
+        );
+        merge(
+            matches,
+            "verify_checksums",
+            &mut resolve_args.verify_checksums,
+            self.verify_checksums,
+        );
+        merge(
+            matches,
+            "check_command",
+            &mut resolve_args.check_command,
+            self.check_command.map(Some),
+        );
+        merge(
+            matches,
+            "features",
+            &mut resolve_args.features,
+            self.features,
+        );
+        merge(
+            matches,
+            "all_features",
+            &mut resolve_args.all_features,
+            self.all_features,
+        );
+        merge(
+            matches,
+            "no_default_features",
+            &mut resolve_args.no_default_features,
+            self.no_default_features,
+        );
+        merge(matches, "jobs", &mut resolve_args.jobs, self.jobs.map(Some));
+        merge(
+            matches,
+            "throttle_ms",
+            &mut resolve_args.throttle_ms,
+            self.throttle_ms,
+        );
+        merge(
+            matches,
+            "minimize",
+            &mut resolve_args.minimize,
+            self.minimize,
+        );
+        merge(
+            matches,
+            "linear_search",
+            &mut resolve_args.linear_search,
+            self.linear_search,
+        );
+        merge(
+            matches,
+            "keep_going",
+            &mut resolve_args.keep_going,
+            self.keep_going,
+        );
+        merge(
+            matches,
+            "allow_prerelease",
+            &mut resolve_args.allow_prerelease,
+            self.allow_prerelease,
+        );
+        merge(
+            matches,
+            "use_docsrs_prefilter",
+            &mut resolve_args.use_docsrs_prefilter,
+            self.use_docsrs_prefilter,
+        );
+        merge(
+            matches,
+            "no_clean",
+            &mut resolve_args.no_clean,
+            self.no_clean,
+        );
+        merge(
+            matches,
+            "max_comparisons",
+            &mut resolve_args.max_comparisons,
+            self.max_comparisons.map(Some),
+        );
+        merge(
+            matches,
+            "lock_only",
+            &mut resolve_args.lock_only,
+            self.lock_only,
+        );
+        merge(
+            matches,
+            "build_timeout",
+            &mut resolve_args.build_timeout,
+            self.build_timeout.map(Some),
+        );
+        merge(
+            matches,
+            "max_duration",
+            &mut resolve_args.max_duration,
+            self.max_duration.map(Some),
+        );
+        merge(
+            matches,
+            "toolchain",
+            &mut resolve_args.toolchain,
+            self.toolchain.map(Some),
+        );
+        merge(matches, "msrv", &mut resolve_args.msrv, self.msrv);
+        merge(matches, "frozen", &mut resolve_args.frozen, self.frozen);
+        merge(
+            matches,
+            "target",
+            &mut resolve_args.target,
+            self.target.map(Some),
+        );
+        merge(
+            matches,
+            "target_dir",
+            &mut resolve_args.target_dir,
+            self.target_dir.map(Some),
+        );
+        merge(
+            matches,
+            "parallel",
+            &mut resolve_args.parallel,
+            self.parallel,
+        );
+        merge(matches, "only", &mut resolve_args.only, self.only);
+        merge(matches, "strict", &mut resolve_args.strict, self.strict);
+        merge(
+            matches,
+            "summary_only",
+            &mut resolve_args.summary_only,
+            self.summary_only,
+        );
+
+        Ok(())
+    }
+}
@@ -1,21 +1,119 @@
 //! Types and helpers for interacting with crates.io and representing crates and their versions.
+use std::{collections::BTreeMap, path::Path};
+
 use cargo_util_schemas::manifest::{PackageName, TomlDependency};
 use chrono::{DateTime, Utc};
-use log::{debug, error, info};
+use futures::StreamExt;
+use log::{debug, error, info, warn};
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
+use crate::registry::Registry;
+
+/// User agent identifying the requests made by cargo-compat, as asked by the crates.io crawler policy.
+pub const DEFAULT_USER_AGENT: &str = "cargo-compat (github.com/BoyeGuillaume/cargo-compat)";
+
+/// Options controlling how crate metadata is fetched from crates.io.
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    /// Maximum number of requests in flight
+    pub concurrency: usize,
+    /// Never touch the network, only use cached metadata
+    pub offline: bool,
+    /// Registry the metadata is fetched from
+    pub registry: Registry,
+    /// Number of times a request failing with a transient error (network, 429, 5xx) is retried
+    pub retries: u32,
+    /// Fail with `Error::CrateNotFound` when a crate does not exist instead of skipping it
+    pub strict: bool,
+    /// Fetch the dependencies of every version as well (slower on crates.io)
+    pub full: bool,
+    /// How long a crate found missing is not fetched again, zero disables the negative cache
+    pub negative_cache_age: chrono::Duration,
+    /// User agent sent with every request
+    pub user_agent: String,
+    /// Save the cache to `cache_path` after every `save_every` fetched crates, so that an interrupted
+    /// fetch keeps its progress. Zero leaves saving to the caller
+    pub save_every: usize,
+    /// Location of the crate cache, used when `save_every` is set
+    pub cache_path: Option<std::path::PathBuf>,
+    /// Token sent in the `Authorization` header of sparse registry requests, for registries that do
+    /// not serve their index anonymously. Ignored for crates.io
+    pub registry_token: Option<String>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            offline: false,
+            registry: Registry::CratesIo,
+            retries: 3,
+            strict: false,
+            full: false,
+            negative_cache_age: chrono::Duration::minutes(10),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            save_every: 0,
+            cache_path: None,
+            registry_token: None,
+        }
+    }
+}
+
+/// Manifest table a dependency is declared in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DependencyKind {
+    /// `[dependencies]`
+    #[default]
+    Normal,
+    /// `[dev-dependencies]`
+    Dev,
+    /// `[build-dependencies]`
+    Build,
+}
+
+impl DependencyKind {
+    /// Flag selecting the table of this kind in `cargo add`
+    pub fn cargo_add_flag(self) -> Option<&'static str> {
+        match self {
+            DependencyKind::Normal => None,
+            DependencyKind::Dev => Some("--dev"),
+            DependencyKind::Build => Some("--build"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dependency {
     pub crate_name: String,
     pub required_version: VersionReq,
     pub features: Vec<String>,
     pub git: bool,
+    /// Git source of the dependency (URL with an optional `?branch=`, `?tag=` or `?rev=` suffix)
+    #[serde(default)]
+    pub git_source: Option<String>,
+    /// Name of the alternative registry the dependency is fetched from (`None` for crates.io)
+    #[serde(default)]
+    pub registry: Option<String>,
+    /// `default-features` setting of the dependency, `None` when not specified (enabled)
+    #[serde(default)]
+    pub default_features: Option<bool>,
     pub optional: bool,
+    /// Inherited from the `[workspace.dependencies]` table of the workspace root manifest
+    #[serde(default)]
+    pub workspace: bool,
+    /// Overridden by a `[patch]` or `[replace]` entry, its source is not the registry
+    #[serde(default)]
+    pub patched: bool,
+    /// Platform of the `[target.'<platform>'.*]` table declaring the dependency (a `cfg(..)`
+    /// expression or a target triple), `None` when it is used on every platform
+    #[serde(default)]
+    pub target: Option<String>,
 }
 
 impl Dependency {
     pub fn from_cargo_toml(
+        manifest_path: &Path,
         name: &PackageName,
         dep: &cargo_util_schemas::manifest::InheritableDependency,
         workspace: Option<&cargo_util_schemas::manifest::TomlWorkspace>,
@@ -26,6 +124,9 @@ impl Dependency {
         let mut features = vec![];
         let mut optional = false;
         let mut git = false;
+        let mut git_source = None;
+        let mut registry = None;
+        let mut default_features = None;
 
         if workspace.is_some() {
             debug!(
@@ -36,30 +137,13 @@ impl Dependency {
 
         let normalized = match &dep {
             InheritableDependency::Value(v) => v,
-            InheritableDependency::Inherit(v) => {
-                if workspace.is_none() {
-                    error!(
-                        "Dependency {} is trying to inherit version from workspace, but no workspace is defined",
-                        crate_name
-                    );
-                    return Err(crate::error::Error::Other(
-                        "Cannot inherit version from workspace".into(),
-                    ));
-                }
-
-                features.extend(v.features.iter().flat_map(|x| x.iter()).cloned());
-
-                workspace
-                    .unwrap()
-                    .dependencies
-                    .as_ref()
-                    .and_then(|deps| deps.get(name))
-                    .ok_or_else(|| {
-                        crate::error::Error::Other(
-                            format!("Dependency {} not found in workspace", crate_name).into(),
-                        )
-                    })?
-            }
+            InheritableDependency::Inherit(_) => workspace
+                .and_then(|workspace| workspace.dependencies.as_ref())
+                .and_then(|deps| deps.get(name))
+                .ok_or_else(|| crate::error::Error::MissingWorkspaceDependency {
+                    dependency: crate_name.clone(),
+                    manifest: manifest_path.to_string_lossy().to_string(),
+                })?,
         };
 
         let required_version = match normalized {
@@ -76,7 +160,23 @@ impl Dependency {
                     features = ftrs.clone();
                 }
                 optional = toml_detailed_dependency.optional.unwrap_or(false);
+                default_features = toml_detailed_dependency.default_features();
                 git = toml_detailed_dependency.git.is_some();
+                registry = toml_detailed_dependency
+                    .registry
+                    .as_ref()
+                    .map(|r| r.to_string());
+                git_source = toml_detailed_dependency.git.as_ref().map(|url| {
+                    let reference = [
+                        ("branch", &toml_detailed_dependency.branch),
+                        ("tag", &toml_detailed_dependency.tag),
+                        ("rev", &toml_detailed_dependency.rev),
+                    ]
+                    .into_iter()
+                    .find_map(|(kind, value)| value.as_ref().map(|v| format!("?{}={}", kind, v)));
+
+                    format!("{}{}", url, reference.unwrap_or_default())
+                });
 
                 VersionReq::parse(toml_detailed_dependency.version.as_deref().unwrap_or("*"))
                     .map_err(crate::error::Error::InvalidVersionSyntax)
@@ -89,12 +189,40 @@ impl Dependency {
             }
         };
 
+        // Merge the member entry following Cargo's inheritance rules: features add up, `optional` is
+        // only set by the member, and `default-features` can only re-enable what the workspace disabled
+        if let InheritableDependency::Inherit(local) = dep {
+            for feature in local.features.iter().flatten() {
+                if !features.contains(feature) {
+                    features.push(feature.clone());
+                }
+            }
+            optional = local.optional.unwrap_or(false);
+            default_features = match (default_features, local.default_features()) {
+                (Some(false), local_default_features) => local_default_features.or(Some(false)),
+                (base, Some(false)) => {
+                    warn!(
+                        "Ignoring default-features = false of dependency {}, its workspace definition enables them",
+                        crate_name
+                    );
+                    base
+                }
+                (base, _) => base,
+            };
+        }
+
         Ok(Self {
             crate_name,
             required_version: required_version?,
             features,
             git,
+            git_source,
+            registry,
+            default_features,
             optional,
+            workspace: matches!(dep, InheritableDependency::Inherit(_)),
+            patched: false,
+            target: None,
         })
     }
 }
@@ -110,6 +238,12 @@ impl TryFrom<crates_io_api::Dependency> for Dependency {
             features: value.features,
             optional: value.optional,
             git: false,
+            git_source: None,
+            registry: None,
+            default_features: Some(value.default_features),
+            workspace: false,
+            patched: false,
+            target: value.target,
         })
     }
 }
@@ -122,6 +256,9 @@ pub struct CrateVersion {
     pub version: Version,
     pub checksum: String,
     pub dependencies: Option<Vec<Dependency>>,
+    /// Minimum rustc version declared by this version (`rust-version`), if any
+    #[serde(default)]
+    pub rust_version: Option<String>,
 }
 
 impl TryFrom<crates_io_api::FullVersion> for CrateVersion {
@@ -142,6 +279,7 @@ impl TryFrom<crates_io_api::FullVersion> for CrateVersion {
                 .map_err(crate::error::Error::InvalidVersionSyntax)?,
             dependencies: Some(dependencies),
             checksum: value.checksum,
+            rust_version: value.rust_version,
         })
     }
 }
@@ -158,6 +296,7 @@ impl TryFrom<crates_io_api::Version> for CrateVersion {
                 .map_err(crate::error::Error::InvalidVersionSyntax)?,
             dependencies: None,
             checksum: value.checksum,
+            rust_version: value.rust_version,
         })
     }
 }
@@ -211,49 +350,246 @@ impl TryFrom<crates_io_api::FullCrate> for Crate {
     }
 }
 
-pub async fn download_crates(crate_names: &[&str]) -> Result<Vec<Crate>, crate::error::Error> {
-    // Create the async-client
-    let async_client = crates_io_api::AsyncClient::new(
-        "rust-version-searcher (github.com/BoyeGuillaume/rust-version-searcher)",
-        std::time::Duration::from_millis(500),
-    )
-    .unwrap();
+/// Outcome of downloading the metadata of a crate that may already be cached.
+pub enum Download {
+    /// Fresh metadata, along with the ETag of the response when the server sent one
+    Fetched { krate: Crate, etag: Option<String> },
+    /// The cached metadata of the named crate is still current (304 Not Modified)
+    NotModified(String),
+    /// The download of the named crate failed for another reason than the crate missing, once the
+    /// retries are exhausted. Its cached metadata, if any, is used instead
+    Failed {
+        name: String,
+        error: crate::error::Error,
+    },
+}
+
+/// Whether a crates.io API error is worth retrying: network failures, rate limiting and server errors.
+fn is_transient(error: &crates_io_api::Error) -> bool {
+    match error {
+        crates_io_api::Error::Http(e) => e.status().is_none_or(|status| {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }),
+        _ => false,
+    }
+}
+
+/// Run `request` for `crate_name`, retrying up to `retries` times with exponential backoff
+/// (1s, 2s, 4s, ...) on transient errors.
+async fn with_retries<T, F>(
+    crate_name: &str,
+    retries: u32,
+    mut request: impl FnMut() -> F,
+) -> Result<T, crates_io_api::Error>
+where
+    F: Future<Output = Result<T, crates_io_api::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                let backoff = std::time::Duration::from_secs(1 << attempt.min(6));
+                attempt += 1;
+                warn!(
+                    "Request for crate {} failed: {}. Retrying in {}s ({}/{})",
+                    crate_name,
+                    e,
+                    backoff.as_secs(),
+                    attempt,
+                    retries
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether a crates.io API error means that the crate does not exist.
+fn is_not_found(error: &crates_io_api::Error) -> bool {
+    match error {
+        crates_io_api::Error::NotFound(_) => true,
+        crates_io_api::Error::Http(e) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+        _ => false,
+    }
+}
+
+/// Outcome of the download of the named crate.
+type NamedOutcome<'a, T> = (&'a str, Result<T, crate::error::Error>);
+
+/// Collect the outcome of each download. Crates that do not exist are an `Error::CrateNotFound` when
+/// `strict`, otherwise they are reported and left out. The other failures are kept along with the
+/// successful downloads.
+fn collect_downloads<T>(
+    results: Vec<(&str, Result<T, crates_io_api::Error>)>,
+    strict: bool,
+) -> Result<Vec<NamedOutcome<'_, T>>, crate::error::Error> {
+    let mut found = vec![];
+    for (name, result) in results {
+        match result {
+            Ok(krate) => found.push((name, Ok(krate))),
+            Err(e) if is_not_found(&e) && !strict => {
+                warn!("Crate {} was not found on crates.io, skipping it", name);
+            }
+            Err(e) if is_not_found(&e) => {
+                return Err(crate::error::Error::CrateNotFound(name.to_string()));
+            }
+            Err(e) => found.push((name, Err(crate::error::Error::CratesIoApiError(e)))),
+        }
+    }
+
+    Ok(found)
+}
+
+/// crates.io client sending `user_agent`, rate limited to one request every 500ms.
+fn crates_io_client(user_agent: &str) -> Result<crates_io_api::AsyncClient, crate::error::Error> {
+    crates_io_api::AsyncClient::new(user_agent, CRATES_IO_RATE_LIMIT).map_err(|e| {
+        crate::error::Error::Other(format!("Invalid user agent '{}': {}", user_agent, e).into())
+    })
+}
+
+/// Minimum delay between two requests to crates.io.
+const CRATES_IO_RATE_LIMIT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Endpoint of the crates.io API listing the versions of a crate.
+const CRATES_IO_CRATES_URL: &str = "https://crates.io/api/v1/crates";
+
+/// crates.io client for the crate endpoint supporting conditional requests, which `crates_io_api`
+/// does not expose. Requests are rate limited like [`crates_io_client`].
+struct ConditionalClient {
+    client: reqwest::Client,
+    last_request: tokio::sync::Mutex<Option<tokio::time::Instant>>,
+}
+
+impl ConditionalClient {
+    fn new(user_agent: &str) -> Result<Self, crate::error::Error> {
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .build()
+            .map_err(|e| {
+                crate::error::Error::Other(
+                    format!("Invalid user agent '{}': {}", user_agent, e).into(),
+                )
+            })?;
+
+        Ok(Self {
+            client,
+            last_request: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Fetch the crate response of `crate_name` along with its ETag, `None` when it did not change
+    /// since the response identified by `etag`.
+    async fn get_crate(
+        &self,
+        crate_name: &str,
+        etag: Option<&str>,
+    ) -> Result<Option<(crates_io_api::CrateResponse, Option<String>)>, crates_io_api::Error> {
+        let mut url =
+            reqwest::Url::parse(CRATES_IO_CRATES_URL).expect("the crates.io API URL is valid");
+        // Pushing the name as a path segment escapes any slash it contains
+        url.path_segments_mut()
+            .expect("the crates.io API URL has a path")
+            .push(crate_name);
+
+        // Requests are serialized to respect the rate limit
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last_request) = *last_request
+            && last_request.elapsed() < CRATES_IO_RATE_LIMIT
+        {
+            tokio::time::sleep(CRATES_IO_RATE_LIMIT - last_request.elapsed()).await;
+        }
+
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await;
+        *last_request = Some(tokio::time::Instant::now());
+
+        let response = response?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let content = response.text().await?;
+
+        serde_json::from_str(&content)
+            .map(|krate| Some((krate, etag)))
+            .map_err(|e| {
+                crates_io_api::Error::Api(crates_io_api::ApiErrors {
+                    errors: vec![crates_io_api::ApiError {
+                        detail: Some(format!("Could not decode the response: {}", e)),
+                    }],
+                })
+            })
+    }
+}
+
+/// Download crate metadata for all `crate_names`, keeping at most `concurrency` requests in flight.
+/// Crates listed in `etags` are only downloaded again when they changed since the response carrying
+/// that ETag.
+pub async fn download_crates(
+    crate_names: &[&str],
+    etags: &BTreeMap<String, String>,
+    concurrency: usize,
+    retries: u32,
+    strict: bool,
+    user_agent: &str,
+) -> Result<Vec<Download>, crate::error::Error> {
+    let client = ConditionalClient::new(user_agent)?;
 
     let atomic_usize = std::sync::atomic::AtomicUsize::new(0);
 
     // For each crate name, download the crate data
     debug!("Downloading crate data for: [{}]", crate_names.join(", "));
-    let crates = crate_names
-        .iter()
+    let crates = futures::stream::iter(crate_names.iter())
         .map(async |name| {
-            let elem = async_client.get_crate(name).await;
+            let etag = etags.get(*name).map(String::as_str);
+            let elem = with_retries(name, retries, || client.get_crate(name, etag)).await;
             info!(
                 "Downloaded crate data for {} ({}/{})",
                 name,
                 atomic_usize.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1,
                 crate_names.len()
             );
-            elem
+            (*name, elem)
         })
-        .collect::<Vec<_>>();
-    let crates = futures::future::join_all(crates)
-        .await
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    collect_downloads(crates, strict)?
         .into_iter()
-        .map(|res| res.map_err(crate::error::Error::CratesIoApiError))
-        .collect::<Result<Vec<_>, _>>()?;
-    crates
-        .into_iter()
-        .map(|c| c.try_into())
+        .map(|(name, response)| match response {
+            Ok(Some((krate, etag))) => Ok(Download::Fetched {
+                krate: krate.try_into()?,
+                etag,
+            }),
+            Ok(None) => Ok(Download::NotModified(name.to_string())),
+            Err(error) => Ok(Download::Failed {
+                name: name.to_string(),
+                error,
+            }),
+        })
         .collect::<Result<Vec<_>, _>>()
 }
 
-pub async fn download_full_crates(crate_names: &[&str]) -> Result<Vec<Crate>, crate::error::Error> {
+/// Download full crate metadata (including dependencies) for all `crate_names`, keeping at most
+/// `concurrency` requests in flight.
+pub async fn download_full_crates(
+    crate_names: &[&str],
+    concurrency: usize,
+    retries: u32,
+    strict: bool,
+    user_agent: &str,
+) -> Result<Vec<Download>, crate::error::Error> {
     // Create the async-client
-    let async_client = crates_io_api::AsyncClient::new(
-        "rust-version-searcher (github.com/BoyeGuillaume/rust-version-searcher)",
-        std::time::Duration::from_millis(500),
-    )
-    .unwrap();
+    let async_client = crates_io_client(user_agent)?;
 
     let atomic_usize = std::sync::atomic::AtomicUsize::new(0);
 
@@ -262,26 +598,31 @@ pub async fn download_full_crates(crate_names: &[&str]) -> Result<Vec<Crate>, cr
         "Downloading full crate data for: [{}]",
         crate_names.join(", ")
     );
-    let crates = crate_names
-        .iter()
+    let crates = futures::stream::iter(crate_names.iter())
         .map(async |name| {
-            let elem = async_client.full_crate(name, true).await;
+            let elem = with_retries(name, retries, || async_client.full_crate(name, true)).await;
             info!(
                 "Downloaded full crate data for {} ({}/{})",
                 name,
                 atomic_usize.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1,
                 crate_names.len()
             );
-            elem
+            (*name, elem)
         })
-        .collect::<Vec<_>>();
-    let crates = futures::future::join_all(crates)
-        .await
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    collect_downloads(crates, strict)?
         .into_iter()
-        .map(|res| res.map_err(crate::error::Error::CratesIoApiError))
-        .collect::<Result<Vec<_>, _>>()?;
-    crates
-        .into_iter()
-        .map(|c| c.try_into())
+        .map(|(name, response)| match response {
+            Ok(krate) => Ok(Download::Fetched {
+                krate: krate.try_into()?,
+                etag: None,
+            }),
+            Err(error) => Ok(Download::Failed {
+                name: name.to_string(),
+                error,
+            }),
+        })
         .collect::<Result<Vec<_>, _>>()
 }
@@ -0,0 +1,62 @@
+//! Minimal docs.rs client, used to skip candidate versions whose documentation build failed there.
+use log::debug;
+use semver::Version;
+use serde::Deserialize;
+
+const DOCS_RS_URL: &str = "https://docs.rs";
+
+/// Response of the `/crate/<name>/<version>/status.json` endpoint.
+#[derive(Deserialize)]
+struct BuildStatus {
+    doc_status: bool,
+}
+
+/// Blocking docs.rs client. It must be created, used and dropped outside of the async runtime, e.g.
+/// from the search workers.
+pub struct DocsRsClient {
+    client: reqwest::blocking::Client,
+}
+
+impl DocsRsClient {
+    pub fn new() -> Result<Self, crate::error::Error> {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(crate::crates::DEFAULT_USER_AGENT)
+            .build()
+            .map_err(|e| crate::error::Error::RegistryError {
+                url: DOCS_RS_URL.to_string(),
+                error: e.to_string(),
+            })?;
+
+        Ok(Self { client })
+    }
+
+    /// Whether docs.rs built `version` of `crate_name` successfully. `None` when unknown: the version
+    /// was not built yet or docs.rs could not be reached.
+    pub fn build_status(&self, crate_name: &str, version: &Version) -> Option<bool> {
+        let url = format!(
+            "{}/crate/{}/{}/status.json",
+            DOCS_RS_URL, crate_name, version
+        );
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .inspect_err(|e| debug!("Failed to query docs.rs at {}: {}", url, e))
+            .ok()?;
+        if !response.status().is_success() {
+            debug!("docs.rs answered {} for {}", response.status(), url);
+            return None;
+        }
+
+        let status = response
+            .json::<BuildStatus>()
+            .inspect_err(|e| debug!("Invalid docs.rs build status at {}: {}", url, e))
+            .ok()?;
+        debug!(
+            "docs.rs build status of {} {}: {}",
+            crate_name, version, status.doc_status
+        );
+        Some(status.doc_status)
+    }
+}
@@ -5,7 +5,7 @@ use std::borrow::Cow;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-/// All possible errors returned by rust-version-searcher.
+/// All possible errors returned by cargo-compat.
 pub enum Error {
     /// The provided version string could not be parsed as a valid semver.
     #[error("The provided version has an invalid syntax: {0}")]
@@ -15,6 +15,27 @@ pub enum Error {
     #[error("An error occurred while communicating with the crates.io API: {0}")]
     CratesIoApiError(#[from] crates_io_api::Error),
 
+    /// A cargo command was killed because it did not finish in time.
+    #[error("{command} timed out after {}s", .timeout.as_secs())]
+    CommandTimeout {
+        command: String,
+        timeout: std::time::Duration,
+    },
+
+    /// Cargo cannot be run with the requested toolchain, usually because it is not installed.
+    #[error(
+        "Cannot run cargo with toolchain '{toolchain}': {error} (install it with `rustup toolchain install {toolchain}`)"
+    )]
+    ToolchainUnavailable { toolchain: String, error: String },
+
+    /// The crate does not exist in the registry it is fetched from.
+    #[error("Crate '{0}' was not found in the registry")]
+    CrateNotFound(String),
+
+    /// Network or protocol error while communicating with an alternative registry.
+    #[error("An error occurred while communicating with the registry at {url}: {error}")]
+    RegistryError { url: String, error: String },
+
     /// Underlying I/O error from the filesystem or a subprocess.
     #[error("An I/O error occurred: {0}")]
     AnyIoError(#[from] std::io::Error),
@@ -44,6 +65,92 @@ pub enum Error {
     #[error("{0}")]
     Other(Cow<'static, str>),
 
+    /// Offline mode was requested but some crates are not present in the cache.
+    #[error(
+        "Offline mode: the following crates are missing from the cache: {}. Fetch them while online (e.g. `cargo compat cache fetch <crate>`)",
+        .0.join(", ")
+    )]
+    MissingFromCache(Vec<String>),
+
+    /// Every published version matching the requirement of a dependency is yanked (or none exists).
+    #[error(
+        "No available version of '{crate_name}' matches '{requirement}' ({})",
+        match .yanked {
+            0 => "no published version matches".to_string(),
+            yanked => format!("{yanked} matching versions are yanked"),
+        }
+    )]
+    NoAvailableVersion {
+        crate_name: String,
+        requirement: semver::VersionReq,
+        yanked: usize,
+    },
+
+    /// Targets require a dependency with requirements that no published version satisfies at once.
+    #[error(
+        "No published version of '{crate_name}' satisfies the requirements of every target: {}",
+        .requirements.join(", ")
+    )]
+    RequirementConflict {
+        crate_name: String,
+        requirements: Vec<String>,
+    },
+
+    /// The currently selected versions fail validation, so there is no working range to search from.
+    #[error("The default configuration is invalid: {0}")]
+    InvalidBaseline(&'static str),
+
+    /// The resolution did not finish within the time allotted by `--max-duration`.
+    #[error("The resolution exceeded its maximum duration of {}s", .0.as_secs())]
+    DeadlineExceeded(std::time::Duration),
+
+    /// The run was interrupted (Ctrl-C) before it completed.
+    #[error("Interrupted by the user")]
+    Interrupted,
+
+    /// A workspace was given without `--include` patterns selecting the members to resolve.
+    #[error(
+        "No include patterns specified for workspace. Workspace processing requires at least one --include pattern."
+    )]
+    MissingIncludePatterns,
+
+    /// None of the workspace members matched the `--include` patterns.
+    #[error(
+        "No packages in the workspace matched the provided include patterns: {patterns:?}. Available packages: {available:?}"
+    )]
+    NoMatchingPackages {
+        patterns: Vec<String>,
+        available: Vec<String>,
+    },
+
+    /// An `--include` pattern is not a valid glob pattern.
+    #[error("Invalid include pattern '{pattern}': {error}")]
+    InvalidIncludePattern {
+        pattern: String,
+        error: glob::PatternError,
+    },
+
+    /// A `members` or `exclude` pattern of a workspace is not a valid glob pattern.
+    #[error("Invalid workspace pattern '{pattern}' in {manifest}: {error}")]
+    InvalidWorkspacePattern {
+        manifest: String,
+        pattern: String,
+        error: glob::PatternError,
+    },
+
+    /// A dependency inherited with `workspace = true` has no definition in the workspace.
+    #[error(
+        "Dependency {dependency} of {manifest} is inherited from the workspace, but [workspace.dependencies] does not define it"
+    )]
+    MissingWorkspaceDependency {
+        dependency: String,
+        manifest: String,
+    },
+
+    /// A package expected to be a workspace member is missing from the members of the workspace.
+    #[error("Package at {member} was not found among the members of the workspace at {root}")]
+    NotAWorkspaceMember { member: String, root: String },
+
     /// The project contains a git dependency which is not supported by this tool.
     #[error("Git packages are not supported: {0}")]
     GitPackageNotSupported(String),
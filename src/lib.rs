@@ -0,0 +1,16 @@
+//! Library behind the `cargo compat` subcommand: reading manifests ([`cargo`]), fetching and caching
+//! crate metadata ([`crates`], [`registry`], [`cache`]), validating candidate versions
+//! ([`validator`]) and searching the compatible range of each dependency ([`resolver`]).
+//!
+//! Every function reports failures through [`error::Error`] rather than exiting, the command line
+//! interface in `main.rs` maps them to exit statuses.
+pub mod cache;
+pub mod cargo;
+pub mod crates;
+pub mod diff;
+pub mod docsrs;
+pub mod error;
+pub mod progress;
+pub mod registry;
+pub mod resolver;
+pub mod validator;
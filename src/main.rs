@@ -1,25 +1,65 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    io::IsTerminal,
     path::{Path, PathBuf},
 };
 
 use chrono::{DateTime, Duration, Utc};
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use log::{debug, info, warn};
-use semver::VersionReq;
-
-use crate::{
-    cache::CrateCache,
-    cargo::{Cargo, CargoPackage},
-    crates::Crate,
-    validator::{BuildOptions, TestOptions},
+use semver::{Version, VersionReq};
+
+use cargo_compat::{
+    cache::{CrateCache, ValidationCache},
+    cargo::CargoPackage,
+    crates::{Crate, Dependency, FetchOptions},
+    error, progress,
+    registry::Registry,
+    resolver::{self, Granularity, RequirementChange, ResolveReport, ResolverOptions, Strategy},
+    validator::{self, BuildOptions, EditBackend, PinStrategy, TestOptions},
 };
-pub mod cache;
-pub mod cargo;
-pub mod crates;
-pub mod error;
-pub mod resolver;
-pub mod validator;
+
+mod config;
+
+// Exit statuses, documented in the README. 2 is left to clap for invalid command lines.
+/// Any error without a more specific status
+const EXIT_FAILURE: i32 = 1;
+/// The currently selected versions do not build (or their tests fail)
+const EXIT_BASELINE_FAILED: i32 = 3;
+/// Crate metadata could not be retrieved from crates.io, a registry or the cache
+const EXIT_REGISTRY_ERROR: i32 = 4;
+/// A manifest or lock file could not be read or parsed
+const EXIT_MANIFEST_ERROR: i32 = 5;
+/// No package or dependency matched the `--include` patterns or `--only` names
+const EXIT_NO_MATCH: i32 = 6;
+/// Some versions matching the current requirements fail (`--strategy verify`)
+const EXIT_VERIFY_FAILED: i32 = 7;
+/// A resolution stopped by `--max-duration`, as used by `timeout(1)`
+const EXIT_DEADLINE_EXCEEDED: i32 = 124;
+/// A run interrupted with Ctrl-C, as shells report a process killed by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Exit status reporting the category of `error`.
+fn exit_code(error: &error::Error) -> i32 {
+    use error::Error;
+
+    match error {
+        Error::InvalidBaseline(_) => EXIT_BASELINE_FAILED,
+        Error::CratesIoApiError(_)
+        | Error::RegistryError { .. }
+        | Error::CrateNotFound(_)
+        | Error::MissingFromCache(_) => EXIT_REGISTRY_ERROR,
+        Error::CargoManifestParseError { .. }
+        | Error::CargoLockParseError { .. }
+        | Error::NotAWorkspaceMember { .. }
+        | Error::InvalidWorkspacePattern { .. }
+        | Error::MissingWorkspaceDependency { .. } => EXIT_MANIFEST_ERROR,
+        Error::NoMatchingPackages { .. } => EXIT_NO_MATCH,
+        Error::DeadlineExceeded(_) => EXIT_DEADLINE_EXCEEDED,
+        Error::Interrupted => EXIT_INTERRUPTED,
+        _ => EXIT_FAILURE,
+    }
+}
 
 cargo_subcommand_metadata::description!(
     "A tool to automatically determine compatible versions of Rust crates for Cargo packages and workspaces."
@@ -31,18 +71,78 @@ pub struct Arguments {
     #[command(subcommand)]
     pub command: Command,
 
-    /// Cache directory to use for storing downloaded crate information and other data
-    /// Defaults to $HOME/.cache/rust-version-searcher
-    /// Use --cache-dir <path> to specify
-    #[clap(long)]
+    /// Configuration file providing defaults for the resolve options, flags given on the command line take
+    /// precedence. Defaults to the cargo-compat.toml of the repository when it exists
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Directory storing the downloaded crate information and validation outcomes. By default, the
+    /// `CARGO_COMPAT_CACHE_DIR` environment variable or else `$HOME/.cache/cargo-compat` will be used.
+    #[clap(long, env = "CARGO_COMPAT_CACHE_DIR")]
     pub cache_dir: Option<String>,
 
-    /// Age limit for cached crate information in hours. Defaults to 48 hours.
+    /// Age limit for cached crate information and validation outcomes in hours. Defaults to 48 hours.
     /// Use --cache-age <hours> to specify
     #[clap(long, default_value_t = 48)]
     pub cache_age: u32,
 
-    /// Whether to display verbose logging information
+    /// Age limit in minutes for cached fetch failures (crates missing from their registry): within it
+    /// the crate is reported missing without being fetched again. Defaults to 10 minutes, 0 disables it.
+    /// Use --negative-cache-age <minutes> to specify
+    #[clap(long, default_value_t = 10)]
+    pub negative_cache_age: u32,
+
+    /// Maximum number of concurrent requests to crates.io when fetching crate information. Defaults to 4.
+    /// Use --fetch-concurrency <n> to specify
+    #[clap(long, default_value_t = 4)]
+    pub fetch_concurrency: usize,
+
+    /// Save the crate cache after every <n> fetched crates instead of once all of them are fetched, so
+    /// that an interrupted fetch keeps its progress. Defaults to 0 (disabled).
+    /// Use --save-every <n> to specify
+    #[clap(long, default_value_t = 0)]
+    pub save_every: usize,
+
+    /// Number of times a crates.io or sparse registry request failing with a transient error (network,
+    /// 429, 5xx) is retried, with exponential backoff. Defaults to 3. A crate still failing then uses
+    /// its cached metadata when there is some
+    #[clap(long, default_value_t = 3)]
+    pub fetch_retries: u32,
+
+    /// User agent sent to crates.io and sparse registries, so that operators can identify their own
+    /// traffic (crates.io asks for one naming the tool and a contact)
+    #[clap(long, default_value = cargo_compat::crates::DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+
+    /// Fetch the full metadata of crates, including the dependencies of every version. Cached entries
+    /// without dependencies are upgraded in place
+    #[clap(long)]
+    pub full_metadata: bool,
+
+    /// Index URL of the registry to fetch crate information from, instead of crates.io. Only sparse
+    /// indexes are supported. Dependencies declaring `registry = "..."` use the index configured for
+    /// that registry in `.cargo/config.toml` instead.
+    /// Use --registry sparse+https://<index>/ to specify
+    #[clap(long)]
+    pub registry: Option<String>,
+
+    /// Token authenticating the requests to the `--registry` index, for private registries that do not
+    /// serve it anonymously. Sent as the `Authorization` header, never to crates.io. Not read from
+    /// `CARGO_REGISTRY_TOKEN`, the crates.io token of cargo, which must not leak to another registry
+    #[clap(long, env = "CARGO_COMPAT_REGISTRY_TOKEN", hide_env_values = true)]
+    pub registry_token: Option<String>,
+
+    /// Offline mode, never contact crates.io and only use cached crate information (regardless of its age)
+    /// Use --offline to enable
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Format of the log records: colored lines (default) or JSON lines with timestamp, level, target,
+    /// message and source location
+    #[clap(long, value_enum, default_value_t = LogFormat::Human)]
+    pub log_format: LogFormat,
+
+    /// Whether to display verbose logging information, this also forwards the output of cargo while validating
     /// Use --verbose or -v to enable
     #[clap(short, long)]
     pub verbose: bool,
@@ -58,6 +158,296 @@ pub struct Arguments {
     pub silent: bool,
 }
 
+/// Options of the `resolve` command
+#[derive(Args, Clone)]
+pub struct ResolveArgs {
+    /// Path to the Cargo.toml file or workspace directory, defaults to current directory
+    pub path: Option<String>,
+
+    /// When reading a workspace, include only members matching these glob patterns; when reading a single
+    /// package, only resolve dependencies whose name matches them (can be used multiple times)
+    /// Example: --include "crates/*" --include "tools/**", or --include "serde*" for a single package
+    #[clap(long)]
+    pub include: Vec<String>,
+
+    /// Optionally specify the path to the `cargo` executable to use. By default, the cargo running cargo-compat as a
+    /// subcommand (`CARGO`) or else the system `cargo` in PATH will be used.
+    #[clap(long, env = "CARGO", default_value = "cargo")]
+    pub cargo_path: String,
+
+    /// How candidate versions are forced into the build: `precise` pins Cargo.lock with `cargo update --precise`,
+    /// `exact` writes an `=x.y.z` requirement (which can fail spuriously when other crates need a newer compatible version)
+    #[clap(long, value_enum, default_value_t = PinStrategyArg::Precise)]
+    pub pin_strategy: PinStrategyArg,
+
+    /// How requirements are written into the manifests: `toml-edit` replaces the version string of each
+    /// entry in the manifest text, keeping formatting and comments, `cargo` runs `cargo add`
+    #[clap(long, value_enum, default_value_t = EditBackendArg::TomlEdit)]
+    pub edit_backend: EditBackendArg,
+
+    /// `widen` searches the widest range of working versions, `verify` validates every version matching
+    /// the current requirement and reports those failing, without changing the requirement
+    #[clap(long, value_enum, default_value_t = StrategyArg::Widen)]
+    pub strategy: StrategyArg,
+
+    /// Precision of the bounds searched: `major` and `minor` only validate the newest version of each
+    /// semver-compatible or minor line, `patch` validates any version
+    #[clap(long, value_enum, default_value_t = GranularityArg::Patch)]
+    pub granularity: GranularityArg,
+
+    /// Build in release mode instead of debug mode
+    #[clap(long)]
+    pub release: bool,
+
+    /// Do not run tests, only build the packages to validate
+    #[clap(long)]
+    pub no_test: bool,
+
+    /// Test every target (`cargo test --all-targets`): binaries, tests, benches and examples
+    #[clap(long)]
+    pub all_targets: bool,
+
+    /// With --all-targets, which leaves the doc tests out, run them in a separate `cargo test --doc`
+    #[clap(long, requires = "all_targets")]
+    pub doc: bool,
+
+    /// Validate candidates with `cargo check` instead of `cargo build`, which skips code generation and
+    /// is much faster. Only applies with --no-test, tests always run with `cargo test`
+    #[clap(long)]
+    pub check: bool,
+
+    /// Search the bounds with `cargo check`, then run the tests on the bounds found only. A bound
+    /// failing its tests is left out and the search continues
+    #[clap(long)]
+    pub confirm_with_tests: bool,
+
+    /// Compare the checksum Cargo.lock records for each candidate with the one published by the
+    /// registry, warning on mismatch
+    #[clap(long)]
+    pub verify_checksums: bool,
+
+    /// Validate candidates by running this command in the repository instead of cargo build/test, a
+    /// non-zero exit status failing the candidate. The command is split into words like a shell does
+    /// (quotes group words) but not run by a shell, wrap pipes and redirections in a script
+    #[clap(long, value_parser = parse_check_command)]
+    pub check_command: Option<String>,
+
+    /// Use the following features when building/testing. `package:feat1,feat2` only enables them on
+    /// that package of the workspace
+    #[clap(long, short)]
+    pub features: Vec<String>,
+
+    /// Build/test with every feature enabled, optional dependencies are then searched as well
+    #[clap(long)]
+    pub all_features: bool,
+
+    /// Build/test without the default features of the selected packages
+    #[clap(long)]
+    pub no_default_features: bool,
+
+    /// Pass --frozen to every cargo command: Cargo.lock is never updated and the network is not used,
+    /// candidates that cannot be locked without changes fail with cargo's error
+    #[clap(long)]
+    pub frozen: bool,
+
+    /// Number of parallel jobs cargo may use when building/testing, defaults to cargo's own default
+    #[clap(long, short)]
+    pub jobs: Option<u32>,
+
+    /// Delay in milliseconds inserted before each candidate validation, 0 disables throttling
+    #[clap(long, default_value_t = 500)]
+    pub throttle_ms: u32,
+
+    /// Write the oldest working version of each dependency as a lower-bound requirement (`>=x.y.z`)
+    /// instead of the range found by the search
+    #[clap(long)]
+    pub minimize: bool,
+
+    /// Validate candidates one by one outward from the current version instead of bisecting, so that
+    /// the range found never contains a failing version when the working versions are not contiguous
+    #[clap(long)]
+    pub linear_search: bool,
+
+    /// Keep resolving the other crates when the search of one fails (e.g. a network error), writing
+    /// the successful resolutions and exiting with a failure status at the end
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Also consider prerelease versions (e.g. `1.0.0-alpha`) as candidates
+    #[clap(long)]
+    pub allow_prerelease: bool,
+
+    /// Skip the local validation of candidate versions whose docs.rs build failed. Such versions are only
+    /// built locally when they lie right outside the range found, to confirm the bound
+    #[clap(long)]
+    pub use_docsrs_prefilter: bool,
+
+    /// Maximum number of candidate validations per crate. Once reached, the remaining candidates are assumed
+    /// to fail and the bounds found so far are kept, trading precision for a bounded run time
+    #[clap(long)]
+    pub max_comparisons: Option<usize>,
+
+    /// Keep the build artifacts once done instead of running `cargo clean`, so that later runs build
+    /// incrementally (uses more disk space)
+    #[clap(long)]
+    pub no_clean: bool,
+
+    /// Keep the manifest requirements: candidates are pinned in Cargo.lock only (`cargo update --precise`) and the
+    /// oldest/newest working versions allowed by the current requirements are reported, nothing is written
+    #[clap(long)]
+    pub lock_only: bool,
+
+    /// Kill cargo commands running longer than this many seconds, the candidate being validated then
+    /// fails (guards against hung builds)
+    #[clap(long)]
+    pub build_timeout: Option<u64>,
+
+    /// Stop the resolution once it ran for this many seconds: no new validation is started, the crates
+    /// resolved so far are reported and the command exits with status 124
+    #[clap(long)]
+    pub max_duration: Option<u64>,
+
+    /// Rustup toolchain every cargo command runs with (e.g. `1.65` or `nightly`), to find the versions
+    /// that build under an older compiler
+    #[clap(long)]
+    pub toolchain: Option<String>,
+
+    /// Without `--toolchain`, validate with the toolchain matching the lowest `rust-version` declared
+    /// by the targets, so that the resolved versions do not raise their MSRV
+    #[clap(long)]
+    pub msrv: bool,
+
+    /// Directory the validation builds write their artifacts to (sets `CARGO_TARGET_DIR`), instead of
+    /// the `target/` directory of the repository
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    /// Target triple to build and test for (passed to cargo as `--target`), so that dependencies
+    /// only used on that platform are exercised
+    #[clap(long)]
+    pub target: Option<String>,
+
+    /// Number of crates searched concurrently, each additional worker validating in its own temporary
+    /// copy of the repository (with its own target directory)
+    #[clap(long, default_value_t = 1)]
+    pub parallel: usize,
+
+    /// Only search the versions of these dependencies (can be used multiple times), the other
+    /// dependencies keep their current versions in every build
+    #[clap(long)]
+    pub only: Vec<String>,
+
+    /// Abort when a dependency does not exist in its registry instead of skipping it with a warning
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Log the outcome of each validated version at debug level only (shown with --verbose), keeping
+    /// the result of each crate and the final summary
+    #[clap(long)]
+    pub summary_only: bool,
+
+    /// Only print the resolved requirements: Cargo.toml and Cargo.lock are restored to their original contents afterwards
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Output format of the resolved versions
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
+    /// Also write the resolve report (the output of --format json) to this file, whatever the output
+    /// format. Only applies to resolve and compat-range
+    #[clap(long)]
+    pub report: Option<PathBuf>,
+}
+
+/// Command line counterpart of [`PinStrategy`]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PinStrategyArg {
+    Precise,
+    Exact,
+}
+
+impl From<PinStrategyArg> for PinStrategy {
+    fn from(value: PinStrategyArg) -> Self {
+        match value {
+            PinStrategyArg::Precise => PinStrategy::Precise,
+            PinStrategyArg::Exact => PinStrategy::Exact,
+        }
+    }
+}
+
+/// Command line counterpart of [`EditBackend`]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EditBackendArg {
+    Cargo,
+    #[value(alias = "in-place")]
+    TomlEdit,
+}
+
+impl From<EditBackendArg> for EditBackend {
+    fn from(value: EditBackendArg) -> Self {
+        match value {
+            EditBackendArg::Cargo => EditBackend::Cargo,
+            EditBackendArg::TomlEdit => EditBackend::TomlEdit,
+        }
+    }
+}
+
+/// Command line counterpart of [`Strategy`]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StrategyArg {
+    Widen,
+    Verify,
+}
+
+impl From<StrategyArg> for Strategy {
+    fn from(value: StrategyArg) -> Self {
+        match value {
+            StrategyArg::Widen => Strategy::Widen,
+            StrategyArg::Verify => Strategy::Verify,
+        }
+    }
+}
+
+/// Command line counterpart of [`Granularity`]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GranularityArg {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl From<GranularityArg> for Granularity {
+    fn from(value: GranularityArg) -> Self {
+        match value {
+            GranularityArg::Major => Granularity::Major,
+            GranularityArg::Minor => Granularity::Minor,
+            GranularityArg::Patch => Granularity::Patch,
+        }
+    }
+}
+
+/// Format of the log records
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Colored, human readable lines
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+/// Format used to print the result of a command
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable list
+    Human,
+    /// JSON object serialized from the resolve report (logs are sent to stderr)
+    Json,
+    /// Ready-to-paste `[dependencies]` tables holding the resolved requirements, the manifests are left
+    /// untouched (`resolve` only, logs are sent to stderr)
+    TomlBlock,
+}
+
 #[derive(Subcommand)]
 pub enum CacheCommand {
     /// Clean the cache directory by removing expired entries
@@ -65,11 +455,23 @@ pub enum CacheCommand {
         /// If set, removes the entire cache directory instead of just expired entries
         #[clap(long)]
         full: bool,
+
+        /// Only remove the cached metadata of the crates whose name matches this glob pattern
+        /// (e.g. `serde*`), whatever their age
+        #[clap(long = "crate", conflicts_with = "full")]
+        crate_pattern: Option<String>,
     },
 
     /// Display information about the current cache
     Info,
 
+    /// Print every cached crate version as a `<name> <version> <yanked>` line, for grep or awk
+    List {
+        /// Only list the versions of this crate
+        #[clap(long = "crate")]
+        crate_name: Option<String>,
+    },
+
     /// Manually fetch a package and display information about it
     Fetch {
         /// Name of the crate to fetch
@@ -82,6 +484,33 @@ pub enum CacheCommand {
         #[clap(long)]
         force: bool,
     },
+
+    /// Fetch every crate listed in a file in one pass, e.g. to warm the cache before an offline run
+    ///
+    /// Missing crates are reported at the end without aborting the batch.
+    FetchAll {
+        /// File listing one crate name per line, empty lines and lines starting with `#` are ignored
+        #[clap(long)]
+        from: PathBuf,
+
+        /// Force re-fetching the crate information even if it is present in the cache
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Write the crate cache to a file, for instance to share it as a CI artifact
+    Export {
+        /// Destination file
+        file: PathBuf,
+    },
+
+    /// Merge a crate cache previously written by `cache export` into the local cache
+    ///
+    /// On conflict the most recently fetched entry of each crate is kept.
+    Import {
+        /// File written by `cache export`
+        file: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -95,8 +524,9 @@ pub enum Command {
         /// Path to the Cargo.toml file or workspace directory, defaults to current directory
         path: Option<String>,
 
-        /// When reading a workspace, include only packages matching these glob patterns (can be used multiple times)
-        /// Example: --include "crates/*" --include "tools/**"
+        /// When reading a workspace, include only members matching these glob patterns; when reading a single
+        /// package, only list dependencies whose name matches them (can be used multiple times)
+        /// Example: --include "crates/*" --include "tools/**", or --include "serde*" for a single package
         #[clap(long)]
         include: Vec<String>,
     },
@@ -106,49 +536,165 @@ pub enum Command {
     /// This will fetch information about all dependencies from crates.io then it will attempt to search for a compatible version
     /// based on the version requirements specified in the Cargo.toml file(s).
     ///
-    /// Note: Git-based dependencies are not supported and will be skipped with a warning.
+    /// Note: Git-based dependencies are kept pinned to their git source: they are part of every build but their version is not searched.
     ///
-    Resolve {
-        /// Path to the Cargo.toml file or workspace directory, defaults to current directory
-        path: Option<String>,
+    Resolve(ResolveArgs),
 
-        /// When reading a workspace, include only packages matching these glob patterns (can be used multiple times)
-        /// Example: --include "crates/*" --include "tools/**"
-        #[clap(long)]
-        include: Vec<String>,
+    /// Show the compatible version range of a single dependency, without modifying the manifest
+    ///
+    /// Only this crate is searched, the other dependencies keep their current versions. The range found is
+    /// compared with the current requirement to tell whether the requirement could be widened.
+    CompatRange {
+        /// Name of the dependency to audit
+        crate_name: String,
 
-        /// Optionally specify the path to the `cargo` executable to use. By default, the system `cargo` in PATH will be used.
-        #[clap(long, default_value = "cargo")]
-        cargo_path: String,
+        #[clap(flatten)]
+        resolve_args: ResolveArgs,
+    },
 
-        /// Build in release mode instead of debug mode
-        #[clap(long)]
-        release: bool,
+    /// Show the compatible version range of a crate on its own, without a local project
+    ///
+    /// Generates a throwaway project in the temporary directory depending only on this crate, then searches
+    /// the versions of the crate with which this trivial project builds (and passes its empty tests). The
+    /// project is removed afterwards, the path argument is not used.
+    FetchResolve {
+        /// Name of the crate to resolve
+        crate_name: String,
 
-        /// Do not run tests, only build the packages to validate
-        #[clap(long)]
-        no_test: bool,
+        /// Requirement of the crate in the generated project, defaults to any version
+        requirement: Option<VersionReq>,
 
-        /// Use the following features when building/testing
-        #[clap(long, short)]
-        features: Vec<String>,
+        #[clap(flatten)]
+        resolve_args: ResolveArgs,
+    },
+
+    /// Validate the current manifest and lock file without searching
+    ///
+    /// Runs only the build/test of the currently selected versions that `resolve` performs before its search,
+    /// and reports whether it passes. The manifests are restored afterwards.
+    Check(ResolveArgs),
+
+    /// Explain why a version of a dependency fails: check the project with that version and print the
+    /// complete cargo output
+    ///
+    /// The other dependencies keep their currently selected versions. The version is checked as given,
+    /// even when it is yanked or outside the current requirement. The manifests are restored afterwards.
+    Why {
+        /// Name of the dependency
+        crate_name: String,
+
+        /// Version of the dependency to check
+        version: Version,
+
+        #[clap(flatten)]
+        resolve_args: ResolveArgs,
+    },
+
+    /// Compare two resolve reports produced with `--format json`
+    ///
+    /// Prints, for each crate, whether its requirement was widened, narrowed, changed, added, removed or left
+    /// unchanged between the old and the new report.
+    Diff {
+        /// Report of the earlier run
+        old: PathBuf,
+
+        /// Report of the later run
+        new: PathBuf,
     },
 }
 
+impl Command {
+    /// Options of the resolve-like commands, which a configuration file may provide defaults for
+    fn resolve_args_mut(&mut self) -> Option<&mut ResolveArgs> {
+        match self {
+            Command::Resolve(resolve_args)
+            | Command::CompatRange { resolve_args, .. }
+            | Command::FetchResolve { resolve_args, .. }
+            | Command::Check(resolve_args)
+            | Command::Why { resolve_args, .. } => Some(resolve_args),
+            Command::Cache(_) | Command::ListDependencies { .. } | Command::Diff { .. } => None,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // If running as a cargo subcommand, skip the first argument
     let mut args_iter = std::env::args();
-    if let Some(first_arg) = args_iter.next() {
-        if !first_arg.ends_with("cargo-compat") {
-            // Not running as a cargo subcommand, restore the iterator
-            args_iter = std::env::args();
-        }
+    if let Some(first_arg) = args_iter.next()
+        && !first_arg.ends_with("cargo-compat")
+    {
+        // Not running as a cargo subcommand, restore the iterator
+        args_iter = std::env::args();
     }
 
-    let args = Arguments::parse_from(args_iter);
+    let matches = Arguments::command().get_matches_from(args_iter);
+    let mut args = Arguments::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     setup_logger(&args);
 
+    if args.registry_token.is_some() && args.registry.is_none() {
+        debug!("No --registry given, the registry token is not sent to crates.io");
+    }
+
+    // Defaults from the configuration file, for the flags not given on the command line
+    let config_path = args.config.clone();
+    if let Some(resolve_args) = args.command.resolve_args_mut()
+        && let Some((_, sub_matches)) = matches.subcommand()
+    {
+        let repository_dir = match resolve_args.path.as_ref().map(PathBuf::from) {
+            Some(path) if path.is_file() => {
+                path.parent().map(Path::to_path_buf).unwrap_or_default()
+            }
+            Some(path) => path,
+            None => std::env::current_dir().unwrap(),
+        };
+
+        match config::Config::discover(config_path.as_deref(), &repository_dir) {
+            Ok(Some((path, config))) => {
+                info!("Using configuration from {}", path.display());
+                if let Err(e) = config.apply(resolve_args, sub_matches) {
+                    log::error!("Invalid configuration in {}: {}", path.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Failed to load configuration: {}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+
+        // Cargo sets `CARGO` to the binary of its own toolchain, which would bypass the rustup proxy
+        // and thus the toolchain selected with `RUSTUP_TOOLCHAIN`
+        if (resolve_args.toolchain.is_some() || resolve_args.msrv)
+            && sub_matches.value_source("cargo_path")
+                == Some(clap::parser::ValueSource::EnvVariable)
+            && std::env::var_os("CARGO").is_some_and(|cargo| cargo == *resolve_args.cargo_path)
+        {
+            debug!(
+                "Ignoring CARGO={} to select the toolchain through the rustup proxy",
+                resolve_args.cargo_path
+            );
+            resolve_args.cargo_path = "cargo".to_string();
+        }
+    }
+
+    if let Command::CompatRange { resolve_args, .. }
+    | Command::FetchResolve { resolve_args, .. }
+    | Command::Check(resolve_args)
+    | Command::Why { resolve_args, .. } = &args.command
+        && resolve_args.format == OutputFormat::TomlBlock
+    {
+        log::error!("--format toml-block only applies to resolve");
+        std::process::exit(EXIT_FAILURE);
+    }
+    if let Command::Check(resolve_args) | Command::Why { resolve_args, .. } = &args.command
+        && resolve_args.report.is_some()
+    {
+        log::error!("--report only applies to resolve and compat-range");
+        std::process::exit(EXIT_FAILURE);
+    }
+
     // Responsibility disclaimer (info-level unless suppressed)
     log::info!(
         "Please use cargo-compat responsibly: resolving can be expensive and may put load on crates.io and docs.rs. Prefer caching, avoid tight loops, and limit scope with --include."
@@ -164,7 +710,8 @@ async fn main() {
                 .map(std::path::PathBuf::from)
                 .unwrap_or_else(|| std::env::current_dir().unwrap());
 
-            let targets = read_cargo_from_path_with_includes(&path, include);
+            let targets = read_cargo_from_path_with_includes(&path, include)
+                .unwrap_or_else(|code| std::process::exit(code));
 
             for package in targets {
                 println!("Package: {} (version: {})", package.name, package.version);
@@ -172,183 +719,1478 @@ async fn main() {
                 println!("Dependencies:");
                 for dep in &package.dependencies {
                     println!(
-                        "  - {} {}{}{}",
+                        "  - {} {}{}{}{}",
                         dep.crate_name,
                         dep.required_version,
                         if dep.optional { " (optional)" } else { "" },
-                        if dep.git { " (git)" } else { "" }
+                        dep.git_source
+                            .as_ref()
+                            .map(|src| format!(" (git: {})", src))
+                            .unwrap_or_default(),
+                        dep.target
+                            .as_ref()
+                            .map(|platform| format!(" (target: {})", platform))
+                            .unwrap_or_default()
                     );
                 }
 
                 println!("Build Dependencies:");
                 for dep in &package.build_dependencies {
                     println!(
-                        "  - {} {}{}{}",
+                        "  - {} {}{}{}{}",
                         dep.crate_name,
                         dep.required_version,
                         if dep.optional { " (optional)" } else { "" },
-                        if dep.git { " (git)" } else { "" }
+                        dep.git_source
+                            .as_ref()
+                            .map(|src| format!(" (git: {})", src))
+                            .unwrap_or_default(),
+                        dep.target
+                            .as_ref()
+                            .map(|platform| format!(" (target: {})", platform))
+                            .unwrap_or_default()
                     );
                 }
 
                 println!("Dev Dependencies:");
                 for dep in &package.dev_dependencies {
                     println!(
-                        "  - {} {}{}{}",
+                        "  - {} {}{}{}{}",
                         dep.crate_name,
                         dep.required_version,
                         if dep.optional { " (optional)" } else { "" },
-                        if dep.git { " (git)" } else { "" }
+                        dep.git_source
+                            .as_ref()
+                            .map(|src| format!(" (git: {})", src))
+                            .unwrap_or_default(),
+                        dep.target
+                            .as_ref()
+                            .map(|platform| format!(" (target: {})", platform))
+                            .unwrap_or_default()
                     );
                 }
 
                 println!();
             }
         }
-        Command::Resolve {
-            path,
-            include,
-            cargo_path,
-            release,
-            features,
-            no_test,
-        } => {
-            do_resolve_command(
-                &args,
-                path,
-                include,
-                cargo_path.clone(),
-                *release,
-                *no_test,
-                features.clone(),
-            )
-            .await;
-        }
+        Command::Resolve(resolve_args) => {
+            do_resolve_command(&args, resolve_args).await;
+        }
+        Command::CompatRange {
+            crate_name,
+            resolve_args,
+        } => {
+            do_compat_range_command(&args, crate_name, resolve_args).await;
+        }
+        Command::FetchResolve {
+            crate_name,
+            requirement,
+            resolve_args,
+        } => {
+            do_fetch_resolve_command(&args, crate_name, requirement.as_ref(), resolve_args).await;
+        }
+        Command::Check(resolve_args) => {
+            do_check_command(&args, resolve_args).await;
+        }
+        Command::Why {
+            crate_name,
+            version,
+            resolve_args,
+        } => {
+            do_why_command(&args, crate_name, version, resolve_args).await;
+        }
+        Command::Diff { old, new } => {
+            do_diff_command(old, new);
+        }
+    }
+}
+
+/// Handle Ctrl-C: the first one stops the validations, the command then fails with
+/// [`error::Error::Interrupted`], restoring the manifests and cleaning up on its way out. A second one
+/// exits right away.
+fn install_interrupt_handler() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            warn!("Failed to listen for Ctrl-C, interrupting leaves the manifests modified");
+            return;
+        }
+        warn!(
+            "Interrupted, restoring the manifests and cleaning up (press Ctrl-C again to exit right away)"
+        );
+        validator::interrupt();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+}
+
+/// Build the resolver of a resolve-like command, searching only the dependencies named in `only` when
+/// it is not empty. Also returns the path of the validation cache. The error holds the exit status,
+/// the failure being logged already.
+async fn prepare_resolver(
+    args: &Arguments,
+    resolve_args: &ResolveArgs,
+    only: &[String],
+) -> Result<(resolver::Resolver, PathBuf), i32> {
+    let path = resolve_args
+        .path
+        .as_ref()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::env::current_dir().unwrap());
+
+    let targets = read_cargo_from_path_with_includes(&path, &resolve_args.include)?;
+
+    let working_dir = working_dir(&path);
+
+    // Read the cache
+    let cache_paths = find_cache_path(&args.cache_dir);
+
+    // Provide a list of all dependencies that must be resolved, grouped by registry
+    let default_registry = default_registry(args)?;
+    let mut all_dependencies: BTreeMap<Registry, Vec<String>> = BTreeMap::new();
+    let mut dependency_registries = BTreeMap::new();
+    let mut dependency_features: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut default_features: BTreeMap<String, bool> = BTreeMap::new();
+    let mut workspace_dependencies = BTreeSet::new();
+    let mut dependency_manifests: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    let repository_dir = working_dir
+        .canonicalize()
+        .unwrap_or_else(|_| working_dir.clone());
+    let mut dependency_platforms = BTreeMap::new();
+    let mut platform_independent = BTreeSet::new();
+    if let Err(e) = check_only_crates(only, &targets) {
+        log::error!("{}", e);
+        return Err(EXIT_NO_MATCH);
+    }
+
+    let (features, package_features) = split_package_features(&resolve_args.features);
+    if let Some(unknown) = package_features
+        .keys()
+        .find(|name| !targets.iter().any(|package| &package.name == *name))
+    {
+        log::error!(
+            "Package {} given to --features is not among the selected packages: {:?}",
+            unknown,
+            targets.iter().map(|p| p.name.as_str()).collect::<Vec<_>>()
+        );
+        return Err(EXIT_NO_MATCH);
+    }
+
+    for package in &targets {
+        let enabled_optional = package.enabled_optional_dependencies(
+            &features
+                .iter()
+                .chain(package_features.get(&package.name).into_iter().flatten())
+                .cloned()
+                .collect::<Vec<_>>(),
+            !resolve_args.no_default_features,
+        );
+        for dep in &package.dependencies {
+            if !only.is_empty() && !only.contains(&dep.crate_name) {
+                continue;
+            }
+
+            if dep.optional
+                && !resolve_args.all_features
+                && !enabled_optional.contains(&dep.crate_name)
+            {
+                info!(
+                    "Optional dependency {} in package {} is not enabled by the selected features and will not be searched",
+                    dep.crate_name, package.name
+                );
+                continue;
+            }
+
+            if dep.git {
+                info!(
+                    "Git dependency {} in package {} is pinned to {} and will not be searched",
+                    dep.crate_name,
+                    package.name,
+                    dep.git_source.as_deref().unwrap_or("its git source")
+                );
+                continue;
+            }
+
+            if dep.patched {
+                warn!(
+                    "Dependency {} in package {} is overridden by [patch] or [replace] and will not be searched",
+                    dep.crate_name, package.name
+                );
+                continue;
+            }
+
+            // Inherited requirements are shared by the members, they are searched and written once
+            if dep.workspace {
+                workspace_dependencies.insert(dep.crate_name.clone());
+            } else {
+                // Manifests are edited relative to the working directory, which parallel workers copy
+                let manifest_path = if package.manifest_path.is_dir() {
+                    package.manifest_path.join("Cargo.toml")
+                } else {
+                    package.manifest_path.clone()
+                };
+                let manifest_path = manifest_path.canonicalize().unwrap_or(manifest_path);
+                let manifest_path = manifest_path
+                    .strip_prefix(&repository_dir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(manifest_path);
+                let manifests = dependency_manifests
+                    .entry(dep.crate_name.clone())
+                    .or_default();
+                for kind in package.dependency_kinds(&dep.crate_name) {
+                    if !manifests.contains(&(manifest_path.clone(), kind)) {
+                        manifests.push((manifest_path.clone(), kind));
+                    }
+                }
+            }
+
+            // `cargo add` edits the platform-specific table unless the crate is used everywhere
+            match &dep.target {
+                Some(platform) => {
+                    dependency_platforms
+                        .entry(dep.crate_name.clone())
+                        .or_insert_with(|| platform.clone());
+                }
+                None => {
+                    platform_independent.insert(dep.crate_name.clone());
+                }
+            }
+
+            // Candidates must provide the features the dependency is used with
+            dependency_features
+                .entry(dep.crate_name.clone())
+                .or_default()
+                .extend(dep.features.iter().cloned());
+
+            // A single `cargo add` serves every target, default features must agree between them
+            let enabled = dep.default_features.unwrap_or(true);
+            match default_features.insert(dep.crate_name.clone(), enabled) {
+                Some(previous) if previous != enabled => warn!(
+                    "Dependency {} is declared both with and without default features, package {} uses default-features = {}",
+                    dep.crate_name, package.name, enabled
+                ),
+                _ => {}
+            }
+
+            let registry = match &dep.registry {
+                Some(name) => {
+                    dependency_registries.insert(dep.crate_name.clone(), name.clone());
+                    Registry::from_cargo_config(&working_dir, name).map_err(|e| {
+                        log::error!(
+                            "Cannot determine registry of dependency {}: {}",
+                            dep.crate_name,
+                            e
+                        );
+                        EXIT_FAILURE
+                    })?
+                }
+                None => default_registry.clone(),
+            };
+
+            // A crate used by several targets is fetched once
+            let registry_dependencies = all_dependencies.entry(registry).or_default();
+            if !registry_dependencies.contains(&dep.crate_name) {
+                registry_dependencies.push(dep.crate_name.clone());
+            }
+        }
+    }
+
+    // Resolve all packages
+    let validation_cache_path = cache_paths.validation_cache.clone();
+    let package_informations =
+        resolve_packages(args, cache_paths, all_dependencies, resolve_args.strict).await?;
+    let build_opts = BuildOptions {
+        packages: Some(targets.iter().map(|p| p.name.clone()).collect()),
+        features: if features.is_empty() {
+            None
+        } else {
+            Some(features)
+        },
+        package_features,
+        all_features: resolve_args.all_features,
+        no_default_features: resolve_args.no_default_features,
+        release: resolve_args.release,
+        jobs: resolve_args.jobs,
+        target: resolve_args.target.clone(),
+    };
+
+    let toolchain = resolve_args.toolchain.clone().or_else(|| {
+        let msrv = targets
+            .iter()
+            .filter_map(|target| target.rust_version.as_ref())
+            .min()
+            .filter(|_| resolve_args.msrv)?;
+        info!("Validating with the rust-version of the targets: {}", msrv);
+        Some(msrv.to_string())
+    });
+    if resolve_args.msrv && toolchain.is_none() {
+        warn!("--msrv is ignored, none of the targets declares a rust-version");
+    }
+
+    let validator = validator::CargoRepoValidator::new(Some(resolve_args.cargo_path.clone()))
+        .with_working_dir(working_dir.clone())
+        .with_registries(dependency_registries)
+        .with_platforms(
+            dependency_platforms
+                .into_iter()
+                .filter(|(name, _)| !platform_independent.contains(name))
+                .collect(),
+        )
+        .with_features(
+            dependency_features
+                .into_iter()
+                .filter(|(_, features)| !features.is_empty())
+                .map(|(name, features)| (name, features.into_iter().collect()))
+                .collect(),
+        )
+        .with_no_default_features(
+            default_features
+                .into_iter()
+                .filter(|(_, enabled)| !enabled)
+                .map(|(name, _)| name)
+                .collect(),
+        )
+        .with_workspace_dependencies(workspace_dependencies)
+        .with_dependency_manifests(dependency_manifests)
+        .with_pin_strategy(resolve_args.pin_strategy.into())
+        .with_edit_backend(resolve_args.edit_backend.into())
+        .with_toolchain(toolchain.clone())
+        .with_target_dir(resolve_args.target_dir.as_ref().map(|target_dir| {
+            // Cargo runs from the repository, a relative directory is taken from where we run
+            std::path::absolute(target_dir).unwrap_or_else(|_| target_dir.clone())
+        }))
+        .with_frozen(resolve_args.frozen)
+        .with_timeout(
+            resolve_args
+                .build_timeout
+                .map(std::time::Duration::from_secs),
+        )
+        // `why` always shows the cargo output it explains the failure with
+        .with_stream_output(
+            args.verbose
+                || matches!(
+                    &args.command,
+                    Command::Why { resolve_args, .. } if resolve_args.format == OutputFormat::Human
+                ),
+        );
+
+    // Fail early rather than failing every candidate when the toolchain is not installed
+    if toolchain.is_some() {
+        match validator.cargo_version() {
+            Ok(version) => info!("Validating with {}", version),
+            Err(e) => {
+                log::error!("{}", e);
+                return Err(EXIT_FAILURE);
+            }
+        }
+    }
+
+    // A value from the configuration file did not go through the argument parser
+    let check_command = match resolve_args
+        .check_command
+        .as_deref()
+        .map(split_check_command)
+    {
+        Some(Ok(words)) => words,
+        Some(Err(e)) => {
+            log::error!("Invalid check-command: {}", e);
+            return Err(EXIT_FAILURE);
+        }
+        None => vec![],
+    };
+    if resolve_args.check_command.is_some() && (resolve_args.check || resolve_args.no_test) {
+        warn!("--check-command replaces cargo build/test, --check and --no-test are ignored");
+    } else if resolve_args.no_test && (resolve_args.all_targets || resolve_args.doc) {
+        warn!("--all-targets and --doc select the tests to run, they are ignored with --no-test");
+    } else if resolve_args.doc && !resolve_args.all_targets {
+        // Only reachable from the configuration file, the argument parser requires --all-targets
+        warn!(
+            "--doc only applies with --all-targets, a plain cargo test already runs the doc tests"
+        );
+    } else if resolve_args.check && !resolve_args.no_test {
+        warn!("--check only applies with --no-test, candidates are validated with cargo test");
+    }
+    if resolve_args.confirm_with_tests {
+        if resolve_args.no_test || resolve_args.check_command.is_some() {
+            warn!(
+                "--confirm-with-tests needs the tests to run, it is ignored with --no-test and --check-command"
+            );
+        } else if resolve_args.strategy == StrategyArg::Verify {
+            warn!(
+                "--confirm-with-tests only applies to the search, --strategy verify runs the tests on every version"
+            );
+        }
+    }
+
+    let mut resolver = resolver::Resolver::new(
+        targets,
+        working_dir,
+        package_informations,
+        Box::new(validator),
+        build_opts,
+        if resolve_args.no_test {
+            None
+        } else {
+            Some(TestOptions {
+                filters: vec![],
+                all_targets: resolve_args.all_targets,
+                doc: resolve_args.doc,
+            })
+        },
+        ResolverOptions {
+            throttle: std::time::Duration::from_millis(resolve_args.throttle_ms as u64),
+            lock_only: resolve_args.lock_only,
+            minimize: resolve_args.minimize,
+            parallel: resolve_args.parallel,
+            allow_prerelease: resolve_args.allow_prerelease,
+            docsrs_prefilter: resolve_args.use_docsrs_prefilter,
+            keep_artifacts: resolve_args.no_clean,
+            max_comparisons: resolve_args.max_comparisons,
+            max_duration: resolve_args
+                .max_duration
+                .map(std::time::Duration::from_secs),
+            cargo_check: resolve_args.check,
+            check_command,
+            strategy: resolve_args.strategy.into(),
+            linear_search: resolve_args.linear_search,
+            granularity: resolve_args.granularity.into(),
+            keep_going: resolve_args.keep_going,
+            confirm_with_tests: resolve_args.confirm_with_tests,
+            verify_checksums: resolve_args.verify_checksums,
+            summary_only: resolve_args.summary_only,
+            // The bar is only drawn for a person watching the human output
+            progress: std::io::stderr().is_terminal()
+                && !args.quiet
+                && !args.silent
+                && args.log_format == LogFormat::Human
+                && resolve_args.format != OutputFormat::Json,
+        },
+    );
+
+    resolver.validation_cache = ValidationCache::load_from_path(&validation_cache_path)
+        .unwrap_or_else(|e| {
+            warn!("Failed to load validation cache: {e}, starting with empty cache");
+            ValidationCache::default()
+        });
+    // Outcomes older than the cache age are validated again
+    resolver
+        .validation_cache
+        .filter_expired_entries(Duration::hours(args.cache_age as i64));
+
+    // From now on the manifests may be modified, Ctrl-C goes through the error paths restoring them
+    install_interrupt_handler();
+
+    Ok((resolver, validation_cache_path))
+}
+
+/// Check that every crate given to `--only` is a normal dependency of one of the `targets`, the only
+/// dependencies searched: dev and build dependencies keep their requirement.
+fn check_only_crates(only: &[String], targets: &[CargoPackage]) -> Result<(), String> {
+    let declares = |dependencies: &[Dependency], name: &str| {
+        dependencies.iter().any(|dep| dep.crate_name == name)
+    };
+    for name in only {
+        if targets
+            .iter()
+            .any(|package| declares(&package.dependencies, name))
+        {
+            continue;
+        }
+
+        if targets.iter().any(|package| {
+            declares(&package.dev_dependencies, name) || declares(&package.build_dependencies, name)
+        }) {
+            return Err(format!(
+                "Crate {} is only a dev or build dependency of the selected packages, --only targets normal dependencies",
+                name
+            ));
+        }
+        return Err(format!(
+            "Crate {} is not a dependency of the selected packages",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Directory cargo is run from and Cargo.lock is read in for the package or workspace at `path`. The
+/// manifest may be given directly: they live in its directory, or in the root of its workspace.
+fn working_dir(path: &Path) -> PathBuf {
+    if let Some(root) = cargo_compat::cargo::find_workspace_root(path) {
+        root
+    } else if path.is_file() {
+        path.parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."))
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Split the `--features` values into the features of every package and those of a single package,
+/// given as `package:feat1,feat2`.
+fn split_package_features(features: &[String]) -> (Vec<String>, BTreeMap<String, Vec<String>>) {
+    let mut shared = vec![];
+    let mut package_features: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for value in features {
+        match value.split_once(':') {
+            Some((package, features)) => package_features
+                .entry(package.trim().to_string())
+                .or_default()
+                .extend(
+                    features
+                        .split([',', ' '])
+                        .filter(|feature| !feature.is_empty())
+                        .map(String::from),
+                ),
+            None => shared.push(value.clone()),
+        }
+    }
+    (shared, package_features)
+}
+
+/// Split a `--check-command` into words like a shell does. It must name a program and balance its
+/// quotes.
+fn split_check_command(command: &str) -> Result<Vec<String>, String> {
+    match shlex::split(command) {
+        None => Err(format!("unbalanced quotes in '{}'", command)),
+        Some(words) if words.is_empty() => Err("the command is empty".to_string()),
+        Some(words) => Ok(words),
+    }
+}
+
+/// Parser of `--check-command`, rejecting the values [`split_check_command`] cannot split.
+fn parse_check_command(command: &str) -> Result<String, String> {
+    split_check_command(command).map(|_| command.to_string())
+}
+
+/// Print rows of crate name, requirement and classification below `title`, each column padded to
+/// its widest cell. The classification is shown in `color` when stdout is a terminal.
+fn print_table(title: &str, rows: &[(&str, String, String, fern::colors::Color)]) {
+    let colored = std::io::stdout().is_terminal();
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or_default();
+    let requirement_width = rows.iter().map(|row| row.1.len()).max().unwrap_or_default();
+
+    println!("{}", title);
+    for (name, requirement, classification, color) in rows {
+        let classification = if colored {
+            format!("\x1B[{}m{}\x1B[0m", color.to_fg_str(), classification)
+        } else {
+            classification.clone()
+        };
+        println!(
+            "  {:<name_width$}  {:<requirement_width$}  {}",
+            name, requirement, classification
+        );
+    }
+}
+
+/// Print the outcome of the resolution, unless the output is suppressed with `--quiet`/`--silent`.
+fn print_resolved_versions(args: &Arguments, report: &ResolveReport, lock_only: bool) {
+    use fern::colors::Color;
+
+    if args.quiet || args.silent {
+        return;
+    }
+
+    if report.verified {
+        let rows = report
+            .requirements
+            .iter()
+            .map(|(package_name, requirement)| {
+                let candidates = report
+                    .summary
+                    .get(package_name)
+                    .map(|summary| summary.candidates)
+                    .unwrap_or_default();
+                let list = |versions: &[Version]| {
+                    versions
+                        .iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                let unverified = report
+                    .unverified_versions
+                    .get(package_name)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                match report.failing_versions.get(package_name) {
+                    Some(failing) => (
+                        package_name.as_str(),
+                        requirement.to_string(),
+                        format!(
+                            "FAIL ({} of {} versions fail: {})",
+                            failing.len(),
+                            candidates,
+                            list(failing)
+                        ),
+                        Color::Red,
+                    ),
+                    None if !unverified.is_empty() => (
+                        package_name.as_str(),
+                        requirement.to_string(),
+                        format!(
+                            "UNVERIFIED ({} of {} versions not validated: {})",
+                            unverified.len(),
+                            candidates,
+                            list(unverified)
+                        ),
+                        Color::Yellow,
+                    ),
+                    None => (
+                        package_name.as_str(),
+                        requirement.to_string(),
+                        format!("OK ({} versions pass)", candidates),
+                        Color::Green,
+                    ),
+                }
+            })
+            .collect::<Vec<_>>();
+        print_table("Verified requirements:", &rows);
+    } else if lock_only {
+        let rows = report
+            .ranges
+            .iter()
+            .map(|(package_name, range)| {
+                (
+                    package_name.as_str(),
+                    format!("{} to {}", range.min, range.max),
+                    report.requirements[package_name].to_string(),
+                    Color::Cyan,
+                )
+            })
+            .collect::<Vec<_>>();
+        print_table("Working locked versions:", &rows);
+    } else if report.minimized {
+        let rows = report
+            .requirements
+            .iter()
+            .map(|(package_name, version)| {
+                (
+                    package_name.as_str(),
+                    version.to_string(),
+                    report
+                        .ranges
+                        .get(package_name)
+                        .map(|range| format!("minimum {}, newest tested {}", range.min, range.max))
+                        .unwrap_or_default(),
+                    Color::Cyan,
+                )
+            })
+            .collect::<Vec<_>>();
+        print_table("Minimum compatible versions:", &rows);
+    } else {
+        let rows = report
+            .requirements
+            .iter()
+            .map(|(package_name, version)| {
+                let kind = report.kinds.get(package_name);
+                (
+                    package_name.as_str(),
+                    version.to_string(),
+                    kind.map(|kind| kind.to_string()).unwrap_or_default(),
+                    match kind {
+                        Some(resolver::RequirementKind::Exact) => Color::Yellow,
+                        Some(resolver::RequirementKind::Range) => Color::Cyan,
+                        _ => Color::Green,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        print_table("Resolved package versions:", &rows);
+        for (package_name, versions) in &report.yanked_at_bounds {
+            println!(
+                "Note: yanked versions of {} at the boundary of its range were not validated: {}",
+                package_name,
+                versions
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        let pinned = report
+            .kinds
+            .iter()
+            .filter(|(_, kind)| **kind == resolver::RequirementKind::Exact)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        if !pinned.is_empty() {
+            warn!(
+                "Only a single version works for: {}. Their requirements are exact pins, which make them hard to share with other crates",
+                pinned.join(", ")
+            );
+        }
+    }
+}
+
+async fn do_resolve_command(args: &Arguments, resolve_args: &ResolveArgs) {
+    let (mut resolver, validation_cache_path) =
+        prepare_resolver(args, resolve_args, &resolve_args.only)
+            .await
+            .unwrap_or_else(|code| std::process::exit(code));
+
+    // Error paths drop the resolver before exiting so that the original manifests get restored
+    if let Err(e) = resolver.populate_default() {
+        log::error!("Failed to populate resolver: {}", e);
+        drop(resolver);
+        std::process::exit(exit_code(&e));
+    };
+
+    let resolved = resolver.resolve().map(|_| ());
+    save_validation_cache(&resolver, &validation_cache_path);
+    match resolve_args.format {
+        OutputFormat::Json => print_json_report(resolver.report()),
+        OutputFormat::TomlBlock => {
+            print_toml_block(resolver.report(), &resolver.path, &resolver.targets)
+        }
+        OutputFormat::Human => {}
+    }
+    if let Some(path) = &resolve_args.report {
+        write_json_report(resolver.report(), path);
+    }
+
+    // Crates resolved before the deadline are still reported, nothing is written
+    if let Err(e @ error::Error::DeadlineExceeded(_)) = &resolved {
+        log::error!("{}", e);
+        if resolve_args.format == OutputFormat::Human {
+            print_resolved_versions(args, resolver.report(), resolve_args.lock_only);
+        }
+        resolver.clean();
+        drop(resolver);
+        std::process::exit(EXIT_DEADLINE_EXCEEDED);
+    }
+
+    if let Err(e) = resolved {
+        log::error!("Failed to resolve packages: {}", e);
+        resolver.clean();
+        drop(resolver);
+        std::process::exit(exit_code(&e));
+    }
+
+    // Print the resolved versions
+    if resolve_args.format == OutputFormat::Human {
+        print_resolved_versions(args, resolver.report(), resolve_args.lock_only);
+    }
+
+    // Overwrite cargo.toml with resolved versions if needed
+    // Verifying leaves the requirements as they are
+    let verify = resolve_args.strategy == StrategyArg::Verify;
+    if resolve_args.dry_run
+        || resolve_args.lock_only
+        || verify
+        || resolve_args.format == OutputFormat::TomlBlock
+    {
+        info!("Restoring the original Cargo.toml and Cargo.lock");
+        if let Err(e) = resolver.restore() {
+            log::error!("Failed to restore original manifests: {}", e);
+        }
+    } else {
+        match resolver.write_cargo_toml_with_resolved_versions() {
+            // Stdout may be reserved for the JSON report
+            Ok(diff) if args.verbose && resolve_args.format == OutputFormat::Human => {
+                print!("{}", diff)
+            }
+            Ok(diff) if args.verbose => eprint!("{}", diff),
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Failed to write resolved versions to Cargo.toml: {}", e);
+                resolver.clean();
+                drop(resolver);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+    resolver.clean();
+
+    let errors = &resolver.report().errors;
+    if !errors.is_empty() {
+        log::error!(
+            "Failed to resolve {} crates, their requirements are unchanged: {}",
+            errors.len(),
+            errors.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        drop(resolver);
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    if !resolver.report().failing_versions.is_empty() {
+        drop(resolver);
+        std::process::exit(EXIT_VERIFY_FAILED);
+    }
+}
+
+async fn do_compat_range_command(args: &Arguments, crate_name: &str, resolve_args: &ResolveArgs) {
+    if let Err(code) = compat_range(args, crate_name, resolve_args).await {
+        std::process::exit(code);
+    }
+}
+
+/// Search the compatible versions of `crate_name`. The error holds the exit status, the failure
+/// being logged already and the manifests restored as the resolver is dropped.
+async fn compat_range(
+    args: &Arguments,
+    crate_name: &str,
+    resolve_args: &ResolveArgs,
+) -> Result<(), i32> {
+    let (mut resolver, validation_cache_path) =
+        prepare_resolver(args, resolve_args, &[crate_name.to_string()]).await?;
+
+    let Some(current) = resolver
+        .targets
+        .iter()
+        .flat_map(|target| &target.dependencies)
+        .find(|dep| dep.crate_name == crate_name)
+        .map(|dep| dep.required_version.clone())
+    else {
+        log::error!(
+            "Crate {} is not a dependency of the selected packages",
+            crate_name
+        );
+        return Err(EXIT_NO_MATCH);
+    };
+    let Some(information) = resolver.package_informations.get(crate_name).cloned() else {
+        log::error!("No registry information available for crate {}", crate_name);
+        return Err(EXIT_FAILURE);
+    };
+
+    if let Err(e) = resolver.populate_default() {
+        log::error!("Failed to populate resolver: {}", e);
+        return Err(exit_code(&e));
+    };
+
+    let resolved = resolver.resolve().map(|_| ());
+    save_validation_cache(&resolver, &validation_cache_path);
+    if let Some(path) = &resolve_args.report {
+        write_json_report(resolver.report(), path);
+    }
+    if let Err(e) = resolved {
+        log::error!("Failed to resolve {}: {}", crate_name, e);
+        resolver.clean();
+        return Err(exit_code(&e));
+    }
+
+    let report = resolver.report();
+    if let Some(e) = report.errors.get(crate_name) {
+        log::error!("Failed to resolve {}: {}", crate_name, e);
+        resolver.clean();
+        return Err(EXIT_FAILURE);
+    }
+    if resolve_args.format == OutputFormat::Json {
+        print_json_report(report);
+    } else if let Some(compatible) = report.requirements.get(crate_name) {
+        let published = information
+            .versions
+            .iter()
+            .filter(|v| !v.yanked)
+            .map(|v| &v.version)
+            .collect::<Vec<_>>();
+        let widened = published
+            .iter()
+            .filter(|v| compatible.matches(v) && !current.matches(v))
+            .count();
+
+        println!("Crate: {}", crate_name);
+        println!(
+            "Current requirement: {} ({} versions)",
+            current,
+            published.iter().filter(|v| current.matches(v)).count()
+        );
+        println!(
+            "Compatible requirement: {} ({} versions)",
+            compatible,
+            published.iter().filter(|v| compatible.matches(v)).count()
+        );
+        if let Some(range) = report.ranges.get(crate_name) {
+            println!("Compatible versions: {} to {}", range.min, range.max);
+        }
+        if widened > 0 {
+            println!(
+                "The current requirement is narrower than necessary: '{}' would allow {} more compatible versions",
+                compatible, widened
+            );
+        } else {
+            println!("The current requirement already allows every compatible version");
+        }
+    } else {
+        // Skipped by the baseline check, no available version matches the current requirement
+        println!("Crate: {}", crate_name);
+        println!(
+            "Current requirement: {} skipped ({})",
+            current,
+            report
+                .skipped
+                .get(crate_name)
+                .map_or("no available version matches", String::as_str)
+        );
+    }
+
+    if let Err(e) = resolver.restore() {
+        log::error!("Failed to restore original manifests: {}", e);
+    }
+    resolver.clean();
+
+    Ok(())
+}
+
+/// Run compat-range on a generated project depending only on `crate_name`, created in the temporary
+/// directory and removed afterwards.
+async fn do_fetch_resolve_command(
+    args: &Arguments,
+    crate_name: &str,
+    requirement: Option<&VersionReq>,
+    resolve_args: &ResolveArgs,
+) {
+    if let Err(e) = cargo_util_schemas::manifest::PackageName::new(crate_name.to_string()) {
+        log::error!("Invalid crate name '{}': {}", crate_name, e);
+        std::process::exit(EXIT_FAILURE);
+    }
+    if let Some(path) = &resolve_args.path {
+        warn!(
+            "Ignoring path {}, fetch-resolve generates its own project",
+            path
+        );
+    }
+
+    let requirement = requirement
+        .map(VersionReq::to_string)
+        .unwrap_or_else(|| "*".to_string());
+    let manifest = format!(
+        "[package]\nname = \"cargo-compat-probe\"\nversion = \"0.0.0\"\nedition = \"2021\"\npublish = false\n\n[dependencies]\n{} = {}\n",
+        crate_name,
+        toml::Value::String(requirement)
+    );
+    // A directory of its own per run, concurrent runs do not clobber each other's project
+    let project_dir = match TempDir::new(&format!("cargo-compat-fetch-resolve-{}", crate_name)) {
+        Ok(project_dir) => project_dir,
+        Err(e) => {
+            log::error!("Failed to create the project directory: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    let generated = std::fs::create_dir_all(project_dir.path().join("src"))
+        .and_then(|_| std::fs::write(project_dir.path().join("Cargo.toml"), manifest))
+        .and_then(|_| std::fs::write(project_dir.path().join("src").join("lib.rs"), ""));
+    if let Err(e) = generated {
+        log::error!(
+            "Failed to generate the project at {}: {}",
+            project_dir.path().display(),
+            e
+        );
+        drop(project_dir);
+        std::process::exit(EXIT_FAILURE);
+    }
+    info!(
+        "Generated a project depending on {} at {}",
+        crate_name,
+        project_dir.path().display()
+    );
+
+    let resolve_args = ResolveArgs {
+        path: Some(project_dir.path().to_string_lossy().to_string()),
+        include: vec![],
+        only: vec![],
+        ..resolve_args.clone()
+    };
+    let resolved = compat_range(args, crate_name, &resolve_args).await;
+
+    // Removed before exiting, which skips destructors
+    drop(project_dir);
+    if let Err(code) = resolved {
+        std::process::exit(code);
+    }
+}
+
+/// Directory created in the temporary directory, removed along with its contents when dropped.
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a directory whose name starts with `prefix` and is unique to this run.
+    fn new(prefix: &str) -> std::io::Result<Self> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let mut attempt = 0;
+        loop {
+            let path = std::env::temp_dir().join(format!(
+                "{}-{}-{}-{}",
+                prefix,
+                std::process::id(),
+                nanos,
+                attempt
+            ));
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 16 => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!(
+                "Failed to remove the temporary directory {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+async fn do_check_command(args: &Arguments, resolve_args: &ResolveArgs) {
+    let (mut resolver, _) = prepare_resolver(args, resolve_args, &resolve_args.only)
+        .await
+        .unwrap_or_else(|code| std::process::exit(code));
+
+    // Error paths drop the resolver before exiting so that the original manifests get restored
+    if let Err(e) = resolver.populate_default() {
+        log::error!("Failed to populate resolver: {}", e);
+        drop(resolver);
+        std::process::exit(exit_code(&e));
+    };
+
+    let checked = resolver.check_baseline();
+    if let Err(e) = resolver.restore() {
+        log::error!("Failed to restore original manifests: {}", e);
+    }
+    resolver.clean();
+
+    let validation_error = match checked {
+        Ok(validation_error) => validation_error,
+        Err(e) => {
+            log::error!("Failed to check the current configuration: {}", e);
+            drop(resolver);
+            std::process::exit(exit_code(&e));
+        }
+    };
+
+    if resolve_args.format == OutputFormat::Json {
+        let output = serde_json::json!({
+            "passed": validation_error.is_none(),
+            "versions": resolver.packages(),
+            "error": &validation_error,
+        });
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                log::error!("Failed to serialize check result: {}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    } else {
+        println!("Checked versions:");
+        for (package_name, version) in resolver.packages() {
+            println!("- {}: {}", package_name, version);
+        }
+
+        match &validation_error {
+            None if resolve_args.check_command.is_some() => {
+                println!("PASS: the check command succeeds with the current configuration")
+            }
+            None => println!(
+                "PASS: the current configuration {}{}",
+                if resolve_args.no_test && resolve_args.check {
+                    "type checks"
+                } else {
+                    "builds"
+                },
+                if resolve_args.no_test {
+                    ""
+                } else {
+                    " and passes its tests"
+                }
+            ),
+            Some(validation_error) => {
+                println!("FAIL: {}", validation_error.reason());
+                if !validation_error.features.is_empty() {
+                    println!("Features: {}", validation_error.features.join(", "));
+                }
+                if let Some(build_failure) = &validation_error.build_failure {
+                    println!("Exit code: {}", build_failure.cargo_error_code);
+                    println!("{}", build_failure.message.trim_end());
+                }
+            }
+        }
+    }
+
+    if validation_error.is_some() {
+        drop(resolver);
+        std::process::exit(EXIT_BASELINE_FAILED);
+    }
+}
+
+async fn do_why_command(
+    args: &Arguments,
+    crate_name: &str,
+    version: &Version,
+    resolve_args: &ResolveArgs,
+) {
+    let (mut resolver, _) = prepare_resolver(args, resolve_args, &[crate_name.to_string()])
+        .await
+        .unwrap_or_else(|code| std::process::exit(code));
+
+    // Error paths drop the resolver before exiting so that the original manifests get restored
+    if let Err(e) = resolver.populate_default() {
+        log::error!("Failed to populate resolver: {}", e);
+        drop(resolver);
+        std::process::exit(exit_code(&e));
+    };
+
+    let checked = resolver.check_version(crate_name, version);
+    if let Err(e) = resolver.restore() {
+        log::error!("Failed to restore original manifests: {}", e);
+    }
+    resolver.clean();
+
+    let validation_error = match checked {
+        Ok(validation_error) => validation_error,
+        Err(e) => {
+            log::error!("Failed to check {} {}: {}", crate_name, version, e);
+            drop(resolver);
+            std::process::exit(exit_code(&e));
+        }
+    };
+
+    if resolve_args.format == OutputFormat::Json {
+        let output = serde_json::json!({
+            "crate": crate_name,
+            "version": version,
+            "passed": validation_error.is_none(),
+            "error": &validation_error,
+        });
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                log::error!("Failed to serialize check result: {}", e);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    } else {
+        match &validation_error {
+            None => println!(
+                "PASS: {} {} is compatible with the current configuration",
+                crate_name, version
+            ),
+            Some(validation_error) => {
+                println!(
+                    "FAIL: {} {} is incompatible, {} (output above)",
+                    crate_name,
+                    version,
+                    validation_error.reason()
+                );
+                if !validation_error.features.is_empty() {
+                    println!("Features: {}", validation_error.features.join(", "));
+                }
+                if let Some(build_failure) = &validation_error.build_failure {
+                    println!("Exit code: {}", build_failure.cargo_error_code);
+                }
+            }
+        }
     }
-}
 
-async fn do_resolve_command(
-    args: &Arguments,
-    path: &Option<String>,
-    include: &[String],
-    cargo_path: String,
-    release: bool,
-    no_test: bool,
-    features: Vec<String>,
-) {
-    let path = path
-        .as_ref()
-        .map(std::path::PathBuf::from)
-        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    if validation_error.is_some() {
+        drop(resolver);
+        std::process::exit(EXIT_BASELINE_FAILED);
+    }
+}
 
-    let targets = read_cargo_from_path_with_includes(&path, include);
+fn read_json_report(path: &Path) -> ResolveReport {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        log::error!("Failed to read resolve report {}: {}", path.display(), e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        log::error!("Failed to parse resolve report {}: {}", path.display(), e);
+        std::process::exit(EXIT_FAILURE);
+    })
+}
 
-    // Read the cache
-    let cache_paths = find_cache_path(&args.cache_dir);
+fn do_diff_command(old: &Path, new: &Path) {
+    let changes = read_json_report(old).diff(&read_json_report(new));
 
-    // Provide a list of all dependencies that must be resolved
-    let mut all_dependencies = Vec::new();
-    for package in &targets {
-        for dep in &package.dependencies {
-            if dep.git {
-                warn!(
-                    "Git dependency {} in package {} is not supported and will be skipped",
-                    dep.crate_name, package.name
-                );
-                continue;
+    let mut sections: BTreeMap<usize, (&str, Vec<String>)> = BTreeMap::new();
+    for (name, change) in &changes {
+        let (order, title, description) = match change {
+            RequirementChange::Widened { old, new } => {
+                (0, "Widened", format!("{} -> {}", old, new))
+            }
+            RequirementChange::Narrowed { old, new } => {
+                (1, "Narrowed", format!("{} -> {}", old, new))
             }
+            RequirementChange::Changed { old, new } => {
+                (2, "Changed", format!("{} -> {}", old, new))
+            }
+            RequirementChange::Added(new) => (3, "Added", new.to_string()),
+            RequirementChange::Removed(old) => (4, "Removed", old.to_string()),
+            RequirementChange::Unchanged(req) => (5, "Unchanged", req.to_string()),
+        };
+        sections
+            .entry(order)
+            .or_insert((title, vec![]))
+            .1
+            .push(format!("- {}: {}", name, description));
+    }
 
-            all_dependencies.push(dep.crate_name.clone());
+    for (title, entries) in sections.values() {
+        println!("{}:", title);
+        for entry in entries {
+            println!("{}", entry);
         }
     }
+}
 
-    // Resolve all packages
-    let package_informations = resolve_packages(args, cache_paths, all_dependencies).await;
-    let build_opts = BuildOptions {
-        packages: Some(targets.iter().map(|p| p.name.clone()).collect()),
-        features: if features.is_empty() {
-            None
-        } else {
-            Some(features)
-        },
-        release,
-    };
+fn save_validation_cache(resolver: &resolver::Resolver, path: &Path) {
+    resolver
+        .validation_cache
+        .save_to_path(path)
+        .unwrap_or_else(|e| {
+            log::warn!(
+                "Failed to save validation cache to {}: {}",
+                path.display(),
+                e
+            );
+        });
+}
 
-    let mut resolver = resolver::Resolver::new(
-        targets,
-        path,
-        package_informations,
-        Box::new(validator::CargoRepoValidator::new(Some(cargo_path))),
-        build_opts,
-        if no_test {
-            None
-        } else {
-            Some(TestOptions { filters: vec![] })
-        },
-    );
+/// Registry given with `--registry`, crates.io by default. The error holds the exit status, the
+/// invalid URL being logged already.
+fn default_registry(args: &Arguments) -> Result<Registry, i32> {
+    match &args.registry {
+        None => Ok(Registry::CratesIo),
+        Some(url) => Registry::from_index_url(url).map_err(|e| {
+            log::error!("Invalid --registry: {}", e);
+            EXIT_FAILURE
+        }),
+    }
+}
 
-    if let Err(e) = resolver.populate_default() {
-        log::error!("Failed to populate resolver: {}", e);
-        std::process::exit(1);
-    };
+fn fetch_options(args: &Arguments) -> FetchOptions {
+    FetchOptions {
+        concurrency: args.fetch_concurrency,
+        offline: args.offline,
+        registry: default_registry(args).unwrap_or_else(|code| std::process::exit(code)),
+        retries: args.fetch_retries,
+        strict: false,
+        full: args.full_metadata,
+        negative_cache_age: Duration::minutes(args.negative_cache_age as i64),
+        user_agent: args.user_agent.clone(),
+        save_every: args.save_every,
+        cache_path: Some(find_cache_path(&args.cache_dir).crate_cache),
+        registry_token: args.registry_token.clone(),
+    }
+}
 
-    let versions = match resolver.resolve() {
-        Err(e) => {
-            log::error!("Failed to resolve packages: {}", e);
-            std::process::exit(1);
-        }
-        Ok(v) => v,
-    };
+/// Print the resolved requirements as TOML tables ready to be pasted in the manifests, see
+/// [`toml_blocks`].
+fn print_toml_block(report: &ResolveReport, root: &Path, targets: &[CargoPackage]) {
+    print!("{}", toml_blocks(report, root, targets));
+}
 
-    // Print the resolved versions
-    println!("Resolved package versions:");
-    for (package_name, version) in versions {
-        println!("- {}: {}", package_name, version);
+/// The resolved requirements as TOML tables, one block per manifest headed by a comment naming it
+/// (relative to the repository `root`). Each dependency is under the table declaring it, in the root
+/// manifest's `[workspace.dependencies]` for the inherited ones, and keeps its features,
+/// default-features, optional and registry settings.
+fn toml_blocks(report: &ResolveReport, root: &Path, targets: &[CargoPackage]) -> String {
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let root_manifest = root.join("Cargo.toml");
+    // Tables of each manifest, in order of appearance, with their entries by crate name
+    type Sections<'a> = Vec<(String, BTreeMap<&'a str, String>)>;
+    let mut manifests: Vec<(PathBuf, Sections)> = vec![];
+
+    for package in targets {
+        // The manifest path of a single package can be its directory
+        let package_manifest = if package.manifest_path.is_dir() {
+            package.manifest_path.join("Cargo.toml")
+        } else {
+            package.manifest_path.clone()
+        };
+        for (table, dependencies) in [
+            ("dependencies", &package.dependencies),
+            ("dev-dependencies", &package.dev_dependencies),
+            ("build-dependencies", &package.build_dependencies),
+        ] {
+            for dep in dependencies {
+                let Some(requirement) = report.requirements.get(&dep.crate_name) else {
+                    continue;
+                };
+                let header = match &dep.target {
+                    _ if dep.workspace => "workspace.dependencies".to_string(),
+                    Some(platform) => format!("target.{}.{}", quote(platform), table),
+                    None => table.to_string(),
+                };
+
+                // Inherited dependencies keep the settings of their member entries
+                let mut fields = vec![format!("version = {}", quote(&requirement.to_string()))];
+                if !dep.workspace {
+                    if !dep.features.is_empty() {
+                        fields.push(format!(
+                            "features = [{}]",
+                            dep.features
+                                .iter()
+                                .map(|feature| quote(feature))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                    if let Some(default_features) = dep.default_features {
+                        fields.push(format!("default-features = {}", default_features));
+                    }
+                    if dep.optional {
+                        fields.push("optional = true".to_string());
+                    }
+                    if let Some(registry) = &dep.registry {
+                        fields.push(format!("registry = {}", quote(registry)));
+                    }
+                }
+                let value = match fields.as_slice() {
+                    [_] => quote(&requirement.to_string()),
+                    _ => format!("{{ {} }}", fields.join(", ")),
+                };
+
+                let manifest = if dep.workspace {
+                    &root_manifest
+                } else {
+                    &package_manifest
+                };
+                let index = match manifests.iter().position(|(path, _)| path == manifest) {
+                    Some(index) => index,
+                    None => {
+                        manifests.push((manifest.clone(), vec![]));
+                        manifests.len() - 1
+                    }
+                };
+                let sections = &mut manifests[index].1;
+                let index = match sections.iter().position(|(name, _)| *name == header) {
+                    Some(index) => index,
+                    None => {
+                        sections.push((header, BTreeMap::new()));
+                        sections.len() - 1
+                    }
+                };
+                sections[index].1.insert(&dep.crate_name, value);
+            }
+        }
     }
 
-    // Overwrite cargo.toml with resolved versions if needed
-    if let Err(e) = resolver.write_cargo_toml_with_resolved_versions() {
-        log::error!("Failed to write resolved versions to Cargo.toml: {}", e);
-        std::process::exit(1);
+    // The root manifest first, the members in order
+    manifests.sort_by_key(|(manifest, _)| *manifest != root_manifest);
+    let blocks = manifests
+        .iter()
+        .map(|(manifest, sections)| {
+            let mut block = format!(
+                "# {}\n",
+                manifest.strip_prefix(root).unwrap_or(manifest).display()
+            );
+            for (index, (header, entries)) in sections.iter().enumerate() {
+                if index > 0 {
+                    block.push('\n');
+                }
+                block.push_str(&format!("[{}]\n", header));
+                for (name, value) in entries {
+                    block.push_str(&format!("{} = {}\n", name, value));
+                }
+            }
+            block
+        })
+        .collect::<Vec<_>>();
+    blocks.join("\n")
+}
+
+/// The JSON serialization of `report`, shared by `--format json` and `--report`.
+fn json_report(report: &ResolveReport) -> String {
+    serde_json::to_string_pretty(report).unwrap_or_else(|e| {
+        log::error!("Failed to serialize resolve report: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    })
+}
+
+fn print_json_report(report: &ResolveReport) {
+    println!("{}", json_report(report));
+}
+
+/// Write the JSON report to `path` for `--report`, a failure is logged without stopping the command.
+fn write_json_report(report: &ResolveReport, path: &Path) {
+    match std::fs::write(path, json_report(report) + "\n") {
+        Ok(()) => info!("Wrote the resolve report to {}", path.display()),
+        Err(e) => log::error!(
+            "Failed to write the resolve report to {}: {}",
+            path.display(),
+            e
+        ),
     }
-    resolver.clean();
 }
 
 async fn resolve_packages(
     args: &Arguments,
     cache_paths: CachePaths,
-    all_dependencies: Vec<String>,
-) -> BTreeMap<String, Crate> {
+    all_dependencies: BTreeMap<Registry, Vec<String>>,
+    strict: bool,
+) -> Result<BTreeMap<String, Crate>, i32> {
     // Load the cache
     let mut cache = CrateCache::load_from_path(&cache_paths.crate_cache).unwrap_or_else(|e| {
         warn!("Failed to load cache: {e}, starting with empty cache");
         CrateCache::default()
     });
 
-    // Retrieve packages, fetching missing ones
-    let packages_map = cache
-        .retrieve_packages_fetch(
-            &all_dependencies
-                .iter()
-                .map(|s| s.as_str())
-                .collect::<Vec<_>>(),
-            Duration::hours(args.cache_age as i64),
-        )
-        .await
-        .unwrap_or_else(|e| {
-            log::error!("Failed to retrieve packages: {}", e);
-            // Write back the cache before exiting
-            cache
-                .save_to_path(&cache_paths.crate_cache)
-                .unwrap_or_else(|e| {
-                    log::warn!(
-                        "Failed to save cache to {}: {}",
-                        cache_paths.crate_cache.display(),
-                        e
-                    );
-                });
-            std::process::exit(1);
-        });
+    // Retrieve packages from each registry, fetching missing ones
+    let mut packages_map = BTreeMap::new();
+    let default_registry = default_registry(args)?;
+    for (registry, dependencies) in all_dependencies {
+        // The token authenticates to the --registry index only, other registries never receive it
+        let registry_token = args
+            .registry_token
+            .clone()
+            .filter(|_| registry == default_registry);
+        let fetch_opts = FetchOptions {
+            registry,
+            strict,
+            registry_token,
+            ..fetch_options(args)
+        };
+
+        let packages = match cache
+            .retrieve_packages_fetch(
+                &dependencies.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                Duration::hours(args.cache_age as i64),
+                &fetch_opts,
+            )
+            .await
+        {
+            Ok(packages) => packages,
+            Err(e) => {
+                log::error!("Failed to retrieve packages: {}", e);
+                // Write back the cache before exiting
+                cache
+                    .save_to_path(&cache_paths.crate_cache)
+                    .unwrap_or_else(|e| {
+                        log::warn!(
+                            "Failed to save cache to {}: {}",
+                            cache_paths.crate_cache.display(),
+                            e
+                        );
+                    });
+                return Err(exit_code(&e));
+            }
+        };
+        packages_map.extend(packages);
+    }
 
     // Write back the cache
     cache
@@ -361,14 +2203,17 @@ async fn resolve_packages(
             );
         });
 
-    packages_map
+    Ok(packages_map)
 }
 
 async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
     let cache_age_limit = Duration::hours(args.cache_age as i64);
 
     match command {
-        CacheCommand::Clean { full } => {
+        CacheCommand::Clean {
+            full,
+            crate_pattern,
+        } => {
             let cache_paths = find_cache_path(&args.cache_dir);
             if !cache_paths.base_cache_dir.is_dir() {
                 info!(
@@ -378,7 +2223,33 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
                 return;
             }
 
-            if *full {
+            if let Some(crate_pattern) = crate_pattern {
+                let pattern = glob::Pattern::new(crate_pattern).unwrap_or_else(|e| {
+                    log::error!("Invalid crate pattern '{}': {}", crate_pattern, e);
+                    std::process::exit(EXIT_FAILURE);
+                });
+                let mut cache = CrateCache::load_from_path(&cache_paths.crate_cache)
+                    .unwrap_or_else(|e| {
+                        log::error!(
+                            "Failed to load cache from {}: {}",
+                            cache_paths.crate_cache.display(),
+                            e
+                        );
+                        std::process::exit(EXIT_FAILURE);
+                    });
+
+                let removed_count = cache.remove_matching(&pattern);
+                info!(
+                    "Removed {} cache entries matching '{}' ({} total entries remaining)",
+                    removed_count,
+                    crate_pattern,
+                    cache.entries.len()
+                );
+                if let Err(e) = cache.save_to_path(&cache_paths.crate_cache) {
+                    log::error!("Failed to save cleaned cache: {}", e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            } else if *full {
                 info!(
                     "Removing entire cache directory: {}",
                     cache_paths.base_cache_dir.display()
@@ -411,9 +2282,19 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
                             cache.entries.len()
                         );
 
+                        let initial_failures = cache.failures.len();
+                        cache.filter_expired_failures(Duration::minutes(
+                            args.negative_cache_age as i64,
+                        ));
+                        debug!(
+                            "Removed {} expired fetch failures ({} remaining)",
+                            initial_failures - cache.failures.len(),
+                            cache.failures.len()
+                        );
+
                         if let Err(e) = cache.save_to_path(&cache_paths.crate_cache) {
                             log::error!("Failed to save cleaned cache: {}", e);
-                            std::process::exit(1);
+                            std::process::exit(EXIT_FAILURE);
                         }
                     }
                     Err(e) => warn!(
@@ -422,7 +2303,85 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
                         e
                     ),
                 }
+
+                match ValidationCache::load_from_path(&cache_paths.validation_cache) {
+                    Ok(mut cache) => {
+                        let initial_count = cache.size();
+
+                        cache.filter_expired_entries(cache_age_limit);
+
+                        info!(
+                            "Removed {} expired validation entries ({} total entries remaining)",
+                            initial_count - cache.size(),
+                            cache.size()
+                        );
+
+                        if let Err(e) = cache.save_to_path(&cache_paths.validation_cache) {
+                            log::error!("Failed to save cleaned validation cache: {}", e);
+                            std::process::exit(EXIT_FAILURE);
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Failed to load validation cache from {}: {}, nothing to clean",
+                        cache_paths.validation_cache.display(),
+                        e
+                    ),
+                }
+            }
+        }
+
+        CacheCommand::Export { file } => {
+            let cache_paths = find_cache_path(&args.cache_dir);
+            let cache = CrateCache::load_from_path(&cache_paths.crate_cache).unwrap_or_else(|e| {
+                log::error!(
+                    "Failed to load cache from {}: {}",
+                    cache_paths.crate_cache.display(),
+                    e
+                );
+                std::process::exit(EXIT_FAILURE);
+            });
+
+            if let Err(e) = cache.save_to_path(file) {
+                log::error!("Failed to export cache to {}: {}", file.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            }
+            info!(
+                "Exported {} cache entries to {}",
+                cache.entries.len(),
+                file.display()
+            );
+        }
+
+        CacheCommand::Import { file } => {
+            if !file.is_file() {
+                log::error!("Cache file {} does not exist", file.display());
+                std::process::exit(EXIT_FAILURE);
+            }
+
+            let imported = CrateCache::load_from_path(file).unwrap_or_else(|e| {
+                log::error!("Invalid cache file {}: {}", file.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            });
+
+            let cache_paths = find_cache_path(&args.cache_dir);
+            let mut cache =
+                CrateCache::load_from_path(&cache_paths.crate_cache).unwrap_or_else(|e| {
+                    warn!("Failed to load cache: {e}, starting with empty cache");
+                    CrateCache::default()
+                });
+
+            let (added, updated) = cache.merge(imported);
+            if let Err(e) = cache.save_to_path(&cache_paths.crate_cache) {
+                log::error!("Failed to save merged cache: {}", e);
+                std::process::exit(EXIT_FAILURE);
             }
+            info!(
+                "Imported cache from {}: {} entries added, {} updated ({} total entries)",
+                file.display(),
+                added,
+                updated,
+                cache.entries.len()
+            );
         }
 
         CacheCommand::Info => {
@@ -430,6 +2389,10 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
             let cache_paths = find_cache_path(&args.cache_dir);
             println!("Cache directory: {}", cache_paths.base_cache_dir.display());
             println!("Crate cache file: {}", cache_paths.crate_cache.display());
+            println!(
+                "Validation cache file: {}",
+                cache_paths.validation_cache.display()
+            );
 
             // Load the cache
             let cache = match CrateCache::load_from_path(&cache_paths.crate_cache) {
@@ -440,19 +2403,73 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
                         cache_paths.crate_cache.display(),
                         e
                     );
-                    std::process::exit(1);
+                    std::process::exit(EXIT_FAILURE);
                 }
             };
 
+            match ValidationCache::load_from_path(&cache_paths.validation_cache) {
+                Ok(validation_cache) => {
+                    println!("Total cached validations: {}", validation_cache.size())
+                }
+                Err(e) => warn!(
+                    "Failed to load validation cache from {}: {}",
+                    cache_paths.validation_cache.display(),
+                    e
+                ),
+            }
+
             println!("Total cached crates: {}", cache.entries.len());
             for (crate_name, entry) in &cache.entries {
                 let age = Utc::now() - entry.last_fetched_at;
                 println!(
-                    "- {}: last fetched at {} (age: {} hours)",
+                    "- {}: last fetched at {} (age: {} hours){}",
                     crate_name,
                     local_datetime(entry.last_fetched_at),
-                    age.num_hours()
+                    age.num_hours(),
+                    if entry.full { " [full]" } else { "" }
+                );
+            }
+
+            if !cache.failures.is_empty() {
+                println!("Cached fetch failures: {}", cache.failures.len());
+                for (crate_name, failed_at) in &cache.failures {
+                    let age = Utc::now() - *failed_at;
+                    println!(
+                        "- {}: not found at {} (age: {} minutes)",
+                        crate_name,
+                        local_datetime(*failed_at),
+                        age.num_minutes()
+                    );
+                }
+            }
+        }
+        CacheCommand::List { crate_name } => {
+            let cache_paths = find_cache_path(&args.cache_dir);
+            let cache = CrateCache::load_from_path(&cache_paths.crate_cache).unwrap_or_else(|e| {
+                log::error!(
+                    "Failed to load cache from {}: {}",
+                    cache_paths.crate_cache.display(),
+                    e
                 );
+                std::process::exit(EXIT_FAILURE);
+            });
+
+            for entry in cache.entries.values() {
+                if crate_name
+                    .as_ref()
+                    .is_some_and(|name| *name != entry.krate.name)
+                {
+                    continue;
+                }
+
+                let mut versions = entry.krate.versions.iter().collect::<Vec<_>>();
+                versions.sort_by(|a, b| a.version.cmp(&b.version));
+                for version in versions {
+                    println!(
+                        "{} {} {}",
+                        entry.krate.name, version.version, version.yanked
+                    );
+                }
             }
         }
         CacheCommand::Fetch {
@@ -478,11 +2495,18 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
             };
 
             let information = cache
-                .retrieve_packages_fetch(&[crate_name.as_ref()], age_limit)
+                .retrieve_packages_fetch(
+                    &[crate_name.as_ref()],
+                    age_limit,
+                    &FetchOptions {
+                        strict: true,
+                        ..fetch_options(args)
+                    },
+                )
                 .await
                 .unwrap_or_else(|e| {
                     log::error!("Failed to fetch crate {}: {}", crate_name, e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code(&e));
                 })
                 .remove(crate_name)
                 .unwrap();
@@ -517,6 +2541,72 @@ async fn do_cache_command(command: &CacheCommand, args: &Arguments) {
                 }
             }
         }
+        CacheCommand::FetchAll { from, force } => {
+            let cache_paths = find_cache_path(&args.cache_dir);
+            let content = std::fs::read_to_string(from).unwrap_or_else(|e| {
+                log::error!("Failed to read crate list {}: {}", from.display(), e);
+                std::process::exit(EXIT_FAILURE);
+            });
+            let crate_names = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+
+            // Load the cache
+            let mut cache =
+                CrateCache::load_from_path(&cache_paths.crate_cache).unwrap_or_else(|e| {
+                    warn!("Failed to load cache: {e}, starting with empty cache");
+                    CrateCache::default()
+                });
+
+            let age_limit = if *force {
+                Duration::hours(0)
+            } else {
+                cache_age_limit
+            };
+
+            // Missing crates are left out instead of failing the whole batch
+            let information = cache
+                .retrieve_packages_fetch(&crate_names, age_limit, &fetch_options(args))
+                .await;
+
+            // Saved even when the fetch failed, to keep the crates downloaded before the failure
+            cache
+                .save_to_path(&cache_paths.crate_cache)
+                .unwrap_or_else(|e| {
+                    log::warn!(
+                        "Failed to save cache to {}: {}",
+                        cache_paths.crate_cache.display(),
+                        e
+                    );
+                });
+            let information = information.unwrap_or_else(|e| {
+                log::error!("Failed to fetch crates: {}", e);
+                std::process::exit(exit_code(&e));
+            });
+
+            let mut missing = 0;
+            for name in &crate_names {
+                match information.get(*name) {
+                    Some(krate) => println!("- {}: OK ({} versions)", name, krate.versions.len()),
+                    None => {
+                        missing += 1;
+                        println!("- {}: FAILED (not found)", name);
+                    }
+                }
+            }
+            println!(
+                "Fetched {}/{} crates",
+                crate_names.len() - missing,
+                crate_names.len()
+            );
+            if missing > 0 {
+                std::process::exit(EXIT_REGISTRY_ERROR);
+            }
+        }
     }
 }
 
@@ -541,8 +2631,19 @@ fn setup_logger(args: &Arguments) {
 
     let with_location = matches!(level, log::LevelFilter::Debug | log::LevelFilter::Trace);
 
-    let base = fern::Dispatch::new()
-        .format(move |out, message, record| {
+    // Keep stdout clean for machine-readable output
+    let stdout_reserved = matches!(
+        &args.command,
+        Command::Resolve(resolve_args)
+            | Command::CompatRange { resolve_args, .. }
+            | Command::FetchResolve { resolve_args, .. }
+            | Command::Check(resolve_args)
+            | Command::Why { resolve_args, .. }
+            if resolve_args.format != OutputFormat::Human
+    );
+
+    let base = match args.log_format {
+        LogFormat::Human => fern::Dispatch::new().format(move |out, message, record| {
             let ts = chrono::Local::now().format("%d/%m/%Y %H:%M:%S");
             let lvl = colors.color(record.level());
 
@@ -559,21 +2660,42 @@ fn setup_logger(args: &Arguments) {
             };
 
             out.finish(format_args!("[{}] {}{} -- {}", ts, loc, lvl, message))
-        })
-        .level(level);
+        }),
+        // One JSON object per record, for log pipelines
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            let mut entry = serde_json::Map::new();
+            entry.insert("timestamp".into(), Utc::now().to_rfc3339().into());
+            entry.insert("level".into(), record.level().as_str().into());
+            entry.insert("target".into(), record.target().into());
+            entry.insert("message".into(), message.to_string().into());
+            if let Some(file) = record.file() {
+                entry.insert("file".into(), file.into());
+            }
+            if let Some(line) = record.line() {
+                entry.insert("line".into(), line.into());
+            }
+
+            out.finish(format_args!("{}", serde_json::Value::Object(entry)))
+        }),
+    }
+    .level(level);
 
     base
         // stdout: everything below Error
         .chain(
             fern::Dispatch::new()
-                .filter(|meta| meta.level() < log::Level::Error)
-                .chain(std::io::stdout()),
+                .filter(move |meta| !stdout_reserved && meta.level() < log::Level::Error)
+                .chain(fern::Output::call(|record| {
+                    progress::suspend(|| println!("{}", record.args()))
+                })),
         )
         // stderr: Error and above
         .chain(
             fern::Dispatch::new()
-                .filter(|meta| meta.level() >= log::Level::Error)
-                .chain(std::io::stderr()),
+                .filter(move |meta| stdout_reserved || meta.level() >= log::Level::Error)
+                .chain(fern::Output::call(|record| {
+                    progress::suspend(|| eprintln!("{}", record.args()))
+                })),
         )
         .apply()
         .unwrap();
@@ -582,6 +2704,7 @@ fn setup_logger(args: &Arguments) {
 struct CachePaths {
     base_cache_dir: PathBuf,
     crate_cache: PathBuf,
+    validation_cache: PathBuf,
 }
 
 fn find_cache_path(cache_dir: &Option<String>) -> CachePaths {
@@ -605,74 +2728,169 @@ fn find_cache_path(cache_dir: &Option<String>) -> CachePaths {
     CachePaths {
         base_cache_dir: base_cache_dir.clone(),
         crate_cache: base_cache_dir.join("crate_cache.cbor"),
+        validation_cache: base_cache_dir.join("validation_cache.cbor"),
     }
 }
 
-fn read_cargo_from_path(path: &Path) -> Cargo {
-    match Cargo::from_path(path) {
-        Ok(cargo) => cargo,
-        Err(e) => {
-            log::error!("Error reading Cargo manifest: {}", e);
-            std::process::exit(1);
+/// Read the packages to resolve at `path`, the error holding the exit status matching the failure
+/// (already logged) when they cannot be read.
+fn read_cargo_from_path_with_includes(
+    path: &Path,
+    includes: &[String],
+) -> Result<Vec<CargoPackage>, i32> {
+    cargo_compat::cargo::read_targets(path, includes).map_err(|e| {
+        log::error!("{}", e);
+        match e {
+            error::Error::MissingIncludePatterns => EXIT_FAILURE,
+            error::Error::NoMatchingPackages { .. } => EXIT_NO_MATCH,
+            _ => EXIT_MANIFEST_ERROR,
         }
-    }
+    })
 }
 
-fn read_cargo_from_path_with_includes(path: &Path, includes: &[String]) -> Vec<CargoPackage> {
-    let cargo = read_cargo_from_path(path);
+pub fn local_datetime(dt: DateTime<Utc>) -> String {
+    dt.with_timezone(&chrono::Local)
+        .format("%d/%m/%Y %H:%M:%S")
+        .to_string()
+    // dt.with_timezone(&chrono::Local)
+}
 
-    // Match include patterns when using libraries
-    match cargo {
-        Cargo::Single(cargo_package) => {
-            if !includes.is_empty() {
-                warn!("Include patterns are ignored when processing a single package");
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            vec![cargo_package]
+    fn resolve_args(args: &[&str]) -> Result<ResolveArgs, clap::Error> {
+        let args = Arguments::try_parse_from(["cargo-compat", "resolve"].iter().chain(args))?;
+        match args.command {
+            Command::Resolve(resolve_args) => Ok(resolve_args),
+            _ => unreachable!(),
         }
-        Cargo::Workspace(cargo_packages) => {
-            if includes.is_empty() {
-                log::error!(
-                    "No include patterns specified for workspace. Workspace processing requires at least one --include pattern."
-                );
-                std::process::exit(1);
-            }
+    }
 
-            let include_patterns = includes
-                .iter()
-                .map(|p| glob::Pattern::new(p).unwrap())
-                .collect::<Vec<_>>();
+    #[test]
+    fn check_command_is_split_with_shell_quoting() {
+        let args = resolve_args(&["--check-command", "./run.sh 'two words' --flag"]).unwrap();
+        assert_eq!(
+            split_check_command(args.check_command.as_deref().unwrap()).unwrap(),
+            vec!["./run.sh", "two words", "--flag"]
+        );
+    }
 
-            let targets = cargo_packages
-                .iter()
-                .filter(|pkg| {
-                    include_patterns
-                        .iter()
-                        .any(|pat| pat.matches(pkg.name.as_ref()))
-                })
-                .cloned()
-                .collect::<Vec<_>>();
+    #[test]
+    fn empty_or_unbalanced_check_command_is_rejected() {
+        assert!(resolve_args(&["--check-command", ""]).is_err());
+        assert!(resolve_args(&["--check-command", "  "]).is_err());
+        assert!(resolve_args(&["--check-command", "./run.sh 'open"]).is_err());
+    }
 
-            if targets.is_empty() {
-                log::error!(
-                    "No packages in the workspace matched the provided include patterns: {:?}. Available packages: {:?}",
-                    includes,
-                    cargo_packages
-                        .iter()
-                        .map(|p| p.name.clone())
-                        .collect::<Vec<_>>()
-                );
-                std::process::exit(1);
-            }
+    #[test]
+    fn toml_block_has_a_block_per_manifest() {
+        let dir =
+            std::env::temp_dir().join(format!("cargo-compat-toml-block-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nfoo = \"1\"\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo.workspace = true\nbar = { version = \"1\", features = [\"x\"] }\n",
+            ),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dev-dependencies]\nbar = \"1\"\n",
+            ),
+        ] {
+            std::fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(path), content).unwrap();
+        }
+        let cargo = cargo_compat::cargo::Cargo::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let cargo_compat::cargo::Cargo::Workspace(mut members) = cargo.unwrap() else {
+            panic!("not a workspace");
+        };
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let report = ResolveReport {
+            requirements: BTreeMap::from([
+                ("foo".to_string(), VersionReq::parse("^1.2").unwrap()),
+                ("bar".to_string(), VersionReq::parse("^1.4").unwrap()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            toml_blocks(&report, &dir, &members),
+            "# Cargo.toml\n[workspace.dependencies]\nfoo = \"^1.2\"\n\n\
+             # a/Cargo.toml\n[dependencies]\nbar = { version = \"^1.4\", features = [\"x\"] }\n\n\
+             # b/Cargo.toml\n[dev-dependencies]\nbar = \"^1.4\"\n"
+        );
+    }
+
+    #[test]
+    fn doc_requires_all_targets() {
+        assert!(resolve_args(&["--doc"]).is_err());
+        let args = resolve_args(&["--all-targets", "--doc"]).unwrap();
+        assert!(args.all_targets && args.doc);
+    }
 
-            targets
+    #[test]
+    fn only_crates_must_be_normal_dependencies() {
+        let dir = std::env::temp_dir().join(format!("cargo-compat-only-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n\n[dependencies]\nfoo = \"1\"\n\n[dev-dependencies]\nbar = \"1\"\n\n[build-dependencies]\nbaz = \"1\"\n",
+        )
+        .unwrap();
+        let targets = cargo_compat::cargo::read_targets(&dir, &[]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let targets = targets.unwrap();
+        let check = |name: &str| check_only_crates(&[name.to_string()], &targets);
+
+        assert!(check("foo").is_ok());
+        for name in ["bar", "baz"] {
+            assert!(
+                check(name)
+                    .unwrap_err()
+                    .contains("only a dev or build dependency"),
+                "{name}"
+            );
         }
+        assert!(
+            check("qux")
+                .unwrap_err()
+                .contains("is not a dependency of the selected packages")
+        );
     }
-}
 
-pub fn local_datetime(dt: DateTime<Utc>) -> String {
-    dt.with_timezone(&chrono::Local)
-        .format("%d/%m/%Y %H:%M:%S")
-        .to_string()
-    // dt.with_timezone(&chrono::Local)
+    #[test]
+    fn edit_backend_defaults_to_toml_edit() {
+        let backend = |args: &[&str]| EditBackend::from(resolve_args(args).unwrap().edit_backend);
+        assert_eq!(backend(&[]), EditBackend::TomlEdit);
+        assert_eq!(
+            backend(&["--edit-backend", "in-place"]),
+            EditBackend::TomlEdit
+        );
+        assert_eq!(backend(&["--edit-backend", "cargo"]), EditBackend::Cargo);
+    }
+
+    #[test]
+    fn manifest_in_a_nested_directory_is_run_from_its_directory() {
+        let dir = std::env::temp_dir().join(format!("cargo-compat-nested-{}", std::process::id()));
+        let manifest = dir.join("crates/demo/Cargo.toml");
+        std::fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        std::fs::write(
+            &manifest,
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let from_manifest = working_dir(&manifest);
+        let from_directory = working_dir(manifest.parent().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(from_manifest, dir.join("crates/demo"));
+        assert_eq!(from_directory, dir.join("crates/demo"));
+        assert_eq!(working_dir(Path::new("Cargo.toml")), PathBuf::from("."));
+    }
 }
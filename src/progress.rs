@@ -0,0 +1,226 @@
+//! Progress bar of the resolution drawn on stderr, see [`ProgressBar`].
+//!
+//! The bar is drawn by hand with ANSI escapes rather than through `indicatif`, which is not among
+//! the dependencies: it only needs to redraw a single line, and [`suspend`] plays the part of
+//! `indicatif`'s `ProgressBar::suspend` for the log lines.
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Width of the bar itself, in characters
+const BAR_WIDTH: usize = 30;
+
+/// State of the progress bar currently drawn, if any.
+static ACTIVE: Mutex<Option<State>> = Mutex::new(None);
+
+struct State {
+    total: usize,
+    finished: usize,
+    /// Crates being searched, in the order their search started
+    current: Vec<String>,
+    started_at: Instant,
+}
+
+impl State {
+    fn render(&self) -> String {
+        let filled = (self.finished * BAR_WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(BAR_WIDTH);
+        let eta = match self.finished {
+            0 => "--".to_string(),
+            finished => format_duration(
+                self.started_at.elapsed() / finished as u32 * (self.total - finished) as u32,
+            ),
+        };
+
+        let current = if self.current.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", self.current.join(", "))
+        };
+
+        format!(
+            "[{}{}] resolving crate {}/{}{}  ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            (self.finished + 1).min(self.total),
+            self.total,
+            current,
+            eta
+        )
+    }
+
+    fn draw(&self, out: &mut impl Write) {
+        let _ = write!(out, "\r\x1B[2K{}", self.render()).and_then(|_| out.flush());
+    }
+}
+
+/// Clear the line of the bar on `out`.
+fn clear(out: &mut impl Write) {
+    let _ = write!(out, "\r\x1B[2K").and_then(|_| out.flush());
+}
+
+/// Human-readable duration, e.g. `1h05m`, `3m12s` or `42s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Progress of the resolution over its crates, with the crates being searched and the estimated
+/// time left, drawn on the last line of stderr until dropped.
+///
+/// There is a single bar at a time: while it is drawn, output written through [`suspend`] (the
+/// log lines) clears it first and draws it again below.
+pub struct ProgressBar {
+    _private: (),
+}
+
+impl ProgressBar {
+    pub fn new(total: usize) -> Self {
+        let state = State {
+            total,
+            finished: 0,
+            current: vec![],
+            started_at: Instant::now(),
+        };
+        state.draw(&mut std::io::stderr().lock());
+        *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = Some(state);
+        Self { _private: () }
+    }
+
+    /// The search of `crate_name` started.
+    pub fn start(&self, crate_name: &str) {
+        update(|state| state.current.push(crate_name.to_string()));
+    }
+
+    /// The search of `crate_name` finished, successfully or not.
+    pub fn finish(&self, crate_name: &str) {
+        update(|state| {
+            state.current.retain(|name| name != crate_name);
+            state.finished += 1;
+        });
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if ACTIVE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .is_some()
+        {
+            clear(&mut std::io::stderr().lock());
+        }
+    }
+}
+
+fn update(f: impl FnOnce(&mut State)) {
+    if let Some(state) = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        f(state);
+        state.draw(&mut std::io::stderr().lock());
+    }
+}
+
+/// Run `f`, writing to the terminal, with the progress bar cleared and drawn again afterwards.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    suspend_on(&mut std::io::stderr().lock(), |_| f())
+}
+
+/// Run `f` writing to `out`, the terminal the bar is drawn on, with the bar cleared first and drawn
+/// again below its output. The bar stays locked meanwhile, so that no update is drawn in between.
+fn suspend_on<W: Write, R>(out: &mut W, f: impl FnOnce(&mut W) -> R) -> R {
+    let active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    match active.as_ref() {
+        Some(state) => {
+            clear(out);
+            let result = f(out);
+            state.draw(out);
+            result
+        }
+        None => f(out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_shows_the_crates_searched_and_the_time_left() {
+        let mut state = State {
+            total: 4,
+            finished: 0,
+            current: vec!["serde".to_string()],
+            started_at: Instant::now(),
+        };
+        assert_eq!(
+            state.render(),
+            format!("[{}] resolving crate 1/4: serde  ETA --", "-".repeat(30))
+        );
+
+        state.finished = 2;
+        state.current = vec!["tokio".to_string(), "log".to_string()];
+        state.started_at = Instant::now() - Duration::from_secs(90);
+        assert_eq!(
+            state.render(),
+            format!(
+                "[{}{}] resolving crate 3/4: tokio, log  ETA 1m30s",
+                "#".repeat(15),
+                "-".repeat(15)
+            )
+        );
+    }
+
+    /// Lines shown by a terminal given `output`, where `\r\x1B[2K` clears the current line.
+    fn screen(output: &[u8]) -> Vec<String> {
+        let mut lines = vec![String::new()];
+        for part in String::from_utf8_lossy(output).split_inclusive('\n') {
+            for (index, segment) in part.split("\r\x1B[2K").enumerate() {
+                let line = lines.last_mut().unwrap();
+                if index > 0 {
+                    line.clear();
+                }
+                line.push_str(segment.trim_end_matches('\n'));
+            }
+            if part.ends_with('\n') {
+                lines.push(String::new());
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn suspended_output_is_written_above_the_bar() {
+        let state = State {
+            total: 2,
+            finished: 1,
+            current: vec!["log".to_string()],
+            started_at: Instant::now(),
+        };
+        let bar = state.render();
+        let mut output = vec![];
+        state.draw(&mut output);
+        *ACTIVE.lock().unwrap() = Some(state);
+
+        for line in ["foo@1.0.0: OK", "foo@1.1.0: FAIL"] {
+            suspend_on(&mut output, |out| writeln!(out, "{line}").unwrap());
+        }
+        *ACTIVE.lock().unwrap() = None;
+
+        assert_eq!(screen(&output), ["foo@1.0.0: OK", "foo@1.1.0: FAIL", &bar]);
+    }
+
+    #[test]
+    fn durations_are_rounded_to_two_units() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m12s");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h05m");
+    }
+}
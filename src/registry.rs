@@ -0,0 +1,392 @@
+//! Registry abstraction used to fetch crate metadata from crates.io or from alternative (sparse) registries.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::{debug, info, warn};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+
+use crate::crates::{Crate, CrateVersion, Dependency, Download, FetchOptions};
+
+/// Index URL of crates.io, as it may appear in cargo configuration files.
+const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+const CRATES_IO_SPARSE_INDEX: &str = "sparse+https://index.crates.io/";
+
+/// Source of crate metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Registry {
+    /// The crates.io web API
+    #[default]
+    CratesIo,
+    /// A registry served through the sparse index protocol, `index_url` excludes the `sparse+` prefix
+    Sparse { index_url: String },
+}
+
+impl Registry {
+    /// Parse an index URL as written in `.cargo/config.toml` or passed to `--registry`.
+    ///
+    /// Only the sparse protocol (`sparse+https://...`) is supported for registries other than crates.io.
+    pub fn from_index_url(url: &str) -> Result<Self, crate::error::Error> {
+        let trimmed = url.trim_end_matches('/');
+        if url == CRATES_IO_SPARSE_INDEX
+            || trimmed == CRATES_IO_INDEX
+            || trimmed == CRATES_IO_SPARSE_INDEX.trim_end_matches('/')
+            || url == "crates-io"
+        {
+            return Ok(Registry::CratesIo);
+        }
+
+        match url.strip_prefix("sparse+") {
+            Some(index_url) => Ok(Registry::Sparse {
+                index_url: format!("{}/", index_url.trim_end_matches('/')),
+            }),
+            None => Err(crate::error::Error::Other(
+                format!(
+                    "Unsupported registry index '{}': only sparse indexes (sparse+https://...) are supported",
+                    url
+                )
+                .into(),
+            )),
+        }
+    }
+
+    /// Key under which crate metadata from this registry is stored in the cache.
+    ///
+    /// crates.io entries keep the bare crate name so existing caches stay valid.
+    pub fn cache_key(&self, crate_name: &str) -> String {
+        match self {
+            Registry::CratesIo => crate_name.to_string(),
+            Registry::Sparse { index_url } => format!("{}{}", index_url, crate_name),
+        }
+    }
+
+    /// Look up the index URL of a named registry in the cargo configuration, the same way cargo does:
+    /// `CARGO_REGISTRIES_<NAME>_INDEX`, then `.cargo/config.toml` from `start_dir` upward, then `$CARGO_HOME`.
+    pub fn from_cargo_config(start_dir: &Path, name: &str) -> Result<Self, crate::error::Error> {
+        let env_name = format!(
+            "CARGO_REGISTRIES_{}_INDEX",
+            name.to_uppercase().replace('-', "_")
+        );
+        if let Ok(index) = std::env::var(&env_name) {
+            debug!(
+                "Registry '{}' index read from {}: {}",
+                name, env_name, index
+            );
+            return Self::from_index_url(&index);
+        }
+
+        for config_path in cargo_config_paths(start_dir) {
+            let Ok(content) = std::fs::read_to_string(&config_path) else {
+                continue;
+            };
+
+            let config: toml::Table = toml::from_str(&content).map_err(|e| {
+                crate::error::Error::Other(
+                    format!(
+                        "Failed to parse cargo config at {}: {}",
+                        config_path.display(),
+                        e
+                    )
+                    .into(),
+                )
+            })?;
+
+            if let Some(index) = config
+                .get("registries")
+                .and_then(|r| r.get(name))
+                .and_then(|r| r.get("index"))
+                .and_then(|i| i.as_str())
+            {
+                debug!(
+                    "Registry '{}' index read from {}: {}",
+                    name,
+                    config_path.display(),
+                    index
+                );
+                return Self::from_index_url(index);
+            }
+        }
+
+        Err(crate::error::Error::Other(
+            format!(
+                "Registry '{}' is not defined in any cargo configuration ([registries.{}] index = \"...\")",
+                name, name
+            )
+            .into(),
+        ))
+    }
+}
+
+impl std::fmt::Display for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Registry::CratesIo => write!(f, "crates.io"),
+            Registry::Sparse { index_url } => write!(f, "sparse+{}", index_url),
+        }
+    }
+}
+
+/// Candidate cargo configuration files, from the most to the least specific.
+fn cargo_config_paths(start_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    let start_dir = start_dir
+        .canonicalize()
+        .unwrap_or_else(|_| start_dir.to_path_buf());
+
+    for dir in start_dir.ancestors() {
+        paths.push(dir.join(".cargo").join("config.toml"));
+        paths.push(dir.join(".cargo").join("config"));
+    }
+
+    let cargo_home = std::env::var("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+    if let Ok(cargo_home) = cargo_home {
+        paths.push(cargo_home.join("config.toml"));
+        paths.push(cargo_home.join("config"));
+    }
+
+    paths
+}
+
+/// Path of a crate file inside a sparse index, following cargo's name-length prefix layout.
+fn sparse_index_path(crate_name: &str) -> String {
+    let name = crate_name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/// A single line of a sparse index crate file.
+#[derive(Deserialize)]
+struct IndexVersion {
+    name: String,
+    vers: String,
+    #[serde(default)]
+    deps: Vec<IndexDependency>,
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+    rust_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IndexDependency {
+    name: String,
+    req: String,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default)]
+    default_features: Option<bool>,
+    package: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
+}
+
+impl TryFrom<IndexVersion> for CrateVersion {
+    type Error = crate::error::Error;
+
+    fn try_from(value: IndexVersion) -> Result<Self, Self::Error> {
+        let dependencies = value
+            .deps
+            .into_iter()
+            .map(|d| {
+                Ok(Dependency {
+                    crate_name: d.package.unwrap_or(d.name),
+                    required_version: VersionReq::parse(&d.req)
+                        .map_err(crate::error::Error::InvalidVersionSyntax)?,
+                    features: d.features,
+                    git: false,
+                    git_source: None,
+                    registry: None,
+                    default_features: d.default_features,
+                    optional: d.optional,
+                    workspace: false,
+                    patched: false,
+                    target: d.target,
+                })
+            })
+            .collect::<Result<_, crate::error::Error>>()?;
+
+        // Sparse indexes do not record publication dates
+        Ok(Self {
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            updated_at: DateTime::<Utc>::UNIX_EPOCH,
+            yanked: value.yanked,
+            version: Version::parse(&value.vers)
+                .map_err(crate::error::Error::InvalidVersionSyntax)?,
+            checksum: value.cksum,
+            dependencies: Some(dependencies),
+            rust_version: value.rust_version,
+        })
+    }
+}
+
+/// Download the index file of `crate_name`, `None` when the crate is not in the index. When `etag`
+/// is given, the file is only downloaded again if it changed since the response carrying it.
+/// Network failures, 429 and 5xx responses are retried up to `retries` times with exponential
+/// backoff (1s, 2s, 4s, ...).
+async fn download_sparse_crate(
+    client: &reqwest::Client,
+    index_url: &str,
+    crate_name: &str,
+    etag: Option<&str>,
+    retries: u32,
+) -> Result<Option<Download>, crate::error::Error> {
+    let url = format!("{}{}", index_url, sparse_index_path(crate_name));
+    let registry_error = |error: String| crate::error::Error::RegistryError {
+        url: url.clone(),
+        error,
+    };
+
+    let mut attempt = 0;
+    let response = loop {
+        let mut request = client.get(&url);
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let error = match request.send().await {
+            Ok(response)
+                if response.status().is_server_error()
+                    || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+            {
+                format!("HTTP status {}", response.status())
+            }
+            Ok(response) => break response,
+            Err(e) => e.to_string(),
+        };
+        if attempt >= retries {
+            return Err(registry_error(error));
+        }
+
+        let backoff = std::time::Duration::from_secs(1 << attempt.min(6));
+        attempt += 1;
+        warn!(
+            "Request for crate {} failed: {}. Retrying in {}s ({}/{})",
+            crate_name,
+            error,
+            backoff.as_secs(),
+            attempt,
+            retries
+        );
+        tokio::time::sleep(backoff).await;
+    };
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Some(Download::NotModified(crate_name.to_string())));
+    }
+    if !response.status().is_success() {
+        return Err(registry_error(format!("HTTP status {}", response.status())));
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let content = response
+        .text()
+        .await
+        .map_err(|e| registry_error(e.to_string()))?;
+
+    let mut name = crate_name.to_string();
+    let mut versions = vec![];
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let entry: IndexVersion =
+            serde_json::from_str(line).map_err(|e| registry_error(e.to_string()))?;
+        name = entry.name.clone();
+        versions.push(CrateVersion::try_from(entry)?);
+    }
+
+    Ok(Some(Download::Fetched {
+        krate: Crate {
+            name,
+            description: None,
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            updated_at: DateTime::<Utc>::UNIX_EPOCH,
+            versions,
+        },
+        etag,
+    }))
+}
+
+/// Download crate metadata for all `crate_names` from a sparse index, keeping at most
+/// `fetch_opts.concurrency` requests in flight and retrying transient failures up to
+/// `fetch_opts.retries` times. Crates missing from the index are an `Error::CrateNotFound` when
+/// `fetch_opts.strict`, otherwise they are reported and left out. Crates listed in `etags` are only
+/// downloaded again when they changed since the response carrying that ETag. The registry token is
+/// sent as the `Authorization` header of every request, as cargo does for registries requiring
+/// authentication.
+pub async fn download_sparse_crates(
+    index_url: &str,
+    crate_names: &[&str],
+    etags: &BTreeMap<String, String>,
+    fetch_opts: &FetchOptions,
+) -> Result<Vec<Download>, crate::error::Error> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(token) = &fetch_opts.registry_token {
+        let mut value = reqwest::header::HeaderValue::from_str(token).map_err(|_| {
+            crate::error::Error::Other("The registry token is not a valid header value".into())
+        })?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+
+    let client = reqwest::Client::builder()
+        .user_agent(&fetch_opts.user_agent)
+        .default_headers(headers)
+        .build()
+        .map_err(|e| crate::error::Error::RegistryError {
+            url: index_url.to_string(),
+            error: e.to_string(),
+        })?;
+
+    let atomic_usize = std::sync::atomic::AtomicUsize::new(0);
+
+    debug!(
+        "Downloading crate data from {} for: [{}]",
+        index_url,
+        crate_names.join(", ")
+    );
+    futures::stream::iter(crate_names.iter())
+        .map(async |name| {
+            let etag = etags.get(*name).map(String::as_str);
+            let elem =
+                download_sparse_crate(&client, index_url, name, etag, fetch_opts.retries).await;
+            info!(
+                "Downloaded crate data for {} from {} ({}/{})",
+                name,
+                index_url,
+                atomic_usize.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1,
+                crate_names.len()
+            );
+            (*name, elem)
+        })
+        .buffer_unordered(fetch_opts.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .filter_map(|(name, elem)| match elem {
+            Ok(Some(download)) => Some(Ok(download)),
+            Ok(None) if !fetch_opts.strict => {
+                warn!("Crate {} was not found in {}, skipping it", name, index_url);
+                None
+            }
+            Ok(None) => Some(Err(crate::error::Error::CrateNotFound(name.to_string()))),
+            Err(error) => Some(Ok(Download::Failed {
+                name: name.to_string(),
+                error,
+            })),
+        })
+        .collect()
+}
@@ -1,21 +1,358 @@
 //! Core algorithm for selecting the most permissive semver requirements that still validate.
 use std::{
+    cell::{Cell, RefCell},
     collections::{BTreeMap, BTreeSet},
     path::PathBuf,
     sync::atomic::AtomicUsize,
+    time::{Duration, Instant},
 };
 
+use cargo_util_schemas::manifest::RustVersion;
+use chrono::Utc;
 use either::Either;
-use log::{debug, info, warn};
-use semver::{Comparator, Prerelease, Version, VersionReq};
+use log::{debug, info, log, warn};
+use semver::{Comparator, Op, Prerelease, Version, VersionReq};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    cargo::CargoPackage,
-    crates::Crate,
+    cache::{ValidationCache, ValidationCacheEntry, validation_context_hash},
+    cargo::{CargoPackage, ManifestGuard, ManifestSnapshot},
+    crates::{Crate, DependencyKind},
     error::Error,
-    validator::{BuildOptions, Check, RepoValidator, TestOptions},
+    validator::{BuildOptions, Check, RepoValidator, TestOptions, ValidationError},
 };
 
+/// How the compatible versions of each crate are searched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Search the widest range of working versions around the selected one
+    #[default]
+    Widen,
+    /// Validate every version matching the current requirement, which is kept as is
+    Verify,
+}
+
+/// Precision of the bounds searched, coarser granularities validate fewer candidates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// One candidate per semver-compatible line (`1.x`, `0.3.x`)
+    Major,
+    /// One candidate per minor line (`1.2.x`)
+    Minor,
+    /// Every version is a candidate
+    #[default]
+    Patch,
+}
+
+/// Options controlling how the resolver searches for compatible versions.
+#[derive(Clone, Debug)]
+pub struct ResolverOptions {
+    /// Delay inserted before each validator invocation, `Duration::ZERO` disables throttling.
+    pub throttle: Duration,
+    /// Only pin candidates in Cargo.lock (`cargo update --precise`), leaving the manifest requirements
+    /// untouched. Candidates are then restricted to the versions matching the current requirement.
+    pub lock_only: bool,
+    /// Write the oldest working version as a lower-bound-only requirement (`>=x.y.z`) instead of
+    /// the range found by the search
+    pub minimize: bool,
+    /// Number of crates searched concurrently. Each additional worker validates in its own copy of
+    /// the repository, so `1` keeps the search sequential in the repository itself.
+    pub parallel: usize,
+    /// Consider prerelease versions (`1.0.0-alpha`) as candidates, they are skipped by default
+    pub allow_prerelease: bool,
+    /// Treat versions whose docs.rs build failed as failing without building them, unless they bound
+    /// the range found. These outcomes are not cached.
+    pub docsrs_prefilter: bool,
+    /// Keep the build artifacts of the repository when cleaning up (no `cargo clean`), so that the
+    /// next run builds incrementally
+    pub keep_artifacts: bool,
+    /// Maximum number of validations per crate, once reached the remaining candidates are assumed to
+    /// fail and the bounds found so far are kept. `None` searches until the bounds are exact.
+    pub max_comparisons: Option<usize>,
+    /// Wall-clock limit of the whole resolution. Once elapsed, no new validation is started and only
+    /// the crates already resolved are reported.
+    pub max_duration: Option<Duration>,
+    /// Validate candidates with `cargo check` instead of `cargo build` when the tests are not run,
+    /// skipping code generation
+    pub cargo_check: bool,
+    /// Program and arguments validating candidates instead of cargo build/test, empty for none
+    pub check_command: Vec<String>,
+    /// Search for the widest working range, or verify the current requirements
+    pub strategy: Strategy,
+    /// Validate candidates one by one outward from the baseline instead of bisecting, so that the
+    /// range found never contains a failing version even when the passing versions are not
+    /// contiguous. The bisection probes the version past each failing bound and warns when it passes.
+    pub linear_search: bool,
+    /// Candidates kept for the search: with a coarser granularity than `Patch`, only the newest
+    /// version of each line (and the baseline) is validated
+    pub granularity: Granularity,
+    /// Record the error of a crate whose search fails in the report and resolve the others, instead of
+    /// failing the whole resolution
+    pub keep_going: bool,
+    /// Search the bounds with `cargo check`, then run the tests on the bounds found only. A bound
+    /// failing its tests is excluded and the search continues. Only applies when the tests are run.
+    pub confirm_with_tests: bool,
+    /// Compare the checksum recorded in Cargo.lock for each candidate with the one published by the
+    /// registry, warning on mismatch
+    pub verify_checksums: bool,
+    /// Log the outcome of each validated version at debug level instead of info, leaving only the
+    /// result of each crate and the final summary
+    pub summary_only: bool,
+    /// Draw a progress bar of the crates resolved on stderr, see [`crate::progress::ProgressBar`]
+    pub progress: bool,
+}
+
+impl Default for ResolverOptions {
+    fn default() -> Self {
+        Self {
+            throttle: Duration::from_millis(500),
+            lock_only: false,
+            minimize: false,
+            parallel: 1,
+            allow_prerelease: false,
+            docsrs_prefilter: false,
+            keep_artifacts: false,
+            max_comparisons: None,
+            max_duration: None,
+            cargo_check: false,
+            check_command: vec![],
+            strategy: Strategy::Widen,
+            linear_search: false,
+            granularity: Granularity::Patch,
+            keep_going: false,
+            confirm_with_tests: false,
+            verify_checksums: false,
+            summary_only: false,
+            progress: false,
+        }
+    }
+}
+
+/// Machine-readable summary of a resolution run.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResolveReport {
+    /// Resolved requirement for each crate name
+    pub requirements: BTreeMap<String, VersionReq>,
+    /// Whether the default (pre-resolution) configuration passed validation
+    pub baseline_validated: bool,
+    /// Total number of validator invocations performed during the search
+    pub comparisons: usize,
+    /// Oldest and newest working version found for each crate name
+    #[serde(default)]
+    pub ranges: BTreeMap<String, VersionRange>,
+    /// Whether `requirements` hold the minimum compatible versions (`--minimize`)
+    #[serde(default)]
+    pub minimized: bool,
+    /// Cost of the search for each crate name
+    #[serde(default)]
+    pub summary: BTreeMap<String, CrateSummary>,
+    /// Failure of the default configuration, when the search could not start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_error: Option<ValidationError>,
+    /// Whether `requirements` are the current requirements, checked version by version
+    /// (`--strategy verify`)
+    #[serde(default)]
+    pub verified: bool,
+    /// Versions failing validation for each crate name, when verifying the current requirements
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failing_versions: BTreeMap<String, Vec<Version>>,
+    /// Versions left unvalidated once the comparison budget was exhausted for each crate name, when
+    /// verifying the current requirements
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unverified_versions: BTreeMap<String, Vec<Version>>,
+    /// Crates left unresolved because the maximum duration elapsed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+    /// Form of the resolved requirement for each crate name
+    #[serde(default)]
+    pub kinds: BTreeMap<String, RequirementKind>,
+    /// Error of each crate whose search failed, when resolving with `keep_going`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, String>,
+    /// Yanked versions right past the oldest or newest working version of each crate name, which
+    /// the search could not validate
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub yanked_at_bounds: BTreeMap<String, Vec<Version>>,
+    /// Reason each crate was left out of the search, when no available version matches its
+    /// requirement
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped: BTreeMap<String, String>,
+}
+
+/// Form of a requirement: an exact pin only admits one version, a sign of poor compatibility.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequirementKind {
+    /// `*`, every version
+    Wildcard,
+    /// `^x.y.z`, the semver-compatible versions
+    Caret,
+    /// `~x.y.z`, the patch versions
+    Tilde,
+    /// `=x.y.z`, a single version
+    Exact,
+    /// Explicit bounds, e.g. `>=x.y.z, <=x.y.z` or `>=x.y.z`
+    Range,
+}
+
+impl RequirementKind {
+    pub fn of(requirement: &VersionReq) -> Self {
+        match requirement.comparators.as_slice() {
+            [] => Self::Wildcard,
+            [comparator] => match comparator.op {
+                Op::Caret => Self::Caret,
+                Op::Tilde => Self::Tilde,
+                Op::Exact if comparator.patch.is_some() => Self::Exact,
+                _ => Self::Range,
+            },
+            _ => Self::Range,
+        }
+    }
+}
+
+impl std::fmt::Display for RequirementKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Wildcard => write!(f, "wildcard"),
+            Self::Caret => write!(f, "caret"),
+            Self::Tilde => write!(f, "tilde"),
+            Self::Exact => write!(f, "exact pin"),
+            Self::Range => write!(f, "range"),
+        }
+    }
+}
+
+/// Cost of the search for a single crate.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CrateSummary {
+    /// Validator invocations performed (cached outcomes excluded)
+    pub comparisons: usize,
+    /// Number of candidate versions considered
+    pub candidates: usize,
+    /// Number of versions left out of the candidates because they are yanked
+    #[serde(default)]
+    pub yanked: usize,
+    /// Wall-clock time spent searching, in seconds
+    pub elapsed_secs: f64,
+}
+
+/// Inclusive range of working versions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionRange {
+    pub min: Version,
+    pub max: Version,
+}
+
+/// How the requirement of a crate changed between two resolve reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RequirementChange {
+    Added(VersionReq),
+    Removed(VersionReq),
+    Unchanged(VersionReq),
+    /// The working range grew on at least one side and shrank on none
+    Widened {
+        old: VersionReq,
+        new: VersionReq,
+    },
+    /// The working range shrank on at least one side and grew on none
+    Narrowed {
+        old: VersionReq,
+        new: VersionReq,
+    },
+    /// The requirement changed but the ranges cannot be compared (missing or moved on both sides)
+    Changed {
+        old: VersionReq,
+        new: VersionReq,
+    },
+}
+
+impl ResolveReport {
+    /// Compare the requirements of this report with those of a `newer` one, by crate name.
+    ///
+    /// Changed requirements are classified from the working ranges recorded in both reports.
+    pub fn diff(&self, newer: &ResolveReport) -> BTreeMap<String, RequirementChange> {
+        let names = self
+            .requirements
+            .keys()
+            .chain(newer.requirements.keys())
+            .collect::<BTreeSet<_>>();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let change = match (self.requirements.get(name), newer.requirements.get(name)) {
+                    (Some(old), None) => RequirementChange::Removed(old.clone()),
+                    (None, Some(new)) => RequirementChange::Added(new.clone()),
+                    (Some(old), Some(new)) if old == new => {
+                        RequirementChange::Unchanged(new.clone())
+                    }
+                    (Some(old), Some(new)) => {
+                        let (old, new) = (old.clone(), new.clone());
+                        match (self.ranges.get(name), newer.ranges.get(name)) {
+                            (Some(old_range), Some(new_range)) => {
+                                let grew =
+                                    new_range.min < old_range.min || new_range.max > old_range.max;
+                                let shrank =
+                                    new_range.min > old_range.min || new_range.max < old_range.max;
+                                match (grew, shrank) {
+                                    (true, false) => RequirementChange::Widened { old, new },
+                                    (false, true) => RequirementChange::Narrowed { old, new },
+                                    _ => RequirementChange::Changed { old, new },
+                                }
+                            }
+                            _ => RequirementChange::Changed { old, new },
+                        }
+                    }
+                    (None, None) => unreachable!("name comes from one of the reports"),
+                };
+                (name.clone(), change)
+            })
+            .collect()
+    }
+}
+
+/// A package whose compatible versions are searched.
+struct SearchTarget<'a> {
+    name: &'a str,
+    baseline: Version,
+    information: &'a Crate,
+    requirement: Option<VersionReq>,
+    /// Tables declaring the package, each candidate is written to all of them
+    kinds: Vec<DependencyKind>,
+}
+
+/// Outcome of the search for a single package.
+struct PackageResolution {
+    requirement: VersionReq,
+    range: Option<VersionRange>,
+    comparisons: usize,
+    candidates: usize,
+    yanked: usize,
+    /// Yanked versions between a bound of the range and the next candidate past it
+    yanked_at_bounds: Vec<Version>,
+    /// Versions that failed validation, only recorded when verifying
+    failing: Vec<Version>,
+    /// Versions not validated because the comparison budget was exhausted, only recorded when verifying
+    unverified: Vec<Version>,
+}
+
+/// Search of a single crate handed to a worker, along with its cached validation outcomes.
+struct SearchJob<'a> {
+    target: SearchTarget<'a>,
+    validation_cache: BTreeMap<Version, ValidationCacheEntry>,
+    /// Outcomes of the tests run on the bounds, with `confirm_with_tests`
+    confirmation_cache: BTreeMap<Version, ValidationCacheEntry>,
+}
+
+/// Outcome of a [`SearchJob`], the cached outcomes are handed back to be merged into the cache.
+struct SearchOutcome<'a> {
+    name: &'a str,
+    validation_cache: BTreeMap<Version, ValidationCacheEntry>,
+    confirmation_cache: BTreeMap<Version, ValidationCacheEntry>,
+    resolution: Result<PackageResolution, Error>,
+    elapsed: Duration,
+}
+
 /// Resolves dependency version requirements by testing candidate versions against the repository.
 pub struct Resolver {
     pub targets: Vec<CargoPackage>,
@@ -24,9 +361,15 @@ pub struct Resolver {
     pub validator: Box<dyn RepoValidator>,
     pub build_opts: BuildOptions,
     pub test_opts: Option<TestOptions>,
+    pub options: ResolverOptions,
+    /// Results of previous validations, consulted before invoking the validator
+    pub validation_cache: ValidationCache,
 
     packages_requirements: BTreeMap<String, VersionReq>,
     packages: BTreeMap<String, Version>,
+    report: ResolveReport,
+    manifest_guard: Option<ManifestGuard>,
+    worker_dirs: Vec<PathBuf>,
 }
 
 impl Resolver {
@@ -38,6 +381,7 @@ impl Resolver {
         validator: Box<dyn RepoValidator>,
         build_opts: BuildOptions,
         test_opts: Option<TestOptions>,
+        options: ResolverOptions,
     ) -> Self {
         Resolver {
             targets,
@@ -46,15 +390,42 @@ impl Resolver {
             validator,
             build_opts,
             test_opts,
+            options,
+            validation_cache: ValidationCache::default(),
             packages_requirements: BTreeMap::new(),
             packages: BTreeMap::new(),
+            report: ResolveReport::default(),
+            manifest_guard: None,
+            worker_dirs: vec![],
         }
     }
 
     /// Pre-populate selections using Cargo.lock when possible, otherwise pick latest matching versions.
+    /// A missing Cargo.lock is generated first, so that the baseline holds the versions cargo selects.
+    ///
+    /// This also snapshots the manifests and Cargo.lock: they are restored when the resolver is dropped
+    /// unless [`Resolver::commit`] was called. A generated Cargo.lock is then removed again.
     pub fn populate_default(&mut self) -> Result<(), Error> {
+        if self.manifest_guard.is_none() {
+            self.manifest_guard = Some(ManifestGuard::new(ManifestSnapshot::capture(
+                self.modified_files(),
+            )?));
+        }
+
         // First read the Cargo.lock file
         let cargo_lock_path = self.path.join("Cargo.lock");
+        if !cargo_lock_path.exists() {
+            info!(
+                "No Cargo.lock in {}, generating it to select the baseline versions",
+                self.path.display()
+            );
+            if let Err(e) = self.validator.generate_lockfile() {
+                warn!(
+                    "Failed to generate Cargo.lock, the latest matching versions are used as baseline: {}",
+                    e
+                );
+            }
+        }
         let cargo_lock_file = crate::cargo::CargoLockFile::read_from_path(&cargo_lock_path)
             .inspect_err(|err| {
                 warn!("Failed to read Cargo.lock: {err}");
@@ -62,21 +433,81 @@ impl Resolver {
             .ok();
 
         // Secondly, find all of the dependencies we need to resolve
+        let mut declared_requirements: BTreeMap<String, Vec<(String, VersionReq)>> =
+            BTreeMap::new();
         for target in &self.targets {
             for dependency in &target.dependencies {
+                // Git dependencies stay pinned in the manifest: they are built but never searched
                 if dependency.git {
-                    warn!(
-                        "Git packages are not supported. Ignoring package: {}",
+                    debug!(
+                        "Keeping git dependency '{}' pinned to {}",
+                        dependency.crate_name,
+                        dependency.git_source.as_deref().unwrap_or("its git source")
+                    );
+                    continue;
+                }
+
+                // Patched dependencies are built from their override, the registry versions do not apply
+                if dependency.patched {
+                    debug!(
+                        "Keeping dependency '{}' overridden by [patch]/[replace]",
                         dependency.crate_name
                     );
                     continue;
                 }
 
-                self.packages_requirements.insert(
-                    dependency.crate_name.clone(),
-                    dependency.required_version.clone(),
+                declared_requirements
+                    .entry(dependency.crate_name.clone())
+                    .or_default()
+                    .push((target.name.clone(), dependency.required_version.clone()));
+            }
+        }
+
+        // A crate used by several targets is searched once, against the intersection of their requirements
+        for (crate_name, declared) in declared_requirements {
+            let mut requirement = VersionReq {
+                comparators: vec![],
+            };
+            for (_, declared_requirement) in &declared {
+                for comparator in &declared_requirement.comparators {
+                    if !requirement.comparators.contains(comparator) {
+                        requirement.comparators.push(comparator.clone());
+                    }
+                }
+            }
+            if requirement.comparators.is_empty() {
+                requirement = VersionReq::STAR;
+            }
+
+            if declared.iter().any(|(_, r)| *r != declared[0].1) {
+                warn!(
+                    "Dependency '{}' is required differently across targets ({}), searching versions matching '{}'",
+                    crate_name,
+                    declared
+                        .iter()
+                        .map(|(target, r)| format!("{}: {}", target, r))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    requirement
                 );
+
+                if let Some(krate) = self.package_informations.get(&crate_name)
+                    && !krate
+                        .versions
+                        .iter()
+                        .any(|v| requirement.matches(&v.version))
+                {
+                    return Err(Error::RequirementConflict {
+                        crate_name,
+                        requirements: declared
+                            .iter()
+                            .map(|(target, r)| format!("{} requires {}", target, r))
+                            .collect(),
+                    });
+                }
             }
+
+            self.packages_requirements.insert(crate_name, requirement);
         }
 
         // Now, try to resolve each package using the Cargo.lock file
@@ -121,37 +552,66 @@ impl Resolver {
         Ok(())
     }
 
-    /// Run the resolution process and return the final semver requirements by crate name.
-    pub fn resolve(&mut self) -> Result<&BTreeMap<String, VersionReq>, Error> {
-        // First of all search for a configuration that works
-        // We assume the default configuration is the one that works
+    /// Pin the currently selected versions and validate them once, without searching.
+    ///
+    /// Yanked or unpublished selections are replaced by the latest non-yanked version matching the
+    /// requirement first. A crate without any is skipped with a warning: it is neither pinned nor
+    /// searched, rather than built with a version outside its requirement. Returns the validation
+    /// failure of this baseline, if any.
+    pub fn check_baseline(&mut self) -> Result<Option<ValidationError>, Error> {
+        let mut skipped = vec![];
         for (package_name, crate_info) in self.package_informations.iter() {
-            let version = &self.packages[package_name];
+            let version = self.packages.get(package_name).ok_or_else(|| {
+                crate::error::Error::Other(
+                    format!(
+                        "No version selected for package '{}': it was neither found in Cargo.lock nor matched on crates.io",
+                        package_name
+                    )
+                    .into(),
+                )
+            })?;
 
-            let version = crate_info.versions.iter().find(|v| &v.version == version);
-            if version.is_none() || version.unwrap().yanked {
+            let selected = crate_info.versions.iter().find(|v| &v.version == version);
+            if selected.is_none_or(|v| v.yanked) {
                 warn!(
-                    "The selected version '{}' for package '{}' is invalid or yanked.",
-                    version.unwrap().version,
-                    package_name
+                    "The selected version '{}' for package '{}' is {}.",
+                    version,
+                    package_name,
+                    if selected.is_none() {
+                        "not published"
+                    } else {
+                        "yanked"
+                    }
                 );
 
-                // Find the latest non-yanked version
-                let non_yanked_version = crate_info
+                // Find the latest non-yanked version matching the requirement
+                let requirement = self
+                    .packages_requirements
+                    .get(package_name)
+                    .cloned()
+                    .unwrap_or_default();
+                let Some(non_yanked_version) = crate_info
                     .versions
                     .iter()
-                    .filter(|v| !v.yanked)
-                    .filter(|v| {
-                        let req = self.packages_requirements.get(package_name).unwrap();
-                        req.matches(&v.version)
-                    })
+                    .filter(|v| !v.yanked && requirement.matches(&v.version))
                     .max_by_key(|v| v.version.clone())
-                    .or_else(|| crate_info.versions.iter().filter(|v| !v.yanked).next_back())
-                    .ok_or_else(|| {
-                        crate::error::Error::Other(
-                            format!("No available versions for package '{}'", package_name).into(),
-                        )
-                    })?;
+                else {
+                    let reason = crate::error::Error::NoAvailableVersion {
+                        crate_name: package_name.clone(),
+                        requirement: requirement.clone(),
+                        yanked: crate_info
+                            .versions
+                            .iter()
+                            .filter(|v| requirement.matches(&v.version))
+                            .count(),
+                    };
+                    warn!(
+                        "{}, skipping it: its requirement and selected version are left as they are",
+                        reason
+                    );
+                    skipped.push((package_name.clone(), reason.to_string()));
+                    continue;
+                };
 
                 self.packages
                     .insert(package_name.clone(), non_yanked_version.version.clone());
@@ -162,86 +622,827 @@ impl Resolver {
             }
         }
 
-        let check = if let Some(test_opts) = &self.test_opts {
-            Check::RunTest {
-                build_opts: &self.build_opts,
-                test_opts,
-            }
-        } else {
-            Check::Build {
-                build_opts: &self.build_opts,
+        // Skipped crates keep the requirement written in the manifests
+        for (package_name, reason) in skipped {
+            self.package_informations.remove(&package_name);
+            self.packages.remove(&package_name);
+            self.packages_requirements.remove(&package_name);
+            self.report.skipped.insert(package_name, reason);
+        }
+
+        let packages = self.packages.clone();
+        self.pin_packages(&packages)?;
+
+        let check = make_check(
+            &self.build_opts,
+            &self.test_opts,
+            self.options.cargo_check,
+            &self.options.check_command,
+        );
+
+        match self.validator.run_check(check) {
+            Ok(()) => {
+                self.report.baseline_validated = true;
+                Ok(None)
             }
+            Err(Either::Left(validation_error)) => Ok(Some(validation_error)),
+            Err(Either::Right(err)) => Err(err),
+        }
+    }
+
+    /// Pin the currently selected versions with `crate_name` set to `version` instead, and validate
+    /// them once. Unlike [`Resolver::check_baseline`], yanked versions and versions outside the
+    /// current requirement are checked as given. Returns the validation failure, if any.
+    pub fn check_version(
+        &mut self,
+        crate_name: &str,
+        version: &Version,
+    ) -> Result<Option<ValidationError>, Error> {
+        let Some(crate_info) = self.package_informations.get(crate_name) else {
+            return Err(crate::error::Error::Other(
+                format!("No registry information available for crate {}", crate_name).into(),
+            ));
         };
+        match crate_info.versions.iter().find(|v| &v.version == version) {
+            None => {
+                return Err(crate::error::Error::Other(
+                    format!("Version '{}' of '{}' is not published", version, crate_name).into(),
+                ));
+            }
+            Some(selected) if selected.yanked => warn!(
+                "Version '{}' of '{}' is yanked, checking it anyway",
+                version, crate_name
+            ),
+            Some(_) => {}
+        }
+        if let Some(requirement) = self.packages_requirements.get(crate_name)
+            && !requirement.matches(version)
+        {
+            info!(
+                "Version '{}' of '{}' does not match its current requirement '{}'",
+                version, crate_name, requirement
+            );
+        }
 
-        for (name, version) in &self.packages {
+        let mut packages = self.packages.clone();
+        packages.insert(crate_name.to_string(), version.clone());
+        self.pin_packages(&packages)?;
+
+        let check = make_check(
+            &self.build_opts,
+            &self.test_opts,
+            self.options.cargo_check,
+            &self.options.check_command,
+        );
+
+        match self.validator.run_check(check) {
+            Ok(()) => Ok(None),
+            Err(Either::Left(validation_error)) => Ok(Some(validation_error)),
+            Err(Either::Right(err)) => Err(err),
+        }
+    }
+
+    /// Set each dependency of `packages` to its version, in the manifests or only in Cargo.lock.
+    fn pin_packages(&mut self, packages: &BTreeMap<String, Version>) -> Result<(), Error> {
+        for (name, version) in packages {
             info!("Initial package '{}' set to version '{}'", name, version);
-            self.validator
-                .set_dependency(name.clone(), version.clone())
-                .map_err(|_| {
-                    crate::error::Error::Other(format!("Failed to set dependency {}", name).into())
-                })?;
-        }
-
-        self.validator.run_check(check).map_err(|e| match e {
-            Either::Left(validation_error) => {
-                log::error!(
-                    "Cannot resolve packages because default configuration is invalid: {:?}",
-                    validation_error
-                );
-                crate::error::Error::Other(
-                    format!("Validation error: {:?}", validation_error).into(),
+            if self.options.lock_only {
+                self.validator
+                    .set_locked_version(name.clone(), version.clone())
+            } else {
+                self.validator.set_dependency(
+                    name.clone(),
+                    version.clone(),
+                    &dependency_kinds(&self.targets, name),
                 )
             }
-            Either::Right(err) => err,
-        })?;
+            .map_err(|_| {
+                crate::error::Error::Other(format!("Failed to set dependency {}", name).into())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Run the resolution process and return the final semver requirements by crate name.
+    pub fn resolve(&mut self) -> Result<&BTreeMap<String, VersionReq>, Error> {
+        let deadline = self
+            .options
+            .max_duration
+            .map(|max_duration| Instant::now() + max_duration);
+
+        // First of all search for a configuration that works
+        // We assume the default configuration is the one that works
+        if let Some(validation_error) = self.check_baseline()? {
+            log::error!(
+                "Cannot resolve packages because the default configuration is invalid. {}",
+                validation_error
+            );
+            let reason = validation_error.reason();
+            self.report.baseline_error = Some(validation_error);
+            return Err(crate::error::Error::InvalidBaseline(reason));
+        }
+        let verify = self.options.strategy == Strategy::Verify;
+        self.report.minimized = self.options.minimize && !verify;
+        self.report.verified = verify;
+
+        // The tests only confirm the bounds found by a search with `cargo check`
+        let confirm_with_tests = self.options.confirm_with_tests
+            && !verify
+            && self.test_opts.is_some()
+            && self.options.check_command.is_empty();
+        let search_test_opts = if confirm_with_tests {
+            None
+        } else {
+            self.test_opts.clone()
+        };
+        let check = make_check(
+            &self.build_opts,
+            &search_test_opts,
+            self.options.cargo_check || confirm_with_tests,
+            &self.options.check_command,
+        );
+        let confirm = confirm_with_tests.then(|| {
+            make_check(
+                &self.build_opts,
+                &self.test_opts,
+                false,
+                &self.options.check_command,
+            )
+        });
+
+        // Candidate outcomes only depend on the options, the targets and the baseline versions
+        let context = |test_opts: &Option<TestOptions>, cargo_check: bool| {
+            validation_context_hash(&(
+                &self.build_opts,
+                test_opts,
+                self.targets
+                    .iter()
+                    .map(|t| (&t.name, &t.manifest_path))
+                    .collect::<Vec<_>>(),
+                &self.packages,
+                self.options.lock_only,
+                self.validator.environment(),
+                // A successful `cargo check` does not mean the crate builds
+                cargo_check && test_opts.is_none(),
+                &self.options.check_command,
+            ))
+        };
+        let validation_context = context(
+            &search_test_opts,
+            self.options.cargo_check || confirm_with_tests,
+        )?;
+        let confirmation_context = context(&self.test_opts, false)?;
+
+        // Additional workers validate in copies of the repository, taken once the baseline is in place
+        let mut forks = vec![];
+        let mut worker_count = self
+            .options
+            .parallel
+            .min(self.package_informations.len())
+            .max(1);
+        if worker_count > 1 {
+            let mut manifests = self.modified_files();
+            manifests.retain(|path| path.ends_with("Cargo.toml"));
+            manifests.push(self.path.join("Cargo.toml"));
+            let outside = paths_outside_repository(&self.path, &manifests);
+            if !outside.is_empty() {
+                warn!(
+                    "The manifests refer to paths outside of the repository, which its copies would not find ({}), resolving sequentially",
+                    outside
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                worker_count = 1;
+            }
+        }
+        for index in 1..worker_count {
+            let working_dir =
+                std::env::temp_dir().join(format!("cargo-compat-{}-{}", std::process::id(), index));
+            // Recorded first so that a partial copy is removed as well
+            self.worker_dirs.push(working_dir.clone());
+            copy_repository(&self.path, &working_dir)?;
+
+            match self.validator.fork(&working_dir) {
+                Some(validator) => forks.push(validator),
+                None => {
+                    warn!("The validator cannot run in parallel, resolving sequentially");
+                    break;
+                }
+            }
+        }
+        if !forks.is_empty() {
+            info!(
+                "Resolving with {} workers ({} repository copies)",
+                forks.len() + 1,
+                forks.len()
+            );
+        }
 
         // Finally perform the resolution
-        for (package_name, package_information) in self.package_informations.iter() {
-            let version = self.packages[package_name].clone();
+        let jobs = self
+            .package_informations
+            .iter()
+            .map(|(package_name, package_information)| SearchJob {
+                target: SearchTarget {
+                    name: package_name,
+                    baseline: self.packages[package_name].clone(),
+                    information: package_information,
+                    requirement: self.packages_requirements.get(package_name).cloned(),
+                    kinds: dependency_kinds(&self.targets, package_name),
+                },
+                validation_cache: std::mem::take(
+                    self.validation_cache
+                        .crate_entries(validation_context, package_name),
+                ),
+                confirmation_cache: if confirm_with_tests {
+                    std::mem::take(
+                        self.validation_cache
+                            .crate_entries(confirmation_context, package_name),
+                    )
+                } else {
+                    BTreeMap::new()
+                },
+            })
+            .collect::<Vec<_>>();
 
-            let version_req = resolve_package(
-                package_name,
-                version.clone(),
-                package_information,
-                self.validator.as_mut(),
-                check,
-            )?;
+        let validators = std::iter::once(&mut self.validator)
+            .chain(forks.iter_mut())
+            .collect();
+        let mut outcomes =
+            run_search_jobs(jobs, validators, check, confirm, &self.options, deadline);
+        outcomes.sort_by_key(|outcome| outcome.name);
 
+        let mut first_error = None;
+        for outcome in outcomes {
+            *self
+                .validation_cache
+                .crate_entries(validation_context, outcome.name) = outcome.validation_cache;
+            if confirm_with_tests {
+                *self
+                    .validation_cache
+                    .crate_entries(confirmation_context, outcome.name) = outcome.confirmation_cache;
+            }
+
+            let resolution = match outcome.resolution {
+                Ok(resolution) => resolution,
+                Err(Error::DeadlineExceeded(_)) => {
+                    self.report.unresolved.push(outcome.name.to_string());
+                    continue;
+                }
+                Err(e) if self.options.keep_going && !matches!(e, Error::Interrupted) => {
+                    warn!(
+                        "Failed to resolve '{}', keeping its requirement: {}",
+                        outcome.name, e
+                    );
+                    self.report
+                        .errors
+                        .insert(outcome.name.to_string(), e.to_string());
+                    continue;
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+
+            let package_name = outcome.name.to_string();
+            self.report.comparisons += resolution.comparisons;
+            self.report.summary.insert(
+                package_name.clone(),
+                CrateSummary {
+                    comparisons: resolution.comparisons,
+                    candidates: resolution.candidates,
+                    yanked: resolution.yanked,
+                    elapsed_secs: outcome.elapsed.as_secs_f64(),
+                },
+            );
+            self.report
+                .requirements
+                .insert(package_name.clone(), resolution.requirement.clone());
+            self.report.kinds.insert(
+                package_name.clone(),
+                RequirementKind::of(&resolution.requirement),
+            );
+            if let Some(range) = resolution.range {
+                self.report.ranges.insert(package_name.clone(), range);
+            }
+            if !resolution.failing.is_empty() {
+                self.report
+                    .failing_versions
+                    .insert(package_name.clone(), resolution.failing);
+            }
+            if !resolution.unverified.is_empty() {
+                self.report
+                    .unverified_versions
+                    .insert(package_name.clone(), resolution.unverified);
+            }
+            if !resolution.yanked_at_bounds.is_empty() {
+                self.report
+                    .yanked_at_bounds
+                    .insert(package_name.clone(), resolution.yanked_at_bounds);
+            }
             self.packages_requirements
-                .insert(package_name.clone(), version_req);
+                .insert(package_name, resolution.requirement);
         }
 
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+
+        if !self.report.summary.is_empty() {
+            self.log_summary();
+        }
+        self.warn_rust_version_conflicts();
+        if let Some(max_duration) = self.options.max_duration
+            && !self.report.unresolved.is_empty()
+        {
+            warn!(
+                "Maximum duration elapsed, {} crates left unresolved: {}",
+                self.report.unresolved.len(),
+                self.report.unresolved.join(", ")
+            );
+            return Err(Error::DeadlineExceeded(max_duration));
+        }
         Ok(&self.packages_requirements)
     }
 
+    /// Warn about the resolved requirements whose oldest accepted version declares a `rust-version`
+    /// newer than the `rust-version` of a target depending on it: building on the current toolchain
+    /// does not mean the crate still builds with the MSRV of the target.
+    fn warn_rust_version_conflicts(&self) {
+        for (package_name, requirement) in &self.report.requirements {
+            let Some(information) = self.package_informations.get(package_name) else {
+                continue;
+            };
+            let oldest = match self.report.ranges.get(package_name) {
+                Some(range) => information.versions.iter().find(|v| v.version == range.min),
+                None => information
+                    .versions
+                    .iter()
+                    .filter(|v| !v.yanked && requirement.matches(&v.version))
+                    .min_by(|a, b| a.version.cmp(&b.version)),
+            };
+            let Some(dependency_msrv) = oldest
+                .and_then(|v| v.rust_version.as_deref())
+                .and_then(|rust_version| rust_version.parse::<RustVersion>().ok())
+            else {
+                continue;
+            };
+
+            for target in &self.targets {
+                if let Some(target_msrv) = &target.rust_version
+                    && !target.dependency_kinds(package_name).is_empty()
+                    && !dependency_msrv.is_compatible_with(target_msrv.as_partial())
+                {
+                    warn!(
+                        "'{}' {} requires rustc {}, newer than the rust-version {} of '{}': the requirement raises its MSRV",
+                        package_name, requirement, dependency_msrv, target_msrv, target.name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Log a table of the comparisons performed and time spent for each crate.
+    fn log_summary(&self) {
+        let width = self
+            .report
+            .summary
+            .keys()
+            .map(|name| name.len())
+            .chain(std::iter::once("crate".len()))
+            .max()
+            .unwrap_or_default();
+
+        info!(
+            "{:<width$}  {:>11}  {:>10}  {:>6}  {:>9}",
+            "crate", "comparisons", "candidates", "yanked", "time (s)"
+        );
+        for (name, summary) in &self.report.summary {
+            info!(
+                "{:<width$}  {:>11}  {:>10}  {:>6}  {:>9.2}",
+                name, summary.comparisons, summary.candidates, summary.yanked, summary.elapsed_secs
+            );
+        }
+        info!(
+            "{:<width$}  {:>11}  {:>10}  {:>6}  {:>9.2}",
+            "total",
+            self.report.comparisons,
+            self.report
+                .summary
+                .values()
+                .map(|s| s.candidates)
+                .sum::<usize>(),
+            self.report
+                .summary
+                .values()
+                .map(|s| s.yanked)
+                .sum::<usize>(),
+            self.report
+                .summary
+                .values()
+                .map(|s| s.elapsed_secs)
+                .sum::<f64>()
+        );
+    }
+
+    /// Summary of the resolution performed so far, suitable for serialization.
+    pub fn report(&self) -> &ResolveReport {
+        &self.report
+    }
+
+    /// Versions currently selected for each dependency, the baseline once checked.
+    pub fn packages(&self) -> &BTreeMap<String, Version> {
+        &self.packages
+    }
+
+    /// Files modified while resolving: the manifests of the targets, the root manifest and Cargo.lock.
+    pub fn modified_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self
+            .targets
+            .iter()
+            .map(|t| t.manifest_path.clone())
+            .collect();
+        files.push(self.path.clone());
+        files.push(self.path.join("Cargo.lock"));
+        files
+    }
+
+    /// Restore the manifests and Cargo.lock to their contents before resolution.
+    pub fn restore(&self) -> Result<(), Error> {
+        match &self.manifest_guard {
+            Some(guard) => guard.restore(),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the current manifests and Cargo.lock instead of restoring them when the resolver is dropped.
+    pub fn commit(&mut self) {
+        if let Some(guard) = &mut self.manifest_guard {
+            guard.commit();
+        }
+    }
+
     /// Clean any temporary files or processes created by the validator.
     pub fn clean(&mut self) {
-        self.validator.clean();
+        if self.options.keep_artifacts {
+            debug!("Keeping build artifacts");
+        } else {
+            self.validator.clean();
+        }
+
+        for working_dir in self.worker_dirs.drain(..) {
+            debug!("Removing worker copy {}", working_dir.display());
+            if let Err(e) = std::fs::remove_dir_all(&working_dir) {
+                warn!(
+                    "Failed to remove worker copy {}: {}",
+                    working_dir.display(),
+                    e
+                );
+            }
+        }
     }
 
-    /// Persist resolution output back to the repository (e.g., via cargo-edit add commands).
-    pub fn write_cargo_toml_with_resolved_versions(&mut self) -> Result<(), Error> {
-        for (package_name, version) in &self.packages_requirements {
-            self.validator
-                .set_dependency_req(package_name.clone(), version.clone())
-                .map_err(|_| {
-                    crate::error::Error::Other(
-                        format!("Failed to set dependency {}", package_name).into(),
-                    )
-                })?;
+    /// Persist resolution output back to the repository (e.g., via cargo-edit add commands), and return
+    /// a unified diff of the manifest changes.
+    ///
+    /// The requirements are applied with cargo first, the resulting files are then captured, and the
+    /// original files are restored before the new contents are written in one go, each through a
+    /// temporary file renamed over the original. A failing cargo command therefore leaves the original
+    /// manifests intact. On success the modifications are committed.
+    pub fn write_cargo_toml_with_resolved_versions(&mut self) -> Result<String, Error> {
+        // A failed search may have left a candidate in place, start again from the original files
+        if !self.report.errors.is_empty() {
+            self.restore()?;
         }
 
-        Ok(())
+        // Only the searched dependencies are rewritten, the others keep their manifest entry verbatim
+        for (package_name, version) in &self.report.requirements {
+            let written = dependency_kinds(&self.targets, package_name)
+                .into_iter()
+                .try_for_each(|kind| {
+                    self.validator
+                        .set_dependency_req(package_name.clone(), version.clone(), kind)
+                });
+            if written.is_err() {
+                self.restore()?;
+                return Err(crate::error::Error::Other(
+                    format!("Failed to set dependency {}", package_name).into(),
+                ));
+            }
+        }
+
+        let resolved = ManifestSnapshot::capture(self.modified_files())?;
+        self.restore()?;
+
+        let mut diff = String::new();
+        if let Some(guard) = &self.manifest_guard {
+            for ((path, original), (_, content)) in
+                guard.snapshot().files().iter().zip(resolved.files())
+            {
+                // The lock file changes are not worth reviewing
+                if path.file_name().is_some_and(|name| name == "Cargo.lock") {
+                    continue;
+                }
+
+                let label = path.strip_prefix(&self.path).unwrap_or(path);
+                diff.push_str(&crate::diff::unified_diff(
+                    &label.to_string_lossy(),
+                    &String::from_utf8_lossy(original.as_deref().unwrap_or_default()),
+                    &String::from_utf8_lossy(content.as_deref().unwrap_or_default()),
+                ));
+            }
+        }
+
+        resolved.restore()?;
+        self.commit();
+        Ok(diff)
+    }
+}
+
+/// The check to run: the user's check command when there is one, the tests when test options are
+/// given, otherwise a build only.
+fn make_check<'a>(
+    build_opts: &'a BuildOptions,
+    test_opts: &'a Option<TestOptions>,
+    cargo_check: bool,
+    check_command: &'a [String],
+) -> Check<'a> {
+    if !check_command.is_empty() {
+        return Check::Custom {
+            command: check_command,
+        };
+    }
+
+    match test_opts {
+        Some(test_opts) => Check::RunTest {
+            build_opts,
+            test_opts,
+        },
+        None if cargo_check => Check::Check { build_opts },
+        None => Check::Build { build_opts },
+    }
+}
+
+/// Tables declaring `package_name` among the `targets`, `[dependencies]` when none does.
+fn dependency_kinds(targets: &[CargoPackage], package_name: &str) -> Vec<DependencyKind> {
+    let kinds = targets
+        .iter()
+        .flat_map(|target| target.dependency_kinds(package_name))
+        .collect::<BTreeSet<_>>();
+    if kinds.is_empty() {
+        vec![DependencyKind::Normal]
+    } else {
+        kinds.into_iter().collect()
     }
 }
 
+/// Run the searches on the given validators, each validator handling one search at a time.
+fn run_search_jobs<'a>(
+    jobs: Vec<SearchJob<'a>>,
+    validators: Vec<&mut Box<dyn RepoValidator>>,
+    check: Check,
+    confirm: Option<Check>,
+    options: &ResolverOptions,
+    deadline: Option<Instant>,
+) -> Vec<SearchOutcome<'a>> {
+    let total = jobs.len();
+    let queue = std::sync::Mutex::new(jobs.into_iter().enumerate());
+    let outcomes = std::sync::Mutex::new(vec![]);
+    let finished = AtomicUsize::new(0);
+    let progress = options
+        .progress
+        .then(|| crate::progress::ProgressBar::new(total));
+
+    std::thread::scope(|scope| {
+        for validator in validators {
+            let queue = &queue;
+            let outcomes = &outcomes;
+            let finished = &finished;
+            let progress = &progress;
+            scope.spawn(move || {
+                loop {
+                    let Some((index, mut job)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    if crate::validator::is_interrupted() {
+                        outcomes.lock().unwrap().push(SearchOutcome {
+                            name: job.target.name,
+                            validation_cache: job.validation_cache,
+                            confirmation_cache: job.confirmation_cache,
+                            resolution: Err(Error::Interrupted),
+                            elapsed: Duration::ZERO,
+                        });
+                        continue;
+                    }
+                    let started_at = Instant::now();
+                    if let Some(deadline) = deadline
+                        && started_at >= deadline
+                    {
+                        info!(
+                            "Skipping crate {}/{}: '{}' (maximum duration elapsed)",
+                            index + 1,
+                            total,
+                            job.target.name
+                        );
+                        outcomes.lock().unwrap().push(SearchOutcome {
+                            name: job.target.name,
+                            validation_cache: job.validation_cache,
+                            confirmation_cache: job.confirmation_cache,
+                            resolution: Err(Error::DeadlineExceeded(
+                                options.max_duration.unwrap_or_default(),
+                            )),
+                            elapsed: Duration::ZERO,
+                        });
+                        continue;
+                    }
+
+                    if let Some(progress) = progress {
+                        progress.start(job.target.name);
+                    }
+                    info!(
+                        "Resolving crate {}/{}: '{}'",
+                        index + 1,
+                        total,
+                        job.target.name
+                    );
+                    let resolution = resolve_package(
+                        &mut job,
+                        validator.as_mut(),
+                        check,
+                        confirm,
+                        options,
+                        deadline,
+                    );
+                    outcomes.lock().unwrap().push(SearchOutcome {
+                        name: job.target.name,
+                        validation_cache: job.validation_cache,
+                        confirmation_cache: job.confirmation_cache,
+                        resolution,
+                        elapsed: started_at.elapsed(),
+                    });
+                    if let Some(progress) = progress {
+                        progress.finish(job.target.name);
+                    }
+                    info!(
+                        "Finished crate '{}' ({}/{} done)",
+                        job.target.name,
+                        finished.fetch_add(1, std::sync::atomic::Ordering::AcqRel) + 1,
+                        total
+                    );
+                }
+            });
+        }
+    });
+
+    outcomes.into_inner().unwrap()
+}
+
+/// Copy the repository at `source` into `destination` for a parallel worker, leaving out the build
+/// artifacts and version control data at its root.
+fn copy_repository(source: &std::path::Path, destination: &std::path::Path) -> Result<(), Error> {
+    copy_directory(source, destination, true)
+}
+
+fn copy_directory(
+    source: &std::path::Path,
+    destination: &std::path::Path,
+    root: bool,
+) -> Result<(), Error> {
+    let fs_error = |path: &std::path::Path, e: std::io::Error| Error::FileSystemError {
+        path: path.to_string_lossy().to_string(),
+        error: e.kind(),
+    };
+
+    std::fs::create_dir_all(destination).map_err(|e| fs_error(destination, e))?;
+    for entry in std::fs::read_dir(source).map_err(|e| fs_error(source, e))? {
+        let entry = entry.map_err(|e| fs_error(source, e))?;
+        let file_name = entry.file_name();
+        if root && (file_name == "target" || file_name == ".git") {
+            continue;
+        }
+
+        let from = entry.path();
+        let to = destination.join(&file_name);
+        if entry.file_type().map_err(|e| fs_error(&from, e))?.is_dir() {
+            copy_directory(&from, &to, false)?;
+        } else {
+            std::fs::copy(&from, &to).map_err(|e| fs_error(&from, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Paths given in `manifests` (e.g. `path = "../shared"` dependencies) leading outside of the
+/// repository at `root`: a copy of the repository would not find them at the same relative location.
+fn paths_outside_repository(root: &std::path::Path, manifests: &[PathBuf]) -> Vec<PathBuf> {
+    fn collect_paths(value: &toml::Value, paths: &mut Vec<String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    match value {
+                        toml::Value::String(path) if key == "path" => paths.push(path.clone()),
+                        value => collect_paths(value, paths),
+                    }
+                }
+            }
+            toml::Value::Array(values) => values.iter().for_each(|v| collect_paths(v, paths)),
+            _ => {}
+        }
+    }
+
+    // Lexical normalization, the paths may not exist
+    let normalize = |path: &std::path::Path| {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                std::path::Component::ParentDir => {
+                    normalized.pop();
+                }
+                std::path::Component::CurDir => {}
+                component => normalized.push(component),
+            }
+        }
+        normalized
+    };
+    let root = normalize(&std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf()));
+
+    let mut outside = vec![];
+    for manifest in manifests {
+        let Some(table) = std::fs::read_to_string(manifest)
+            .ok()
+            .and_then(|content| content.parse::<toml::Table>().ok())
+        else {
+            continue;
+        };
+        let mut paths = vec![];
+        collect_paths(&toml::Value::Table(table), &mut paths);
+
+        let manifest_dir = manifest.parent().unwrap_or(std::path::Path::new("."));
+        let manifest_dir =
+            std::path::absolute(manifest_dir).unwrap_or_else(|_| manifest_dir.to_path_buf());
+        outside.extend(
+            paths
+                .into_iter()
+                .map(|path| normalize(&manifest_dir.join(path)))
+                .filter(|path| !path.starts_with(&root)),
+        );
+    }
+    outside.sort();
+    outside.dedup();
+    outside
+}
+
+/// Search the compatible versions of the package of `job`. A failing search pins the baseline back
+/// before returning, so that the next crate searched with `validator` starts from the baseline.
 fn resolve_package(
-    package_name: &str,
-    version: Version,
-    package_information: &Crate,
+    job: &mut SearchJob,
     validator: &mut dyn RepoValidator,
     check: Check,
-) -> Result<VersionReq, Error> {
+    confirm: Option<Check>,
+    options: &ResolverOptions,
+    deadline: Option<Instant>,
+) -> Result<PackageResolution, Error> {
+    let resolution = search_package(job, validator, check, confirm, options, deadline);
+
+    // The manifests are restored as an interruption unwinds, pinning the baseline back is not needed
+    if let Err(e) = &resolution
+        && !matches!(e, Error::Interrupted)
+        && let Err(pin_error) = pin_target(validator, &job.target, &job.target.baseline, options)
+    {
+        warn!(
+            "Failed to pin '{}' back to its baseline version '{}': {}",
+            job.target.name, job.target.baseline, pin_error
+        );
+    }
+    resolution
+}
+
+/// Force `version` of the package of `target` into the build, in the manifests or only in Cargo.lock.
+fn pin_target(
+    validator: &mut dyn RepoValidator,
+    target: &SearchTarget,
+    version: &Version,
+    options: &ResolverOptions,
+) -> Result<(), Error> {
+    if options.lock_only {
+        validator.set_locked_version(target.name.to_string(), version.clone())
+    } else {
+        validator.set_dependency(target.name.to_string(), version.clone(), &target.kinds)
+    }
+}
+
+fn search_package(
+    job: &mut SearchJob,
+    validator: &mut dyn RepoValidator,
+    check: Check,
+    confirm: Option<Check>,
+    options: &ResolverOptions,
+    deadline: Option<Instant>,
+) -> Result<PackageResolution, Error> {
     // Acording to semver semantics, patch versions can be updated freely when using caret requirements
     // We need to minimize the number of comparisons as they are very expensive
     // A package with 300 versions will need 2log2(300) ~= 18 comparisons in the worst case to find the correct version bounds
@@ -251,58 +1452,467 @@ fn resolve_package(
     // If this fails, we perform binary search on the patch versions between major1.minor1.last_patch and major2.minor2.last_patch
     //
     // Similarly we can do the same for the major versions, in other words we binary search in a subset
-    let all_versions: Vec<Version> = package_information
+    let SearchJob {
+        target,
+        validation_cache,
+        confirmation_cache,
+    } = job;
+    let package_name = target.name;
+    let version = target.baseline.clone();
+    let package_information = target.information;
+
+    // Cargo.lock can only hold versions allowed by the manifest requirement, which is also the one
+    // being verified
+    let verify = options.strategy == Strategy::Verify;
+    let lock_requirement = target
+        .requirement
+        .as_ref()
+        .filter(|_| options.lock_only || verify);
+    let considered_versions = package_information
         .versions
         .iter()
-        .filter(|v| !v.yanked)
+        .filter(|v| lock_requirement.is_none_or(|req| req.matches(&v.version)))
+        // The baseline stays a candidate even when it is a prerelease, the search starts from it
+        .filter(|v| options.allow_prerelease || v.version.pre.is_empty() || v.version == version)
+        .collect::<Vec<_>>();
+    let mut yanked = 0;
+    let mut all_versions: Vec<Version> = considered_versions
+        .iter()
+        .filter(|v| {
+            if v.yanked {
+                debug!(
+                    "Version '{}' of '{}' is yanked and will not be considered",
+                    v.version, package_name
+                );
+                yanked += 1;
+            }
+            !v.yanked
+        })
         .map(|v| v.version.clone())
         .collect();
 
+    let pin = |validator: &mut dyn RepoValidator, version: &Version| {
+        pin_target(validator, target, version, options)
+    };
+
     let comparison_count = AtomicUsize::new(0);
     let mut old_check: BTreeMap<Version, bool> = BTreeMap::new();
 
+    // While confirming, the bounds are validated with the `confirm` check, bounds failing it are then
+    // failing for the search
+    let confirming = Cell::new(false);
+    let mut old_confirm: BTreeMap<Version, bool> = BTreeMap::new();
+    let failed_confirmation: RefCell<BTreeSet<Version>> = RefCell::new(BTreeSet::new());
+
+    // Versions rejected from their docs.rs build status only, and those that must be validated locally anyway.
+    // Verifying builds every version locally, a docs.rs failure does not make a version fail
+    let docsrs = (options.docsrs_prefilter && !verify)
+        .then(crate::docsrs::DocsRsClient::new)
+        .transpose()
+        .inspect_err(|e| warn!("docs.rs prefilter disabled: {}", e))
+        .ok()
+        .flatten();
+    let docsrs_failed: RefCell<BTreeSet<Version>> = RefCell::new(BTreeSet::new());
+    let confirm_locally: RefCell<BTreeSet<Version>> = RefCell::new(BTreeSet::new());
+
+    let outcome_level = if options.summary_only {
+        log::Level::Debug
+    } else {
+        log::Level::Info
+    };
+    let mut budget_exhausted = false;
+    // Versions assumed failing once the budget is exhausted, without being validated
+    let unvalidated: RefCell<BTreeSet<Version>> = RefCell::new(BTreeSet::new());
     let mut validator_fn = |version: &Version| {
+        let confirm = confirm.filter(|_| confirming.get());
+        if confirm.is_none() && failed_confirmation.borrow().contains(version) {
+            return Ok(false);
+        }
+        let (check, old_check, validation_cache, phase) = match confirm {
+            Some(confirm) => (
+                confirm,
+                &mut old_confirm,
+                &mut *confirmation_cache,
+                " with the tests",
+            ),
+            None => (check, &mut old_check, &mut *validation_cache, ""),
+        };
+
         if old_check.contains_key(version) {
             return Ok(*old_check.get(version).unwrap());
         }
 
-        comparison_count.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
-        std::thread::sleep(std::time::Duration::from_millis(500)); // Throttle comparisons to avoid overwhelming the system
+        if let Some(entry) = validation_cache.get(version) {
+            log!(
+                outcome_level,
+                "Checking package '{}' with version '{}'{}...{} (cached)",
+                package_name,
+                version,
+                phase,
+                if entry.passed { "OK" } else { "FAIL" }
+            );
+            old_check.insert(version.clone(), entry.passed);
+            return Ok(entry.passed);
+        }
 
-        if validator
-            .set_dependency(package_name.to_string(), version.clone())
-            .is_err()
+        if let Some(docsrs) = &docsrs
+            && confirm.is_none()
+            && !confirm_locally.borrow().contains(version)
+            && (docsrs_failed.borrow().contains(version)
+                || docsrs.build_status(package_name, version) == Some(false))
+        {
+            log!(
+                outcome_level,
+                "Checking package '{}' with version '{}'...FAIL (docs.rs build failed, not validated locally)",
+                package_name,
+                version
+            );
+            docsrs_failed.borrow_mut().insert(version.clone());
+            return Ok(false);
+        }
+
+        // The manifests are restored as the error unwinds, pinning the baseline back is not needed
+        if crate::validator::is_interrupted() {
+            return Err(Error::Interrupted);
+        }
+
+        if let Some(deadline) = deadline
+            && Instant::now() >= deadline
         {
-            old_check.insert(version.clone(), false);
             info!(
-                "Checking package '{}' with version '{}'...FAIL",
+                "Stopping the search of package '{}' (maximum duration elapsed)",
+                package_name
+            );
+            return Err(Error::DeadlineExceeded(
+                options.max_duration.unwrap_or_default(),
+            ));
+        }
+
+        if let Some(max_comparisons) = options.max_comparisons
+            && comparison_count.load(std::sync::atomic::Ordering::Acquire) >= max_comparisons
+        {
+            if !budget_exhausted {
+                warn!(
+                    "Comparison budget of {} exhausted for package '{}', the requirement found may be narrower than the actual compatible range",
+                    max_comparisons, package_name
+                );
+                budget_exhausted = true;
+            }
+            debug!(
+                "Assuming package '{}' with version '{}' fails (not validated)",
                 package_name, version
             );
+            unvalidated.borrow_mut().insert(version.clone());
             return Ok(false);
         }
 
-        match validator.run_check(check) {
-            Err(Either::Left(_)) => {
+        comparison_count.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+
+        // Throttle comparisons to avoid overwhelming the system. A blocking sleep is fine here: the
+        // resolution is fully synchronous (each comparison already blocks on a cargo subprocess)
+        // and runs once all network fetches are done, so nothing else is waiting on the runtime.
+        if !options.throttle.is_zero() {
+            std::thread::sleep(options.throttle);
+        }
+
+        let pinned = pin(validator, version);
+        if let Err(Error::Interrupted) = pinned {
+            return Err(Error::Interrupted);
+        }
+        // The candidate could not be set up, which says nothing about it building: not cached
+        if pinned.is_err() {
+            old_check.insert(version.clone(), false);
+            log!(
+                outcome_level,
+                "Checking package '{}' with version '{}'{}...FAIL",
+                package_name,
+                version,
+                phase
+            );
+            return Ok(false);
+        }
+
+        let outcome = validator.run_check(check);
+        if options.verify_checksums {
+            verify_checksum(&*validator, target, version);
+        }
+
+        match outcome {
+            Err(Either::Left(validation_error)) => {
+                if validation_error.features.is_empty() {
+                    log!(
+                        outcome_level,
+                        "Checking package '{}' with version '{}'{}...FAIL",
+                        package_name,
+                        version,
+                        phase
+                    );
+                } else {
+                    log!(
+                        outcome_level,
+                        "Checking package '{}' with version '{}'{}...FAIL (features: {})",
+                        package_name,
+                        version,
+                        phase,
+                        validation_error.features.join(", ")
+                    );
+                }
                 old_check.insert(version.clone(), false);
-                info!(
-                    "Checking package '{}' with version '{}'...FAIL",
-                    package_name, version
+                validation_cache.insert(
+                    version.clone(),
+                    ValidationCacheEntry {
+                        passed: false,
+                        validated_at: validation_error.runned_at,
+                        error: Some(validation_error),
+                    },
                 );
                 Ok(false)
             }
             Err(Either::Right(e)) => Err(e),
             Ok(()) => {
                 old_check.insert(version.clone(), true);
+                validation_cache.insert(
+                    version.clone(),
+                    ValidationCacheEntry {
+                        passed: true,
+                        error: None,
+                        validated_at: Utc::now(),
+                    },
+                );
+                log!(
+                    outcome_level,
+                    "Checking package '{}' with version '{}'{}...OK",
+                    package_name,
+                    version,
+                    phase
+                );
+                Ok(true)
+            }
+        }
+    };
+
+    // Verifying checks every version, only the search is coarsened
+    if !verify && options.granularity != Granularity::Patch {
+        let candidates = all_versions.len();
+        all_versions = collapse_candidates(&all_versions, &version, options.granularity);
+        debug!(
+            "Candidates of '{}' collapsed from {} to {} versions ({:?} granularity)",
+            package_name,
+            candidates,
+            all_versions.len(),
+            options.granularity
+        );
+    }
+
+    let mut sorted_versions = all_versions.clone();
+    sorted_versions.sort();
+
+    // Every candidate is validated, the requirement itself is kept
+    if verify {
+        let mut range: Option<VersionRange> = None;
+        let mut failing = vec![];
+        let mut unverified = vec![];
+        for candidate in &sorted_versions {
+            if !validator_fn(candidate)? {
+                if unvalidated.borrow().contains(candidate) {
+                    unverified.push(candidate.clone());
+                } else {
+                    failing.push(candidate.clone());
+                }
+                continue;
+            }
+            match &mut range {
+                Some(range) => range.max = candidate.clone(),
+                None => {
+                    range = Some(VersionRange {
+                        min: candidate.clone(),
+                        max: candidate.clone(),
+                    })
+                }
+            }
+        }
+
+        let total_comparisons = comparison_count.load(std::sync::atomic::Ordering::Acquire);
+        if !unverified.is_empty() {
+            warn!(
+                "{} versions of '{}' were not verified, the comparison budget was exhausted: {}",
+                unverified.len(),
+                package_name,
+                unverified
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        info!(
+            "Verified package '{}': {} of {} versions matching '{}' pass using {} comparisons",
+            package_name,
+            sorted_versions.len() - failing.len() - unverified.len(),
+            sorted_versions.len(),
+            lock_requirement.cloned().unwrap_or(VersionReq::STAR),
+            total_comparisons
+        );
+
+        pin(validator, &version)?;
+        return Ok(PackageResolution {
+            requirement: lock_requirement.cloned().unwrap_or(VersionReq::STAR),
+            range,
+            comparisons: total_comparisons,
+            candidates: all_versions.len(),
+            yanked,
+            yanked_at_bounds: vec![],
+            failing,
+            unverified,
+        });
+    }
+    let mut output_req = loop {
+        let output_req = if options.linear_search {
+            linear_search_bounds(&version, all_versions.clone(), &mut validator_fn)?
+        } else if options.granularity == Granularity::Patch {
+            two_level_search_bounds(&version, all_versions.clone(), &mut validator_fn)?
+        } else {
+            // The candidates are already one per line
+            binary_search_bounds(&version, all_versions.clone(), &mut validator_fn)?
+        };
+
+        // The versions right outside the range are confirmed locally when docs.rs alone rejected them
+        let first = sorted_versions.iter().position(|v| output_req.matches(v));
+        let last = sorted_versions.iter().rposition(|v| output_req.matches(v));
+        let unconfirmed = [
+            first.and_then(|index| index.checked_sub(1)),
+            last.map(|index| index + 1),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|index| sorted_versions.get(index))
+        .filter(|v| docsrs_failed.borrow().contains(v) && !confirm_locally.borrow().contains(v))
+        .cloned()
+        .collect::<Vec<_>>();
+        if !unconfirmed.is_empty() {
+            for unconfirmed_version in unconfirmed {
                 info!(
-                    "Checking package '{}' with version '{}'...OK",
-                    package_name, version
+                    "Confirming locally that version '{}' of '{}', failing on docs.rs, bounds the range",
+                    unconfirmed_version, package_name
+                );
+                confirm_locally.borrow_mut().insert(unconfirmed_version);
+            }
+            continue;
+        }
+
+        // The baseline passed the tests already, the bounds failing them are left out of the next search
+        if confirm.is_some() {
+            let bounds = [first, last]
+                .into_iter()
+                .flatten()
+                .map(|index| &sorted_versions[index])
+                .filter(|bound| **bound != version)
+                .collect::<BTreeSet<_>>();
+            confirming.set(true);
+            let mut rejected = false;
+            for bound in bounds {
+                if !validator_fn(bound)? {
+                    info!(
+                        "Version '{}' of '{}' fails the tests, searching again without it",
+                        bound, package_name
+                    );
+                    failed_confirmation.borrow_mut().insert(bound.clone());
+                    rejected = true;
+                }
+            }
+            confirming.set(false);
+            if rejected {
+                continue;
+            }
+        }
+
+        break output_req;
+    };
+
+    // The bisection assumes that the passing versions are contiguous: a version passing right past a
+    // failing bound reveals a hole, which the bisection may also have stepped over inside the range
+    if !options.linear_search {
+        let first = sorted_versions.iter().position(|v| output_req.matches(v));
+        let last = sorted_versions.iter().rposition(|v| output_req.matches(v));
+        let beyond_bounds = [
+            first.and_then(|index| index.checked_sub(2)),
+            last.map(|index| index + 2),
+        ];
+        for index in beyond_bounds.into_iter().flatten() {
+            let Some(beyond) = sorted_versions.get(index) else {
+                continue;
+            };
+            if validator_fn(beyond)? {
+                warn!(
+                    "Version '{}' of '{}' passes although the versions between it and the range found fail: \
+                    the passing versions are not contiguous and the bisection may have missed failing versions \
+                    within '{}', use --linear-search to validate every candidate",
+                    beyond, package_name, output_req
                 );
-                Ok(true)
             }
         }
+    }
+
+    let matching_versions = all_versions.iter().filter(|v| output_req.matches(v));
+    let range = matching_versions
+        .clone()
+        .min()
+        .zip(matching_versions.max())
+        .map(|(min, max)| VersionRange {
+            min: min.clone(),
+            max: max.clone(),
+        });
+
+    // The bounds come from the candidates so they are never yanked themselves, but a yanked version
+    // right past a bound was not validated and may have extended the range
+    let yanked_at_bounds = match &range {
+        Some(range) => {
+            let below = all_versions.iter().filter(|v| **v < range.min).max();
+            let above = all_versions.iter().filter(|v| **v > range.max).min();
+            considered_versions
+                .iter()
+                .filter(|v| v.yanked)
+                .map(|v| &v.version)
+                .filter(|v| {
+                    (**v < range.min && below.is_none_or(|below| *v > below))
+                        || (**v > range.max && above.is_none_or(|above| *v < above))
+                })
+                .cloned()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        }
+        None => vec![],
     };
+    if !yanked_at_bounds.is_empty() {
+        info!(
+            "Yanked versions of '{}' sit at the boundary of the range found and were not validated: {}",
+            package_name,
+            yanked_at_bounds
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
 
-    let output_req = binary_search_bounds(&version, all_versions, &mut validator_fn)?;
+    if options.minimize
+        && let Some(range) = &range
+    {
+        info!(
+            "Minimum compatible version of '{}' is '{}'",
+            package_name, range.min
+        );
+        output_req = VersionReq {
+            comparators: vec![Comparator {
+                op: Op::GreaterEq,
+                major: range.min.major,
+                minor: Some(range.min.minor),
+                patch: Some(range.min.patch),
+                pre: range.min.pre.clone(),
+            }],
+        };
+    }
 
     // Determine number of comparisons
     let total_comparisons = comparison_count.load(std::sync::atomic::Ordering::Acquire);
@@ -319,26 +1929,132 @@ fn resolve_package(
     );
 
     // Set dependency back to default
-    validator
-        .set_dependency(package_name.to_string(), version)
-        .unwrap();
-    Ok(output_req)
+    pin(validator, &version)?;
+    Ok(PackageResolution {
+        requirement: output_req,
+        range,
+        comparisons: total_comparisons,
+        candidates: all_versions.len(),
+        yanked,
+        yanked_at_bounds,
+        failing: vec![],
+        unverified: vec![],
+    })
+}
+
+/// Warn when the checksum recorded in the lock file for `version` of the searched crate differs from
+/// the checksum published by the registry. Versions missing from the lock file are not checked.
+fn verify_checksum(validator: &dyn RepoValidator, target: &SearchTarget, version: &Version) {
+    let Some(published) = target
+        .information
+        .versions
+        .iter()
+        .find(|v| &v.version == version)
+        .map(|v| &v.checksum)
+    else {
+        return;
+    };
+    match validator.locked_checksum(target.name, version) {
+        Some(locked) if &locked != published => warn!(
+            "Checksum mismatch for '{}' version '{}': Cargo.lock records {} but the registry publishes {}",
+            target.name, version, locked, published
+        ),
+        Some(_) => debug!(
+            "Checksum of '{}' version '{}' verified",
+            target.name, version
+        ),
+        None => debug!(
+            "No checksum in Cargo.lock for '{}' version '{}', not verified",
+            target.name, version
+        ),
+    }
+}
+
+/// Keep the newest of `versions` on each line of `granularity`, the oldest version, and `baseline`
+/// which the search starts from. Each line is represented by its newest version even on the line of
+/// the baseline, so the upper bound found covers its whole line, while the lower bound is the newest
+/// version of the oldest working line (the older ones being untested), unless the oldest version
+/// works.
+fn collapse_candidates(
+    versions: &[Version],
+    baseline: &Version,
+    granularity: Granularity,
+) -> Vec<Version> {
+    let line = |version: &Version| match granularity {
+        Granularity::Major if version.major > 0 => (version.major, 0, 0),
+        Granularity::Major if version.minor > 0 => (0, version.minor, 0),
+        Granularity::Major => (0, 0, version.patch),
+        Granularity::Minor => (version.major, version.minor, 0),
+        Granularity::Patch => (version.major, version.minor, version.patch),
+    };
+
+    let mut newest: BTreeMap<(u64, u64, u64), &Version> = BTreeMap::new();
+    for version in versions {
+        let entry = newest.entry(line(version)).or_insert(version);
+        if version > *entry {
+            *entry = version;
+        }
+    }
+    let mut candidates = newest.into_values().cloned().collect::<Vec<_>>();
+    for kept in versions.iter().min().into_iter().chain([baseline]) {
+        if !candidates.contains(kept) {
+            candidates.push(kept.clone());
+        }
+    }
+    candidates
 }
 
+/// Search the widest range of `versions` around `initial_version` that passes `validator`, as a
+/// requirement.
+///
+/// Versions are ordered by SemVer precedence, so `1.0.0-rc.1 < 1.0.0-rc.2 < 1.0.0`. Bounds keep the
+/// prerelease of their version, which `VersionReq::matches` requires for a prerelease to match: the
+/// validated bounds always match the requirement, while prereleases strictly inside the range only
+/// do when they share the version of a bound, as with Cargo. Build metadata cannot be expressed in a
+/// requirement and is ignored by the bounds, registries do not publish versions only differing by it.
 fn binary_search_bounds(
     initial_version: &Version,
     mut versions: Vec<Version>,
     validator: &mut impl FnMut(&Version) -> Result<bool, Error>,
 ) -> Result<VersionReq, Error> {
-    // First filter out versions that do not match the requirement and remove duplicates
+    // Sort by precedence and remove duplicates
     versions.sort();
+    versions.dedup();
 
-    // Find the index of the initial version
-    let mut left_invalid = None;
-    let mut left_valid = versions
+    // A single candidate is validated once, the left and right searches would check the same version.
+    // A failing candidate keeps its exact requirement: simplified against itself it would become `*`
+    if let [only_version] = versions.as_slice() {
+        if validator(only_version)? {
+            return Ok(simplify_version_req(VersionReq::STAR, &versions));
+        }
+        return Ok(VersionReq {
+            comparators: vec![Comparator {
+                op: Op::Exact,
+                major: only_version.major,
+                minor: Some(only_version.minor),
+                patch: Some(only_version.patch),
+                pre: only_version.pre.clone(),
+            }],
+        });
+    }
+
+    let initial_index = versions
         .iter()
         .position(|v| *v == *initial_version)
         .unwrap();
+    let (min_index, max_index) = bisect_bounds(initial_index, &versions, validator)?;
+    range_requirement(initial_version, &versions, min_index, max_index)
+}
+
+/// Bisect the sorted `versions` on each side of the valid `initial_index` for the oldest and newest
+/// passing versions, `None` for a side where the outermost version passes.
+fn bisect_bounds(
+    initial_index: usize,
+    versions: &[Version],
+    validator: &mut impl FnMut(&Version) -> Result<bool, Error>,
+) -> Result<(Option<usize>, Option<usize>), Error> {
+    let mut left_invalid = None;
+    let mut left_valid = initial_index;
     let mut right_valid = left_valid;
     let mut right_invalid = None;
 
@@ -358,6 +2074,8 @@ fn binary_search_bounds(
                     left_invalid = Some(mid_index);
                 }
             }
+            // The initial version is the oldest one, it is already known to be valid
+            None if left_valid == 0 => break,
             None => {
                 let is_valid = validator(&versions[0])?;
                 if is_valid {
@@ -385,6 +2103,8 @@ fn binary_search_bounds(
                     right_invalid = Some(mid_index);
                 }
             }
+            // The initial version is the newest one, it is already known to be valid
+            None if right_valid == versions.len() - 1 => break,
             None => {
                 let is_valid = validator(&versions[versions.len() - 1])?;
                 if is_valid {
@@ -396,11 +2116,151 @@ fn binary_search_bounds(
         }
     }
 
-    // Construct the resulting VersionReq
+    Ok((
+        left_invalid.map(|_| left_valid),
+        right_invalid.map(|_| right_valid),
+    ))
+}
+
+/// Search like [`binary_search_bounds`], first over the newest version of each minor line and then
+/// over the patches of the lines bounding the range, so that most validations bisect the minor lines
+/// rather than every patch release. The patches are only bisected when the bound does not fall on a
+/// minor release, which costs a single validation per side otherwise. Versions are validated at most
+/// once.
+fn two_level_search_bounds(
+    initial_version: &Version,
+    mut versions: Vec<Version>,
+    validator: &mut impl FnMut(&Version) -> Result<bool, Error>,
+) -> Result<VersionReq, Error> {
+    versions.sort();
+    versions.dedup();
+    if versions.len() == 1 {
+        return binary_search_bounds(initial_version, versions, validator);
+    }
+
+    // The patch searches start from the line representatives validated by the line search
+    let mut outcomes = BTreeMap::new();
+    let validator = &mut |version: &Version| match outcomes.get(version) {
+        Some(passed) => Ok(*passed),
+        None => {
+            let passed = validator(version)?;
+            outcomes.insert(version.clone(), passed);
+            Ok(passed)
+        }
+    };
+
+    let mut lines = collapse_candidates(&versions, initial_version, Granularity::Minor);
+    lines.sort();
+    let initial_index = lines.iter().position(|v| *v == *initial_version).unwrap();
+    let (min_line, max_line) = bisect_bounds(initial_index, &lines, validator)?;
+
+    // The failing line representative and the versions up to the oldest working one. Compatibility
+    // usually breaks at a minor release, the version right after the failing representative is then
+    // the bound and the patches are not bisected
+    let min_version = match min_line {
+        Some(min_line) => {
+            let patches = versions
+                .iter()
+                .filter(|v| **v >= lines[min_line - 1] && **v <= lines[min_line])
+                .cloned()
+                .collect::<Vec<_>>();
+            if validator(&patches[1])? {
+                Some(patches[1].clone())
+            } else {
+                let (min_index, _) = bisect_bounds(patches.len() - 2, &patches[1..], validator)?;
+                min_index.map(|index| patches[index + 1].clone())
+            }
+        }
+        None => None,
+    };
+
+    // The newest working line representative and the versions up to the failing one, the version
+    // right after the representative failing when the next minor release is the breaking one
+    let max_version = match max_line {
+        Some(max_line) => {
+            let patches = versions
+                .iter()
+                .filter(|v| **v >= lines[max_line] && **v <= lines[max_line + 1])
+                .cloned()
+                .collect::<Vec<_>>();
+            if !validator(&patches[1])? {
+                Some(patches[0].clone())
+            } else {
+                let (_, max_index) = bisect_bounds(0, &patches[1..], validator)?;
+                max_index.map(|index| patches[index + 1].clone())
+            }
+        }
+        None => None,
+    };
+
+    let position = |version: Option<Version>| {
+        version.and_then(|version| versions.iter().position(|v| *v == version))
+    };
+    range_requirement(
+        initial_version,
+        &versions,
+        position(min_version),
+        position(max_version),
+    )
+}
+
+/// Validate `versions` one by one outward from `initial_version`, stopping at the first failure on
+/// each side, as a requirement. Slower than [`binary_search_bounds`] but exact when the passing
+/// versions are not contiguous (e.g. a crate breaking at 1.3 and compatible again at 1.5): the range
+/// never crosses a failing version.
+fn linear_search_bounds(
+    initial_version: &Version,
+    mut versions: Vec<Version>,
+    validator: &mut impl FnMut(&Version) -> Result<bool, Error>,
+) -> Result<VersionReq, Error> {
+    versions.sort();
+    versions.dedup();
+
+    let initial_index = versions
+        .iter()
+        .position(|v| *v == *initial_version)
+        .unwrap();
+
+    let mut min_index = initial_index;
+    let mut lower_bounded = false;
+    for index in (0..initial_index).rev() {
+        if !validator(&versions[index])? {
+            lower_bounded = true;
+            break;
+        }
+        min_index = index;
+    }
+
+    let mut max_index = initial_index;
+    let mut upper_bounded = false;
+    for (index, version) in versions.iter().enumerate().skip(initial_index + 1) {
+        if !validator(version)? {
+            upper_bounded = true;
+            break;
+        }
+        max_index = index;
+    }
+
+    range_requirement(
+        initial_version,
+        &versions,
+        lower_bounded.then_some(min_index),
+        upper_bounded.then_some(max_index),
+    )
+}
+
+/// Requirement matching the sorted `versions` from index `min_index` to `max_index`, a missing index
+/// leaving that side unbounded.
+fn range_requirement(
+    initial_version: &Version,
+    versions: &[Version],
+    min_index: Option<usize>,
+    max_index: Option<usize>,
+) -> Result<VersionReq, Error> {
     let mut bounds = vec![];
 
-    if left_invalid.is_some() {
-        let min_version = versions[left_valid].clone();
+    if let Some(min_index) = min_index {
+        let min_version = versions[min_index].clone();
 
         bounds.push(Comparator {
             op: semver::Op::GreaterEq,
@@ -411,8 +2271,8 @@ fn binary_search_bounds(
         });
     }
 
-    if right_invalid.is_some() {
-        let max_version = versions[right_valid].clone();
+    if let Some(max_index) = max_index {
+        let max_version = versions[max_index].clone();
 
         bounds.push(Comparator {
             op: semver::Op::LessEq,
@@ -426,8 +2286,17 @@ fn binary_search_bounds(
         comparators: bounds,
     };
 
-    // Simplify the version requirement if possible
-    Ok(simplify_version_req(version_req, &versions))
+    // Simplify the version requirement if possible, the validated baseline must remain included
+    let simplified = simplify_version_req(version_req.clone(), versions);
+    if !simplified.matches(initial_version) {
+        warn!(
+            "Simplified requirement '{}' excludes the validated version '{}', keeping '{}'",
+            simplified, initial_version, version_req
+        );
+        return Ok(version_req);
+    }
+
+    Ok(simplified)
 }
 
 fn simplify_version_req(version_req: VersionReq, versions: &[Version]) -> VersionReq {
@@ -455,11 +2324,30 @@ fn simplify_version_req(version_req: VersionReq, versions: &[Version]) -> Versio
         };
     }
 
-    // Try simplify to caret requirements (attempt)
+    // Try simplify to caret requirements (attempt). A caret is based on the lower bound: when only an
+    // upper bound exists, the oldest matching version is the actual lower bound
+    let Some(lower_bound) = version_req
+        .comparators
+        .iter()
+        .find(|c| c.op == Op::GreaterEq)
+        .cloned()
+        .or_else(|| {
+            matching_versions.first().map(|oldest| Comparator {
+                op: Op::GreaterEq,
+                major: oldest.major,
+                minor: Some(oldest.minor),
+                patch: Some(oldest.patch),
+                pre: oldest.pre.clone(),
+            })
+        })
+    else {
+        return version_req;
+    };
+
     let mut proposal_caret = VersionReq {
         comparators: vec![Comparator {
             op: semver::Op::Caret,
-            major: version_req.comparators[0].major,
+            major: lower_bound.major,
             minor: None,
             patch: None,
             pre: Prerelease::EMPTY,
@@ -480,13 +2368,15 @@ fn simplify_version_req(version_req: VersionReq, versions: &[Version]) -> Versio
     }
 
     // Make caret more specific if possible
-    proposal_caret.comparators[0].minor = Some(version_req.comparators[0].minor.unwrap_or(0));
+    proposal_caret.comparators[0].minor = Some(lower_bound.minor.unwrap_or(0));
     if check_proposal(&proposal_caret) {
         return proposal_caret;
     }
 
-    // Make caret even more specific if possible
-    proposal_caret.comparators[0].patch = Some(version_req.comparators[0].patch.unwrap_or(0));
+    // Make caret even more specific if possible, a prerelease lower bound only matches prereleases
+    // of its own version through a caret carrying it
+    proposal_caret.comparators[0].patch = Some(lower_bound.patch.unwrap_or(0));
+    proposal_caret.comparators[0].pre = lower_bound.pre.clone();
     if check_proposal(&proposal_caret) {
         return proposal_caret;
     }
@@ -494,3 +2384,916 @@ fn simplify_version_req(version_req: VersionReq, versions: &[Version]) -> Versio
     // If no simplification was possible, return the original version_req
     version_req
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::MockRepoValidator;
+
+    fn versions(list: &[&str]) -> Vec<Version> {
+        list.iter().map(|v| Version::parse(v).unwrap()).collect()
+    }
+
+    fn req(requirement: &str) -> VersionReq {
+        VersionReq::parse(requirement).unwrap()
+    }
+
+    /// Validator function passing the versions of `foo` in `passing`, driving a
+    /// [`MockRepoValidator`] as the search does, along with a handle counting its checks.
+    fn mock_validator(
+        passing: &[&str],
+    ) -> (
+        MockRepoValidator,
+        impl FnMut(&Version) -> Result<bool, Error>,
+    ) {
+        let passing = versions(passing);
+        let mut validator = MockRepoValidator::new(move |_, version| passing.contains(version));
+        let handle = validator.clone();
+        let validator_fn = move |version: &Version| {
+            validator.set_dependency(
+                "foo".to_string(),
+                version.clone(),
+                &[DependencyKind::Normal],
+            )?;
+            Ok(validator.run_check(Check::Custom { command: &[] }).is_ok())
+        };
+        (handle, validator_fn)
+    }
+
+    /// Registry information of `name` publishing the `published` versions, flagged when yanked.
+    fn krate(name: &str, published: &[(&str, bool)]) -> Crate {
+        Crate {
+            name: name.to_string(),
+            description: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            versions: published
+                .iter()
+                .map(|(version, yanked)| crate::crates::CrateVersion {
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                    yanked: *yanked,
+                    version: Version::parse(version).unwrap(),
+                    checksum: String::new(),
+                    dependencies: None,
+                    rust_version: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Temporary directory of a test repository, removed on drop.
+    struct TestRepository(PathBuf);
+
+    impl Drop for TestRepository {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Resolver of the package written to a fresh directory named after `test`, from `manifest`
+    /// and the `lock` file if any, validating with `validator` against the registry `crates`.
+    ///
+    /// The directory is removed when the returned [`TestRepository`] is dropped, bound before the
+    /// resolver so that the resolver restores its files first.
+    fn resolver(
+        test: &str,
+        manifest: &str,
+        lock: Option<&str>,
+        crates: Vec<Crate>,
+        validator: MockRepoValidator,
+        options: ResolverOptions,
+    ) -> (TestRepository, Resolver) {
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-compat-resolver-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        if let Some(lock) = lock {
+            std::fs::write(dir.join("Cargo.lock"), lock).unwrap();
+        }
+
+        let targets = match crate::cargo::Cargo::from_path(&dir).unwrap() {
+            crate::cargo::Cargo::Single(package) => vec![*package],
+            crate::cargo::Cargo::Workspace(packages) => packages,
+        };
+        let resolver = Resolver::new(
+            targets,
+            dir.clone(),
+            crates
+                .into_iter()
+                .map(|krate| (krate.name.clone(), krate))
+                .collect(),
+            Box::new(validator),
+            BuildOptions {
+                packages: None,
+                features: None,
+                package_features: BTreeMap::new(),
+                all_features: false,
+                no_default_features: false,
+                release: false,
+                jobs: None,
+                target: None,
+            },
+            None,
+            ResolverOptions {
+                throttle: Duration::ZERO,
+                ..options
+            },
+        );
+        (TestRepository(dir), resolver)
+    }
+
+    const DEMO: &str = r#"[package]
+name = "demo"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+foo = "1"
+bar = "0.2"
+"#;
+
+    const MINORS: [&str; 7] = [
+        "1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0", "1.5.0", "1.6.0",
+    ];
+
+    #[test]
+    fn binary_search_finds_contiguous_range() {
+        let (handle, mut validator) = mock_validator(&["1.2.0", "1.3.0", "1.4.0"]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 3, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req(">=1.2.0, <=1.4.0"));
+        // Three bisection steps on each side
+        assert_eq!(handle.checks_run(), 6);
+    }
+
+    #[test]
+    fn binary_search_with_baseline_at_the_edges() {
+        let (_, mut validator) = mock_validator(&["1.0.0", "1.1.0", "1.2.0", "1.3.0"]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 0, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req("<=1.3.0"));
+
+        let (_, mut validator) = mock_validator(&["1.4.0", "1.5.0", "1.6.0"]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 6, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req("^1.4"));
+    }
+
+    #[test]
+    fn simplification_excluding_the_baseline_keeps_the_bounds() {
+        // `*` would cover every candidate but never matches the prerelease baseline
+        let candidates = versions(&["1.0.0-rc.1", "1.0.0"]);
+        let baseline = Version::parse("1.0.0-rc.1").unwrap();
+        assert_eq!(
+            range_requirement(&baseline, &candidates, Some(0), None).unwrap(),
+            req(">=1.0.0-rc.1")
+        );
+    }
+
+    #[test]
+    fn prerelease_bounds_match_their_versions() {
+        let candidates = versions(&["0.9.0", "1.0.0-rc.1", "1.0.0-rc.2", "1.0.0"]);
+        let baseline = Version::parse("1.0.0-rc.2").unwrap();
+        let requirement = |min_index, max_index| {
+            range_requirement(&baseline, &candidates, min_index, max_index).unwrap()
+        };
+
+        // Validated versions match, including the prerelease baseline, and 0.9.0 does not
+        let matched = |requirement: VersionReq| {
+            candidates
+                .iter()
+                .filter(|version| requirement.matches(version))
+                .map(Version::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(requirement(Some(1), None), req("^1.0.0-rc.1"));
+        assert_eq!(
+            matched(requirement(Some(1), None)),
+            ["1.0.0-rc.1", "1.0.0-rc.2", "1.0.0"]
+        );
+        assert_eq!(
+            requirement(Some(1), Some(2)),
+            req(">=1.0.0-rc.1, <=1.0.0-rc.2")
+        );
+        assert_eq!(
+            matched(requirement(Some(1), Some(2))),
+            ["1.0.0-rc.1", "1.0.0-rc.2"]
+        );
+    }
+
+    #[test]
+    fn caret_simplification_of_each_kind_of_bounds() {
+        let candidates = versions(&["1.0.0", "1.1.0", "1.2.0", "2.0.0", "2.1.0"]);
+        let baseline = Version::new(1, 1, 0);
+        let requirement = |min_index, max_index| {
+            range_requirement(&baseline, &candidates, min_index, max_index).unwrap()
+        };
+
+        // No caret covers the lower bound and the next major
+        assert_eq!(requirement(Some(1), None), req(">=1.1.0"));
+        // The oldest candidate is the actual lower bound of an upper bound
+        assert_eq!(requirement(None, Some(2)), req("^1"));
+        assert_eq!(requirement(Some(1), Some(2)), req("^1.1"));
+    }
+
+    #[test]
+    fn binary_search_assumes_contiguous_versions() {
+        // 1.1 and 1.2 fail, but the bisection only probes them when the oldest version fails too
+        let passing = ["1.0.0", "1.3.0", "1.4.0", "1.5.0", "1.6.0"];
+        let (_, mut validator) = mock_validator(&passing);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 5, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, VersionReq::STAR);
+
+        // The linear search stops at the hole instead
+        let (_, mut validator) = mock_validator(&passing);
+        let requirement =
+            linear_search_bounds(&Version::new(1, 5, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req("^1.3"));
+    }
+
+    #[test]
+    fn binary_search_when_every_other_version_fails() {
+        let (_, mut validator) = mock_validator(&[]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 3, 0), versions(&MINORS), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req("=1.3.0"));
+
+        // A single failing candidate is validated once and pinned exactly, a passing one is not
+        let (handle, mut validator) = mock_validator(&[]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 0, 0), versions(&["1.0.0"]), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, req("=1.0.0"));
+        assert_eq!(handle.checks_run(), 1);
+
+        let (_, mut validator) = mock_validator(&["1.0.0"]);
+        let requirement =
+            binary_search_bounds(&Version::new(1, 0, 0), versions(&["1.0.0"]), &mut validator)
+                .unwrap();
+        assert_eq!(requirement, VersionReq::STAR);
+    }
+
+    #[test]
+    fn bisect_bounds_of_contiguous_range() {
+        let (_, mut validator) = mock_validator(&["1.2.0", "1.3.0", "1.4.0"]);
+        assert_eq!(
+            bisect_bounds(3, &versions(&MINORS), &mut validator).unwrap(),
+            (Some(2), Some(4))
+        );
+
+        let (handle, mut validator) = mock_validator(&MINORS);
+        assert_eq!(
+            bisect_bounds(0, &versions(&MINORS), &mut validator).unwrap(),
+            (None, None)
+        );
+        assert_eq!(handle.checks_run(), 1);
+
+        let (_, mut validator) = mock_validator(&[]);
+        assert_eq!(
+            bisect_bounds(6, &versions(&MINORS), &mut validator).unwrap(),
+            (Some(6), None)
+        );
+    }
+
+    #[test]
+    fn bisect_bounds_stop_above_a_hole() {
+        // pass / fail / pass: 1.1 to 1.3 pass, 1.4 fails and everything from 1.5 passes. The bisection
+        // lands on 1.4 and bounds the range above it, leaving out the passing versions below the hole
+        let all = versions(&[
+            "1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0", "1.5.0", "1.6.0", "1.7.0", "1.8.0",
+        ]);
+        let (handle, mut validator) = mock_validator(&[
+            "1.1.0", "1.2.0", "1.3.0", "1.5.0", "1.6.0", "1.7.0", "1.8.0",
+        ]);
+        assert_eq!(
+            bisect_bounds(8, &all, &mut validator).unwrap(),
+            (Some(5), None)
+        );
+        // 1.0, 1.4, 1.6, 1.5 on the left, 1.8 being the baseline there is no right side
+        assert_eq!(handle.checks_run(), 4);
+
+        let requirement = range_requirement(&Version::new(1, 8, 0), &all, Some(5), None).unwrap();
+        assert!(!requirement.matches(&Version::new(1, 4, 0)));
+        assert!(!requirement.matches(&Version::new(1, 2, 0)));
+    }
+
+    #[test]
+    fn collapse_keeps_newest_of_each_line_oldest_and_baseline() {
+        let all = versions(&["1.0.0", "1.0.1", "1.1.0", "1.1.1", "1.1.2", "1.2.0"]);
+        let mut candidates = collapse_candidates(&all, &Version::new(1, 1, 1), Granularity::Minor);
+        candidates.sort();
+        assert_eq!(
+            candidates,
+            versions(&["1.0.0", "1.0.1", "1.1.1", "1.1.2", "1.2.0"])
+        );
+
+        let all = versions(&["0.1.0", "0.1.1", "0.2.0", "1.0.0", "1.2.0", "2.0.0"]);
+        let mut candidates = collapse_candidates(&all, &Version::new(0, 2, 0), Granularity::Major);
+        candidates.sort();
+        assert_eq!(
+            candidates,
+            versions(&["0.1.0", "0.1.1", "0.2.0", "1.2.0", "2.0.0"])
+        );
+
+        let candidates = collapse_candidates(&all, &Version::new(1, 0, 0), Granularity::Patch);
+        assert_eq!(candidates.len(), all.len());
+    }
+
+    /// Every `1.<minor>.<patch>` version for `minor` and `patch` below 10.
+    fn patch_releases() -> Vec<Version> {
+        (0..10)
+            .flat_map(|minor| (0..10).map(move |patch| Version::new(1, minor, patch)))
+            .collect()
+    }
+
+    /// Number of checks run by the two-level and the plain binary searches for the versions of
+    /// [`patch_releases`] matching `valid`, after checking that both find `valid`.
+    fn search_checks(valid: &str, baseline: Version) -> (usize, usize) {
+        let valid = req(valid);
+        let passing = patch_releases()
+            .into_iter()
+            .filter(|v| valid.matches(v))
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+        let passing = passing.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let (two_level_checks, mut validator) = mock_validator(&passing);
+        let two_level =
+            two_level_search_bounds(&baseline, patch_releases(), &mut validator).unwrap();
+        let (binary_checks, mut validator) = mock_validator(&passing);
+        let binary = binary_search_bounds(&baseline, patch_releases(), &mut validator).unwrap();
+
+        assert_eq!(two_level, valid);
+        assert_eq!(binary, valid);
+        (two_level_checks.checks_run(), binary_checks.checks_run())
+    }
+
+    #[test]
+    fn two_level_search_needs_fewer_comparisons() {
+        // Breaking changes at minor releases, the patches are not bisected
+        let (two_level, binary) = search_checks(">=1.3.0, <=1.7.9", Version::new(1, 5, 9));
+        assert_eq!((two_level, binary), (8, 12));
+
+        // Bounds inside a minor line cost at most one more validation per side
+        let (two_level, binary) = search_checks(">=1.2.5, <=1.7.3", Version::new(1, 5, 9));
+        assert!(two_level <= binary + 2, "{} > {} + 2", two_level, binary);
+    }
+
+    #[test]
+    fn two_level_search_over_non_contiguous_lines() {
+        // Minor lines 1.1, 1.2 and 1.4 to 1.6 were never published
+        let all = versions(&[
+            "1.0.0", "1.0.1", "1.3.0", "1.3.1", "1.3.2", "1.7.0", "1.7.1", "1.9.0",
+        ]);
+        let (_, mut validator) = mock_validator(&["1.3.1", "1.3.2", "1.7.0", "1.7.1"]);
+        let requirement =
+            two_level_search_bounds(&Version::new(1, 7, 1), all.clone(), &mut validator).unwrap();
+        assert_eq!(requirement, req(">=1.3.1, <=1.7.1"));
+
+        let (_, mut validator) = mock_validator(&["1.3.1", "1.3.2", "1.7.0", "1.7.1", "1.9.0"]);
+        let requirement =
+            two_level_search_bounds(&Version::new(1, 3, 2), all, &mut validator).unwrap();
+        assert_eq!(requirement, req("^1.3.1"));
+    }
+
+    #[test]
+    fn two_level_search_matches_binary_search() {
+        let all = versions(&[
+            "1.0.0", "1.0.1", "1.0.2", "1.1.0", "1.1.1", "1.1.2", "1.2.0", "1.2.1", "1.3.0",
+            "1.3.1", "1.3.2",
+        ]);
+        let passing = ["1.1.1", "1.1.2", "1.2.0", "1.2.1", "1.3.0"];
+
+        let (handle, mut validator) = mock_validator(&passing);
+        let requirement =
+            two_level_search_bounds(&Version::new(1, 2, 1), all.clone(), &mut validator).unwrap();
+        assert_eq!(requirement, req(">=1.1.1, <=1.3.0"));
+
+        let (_, mut validator) = mock_validator(&passing);
+        let binary = binary_search_bounds(&Version::new(1, 2, 1), all, &mut validator).unwrap();
+        assert_eq!(requirement, binary);
+        assert!(handle.checks_run() > 0);
+    }
+
+    #[test]
+    fn populate_default_selects_latest_version_missing_from_lock_file() {
+        // bar is not in the lock file: the latest version matching its requirement is selected
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let (_repository, mut resolver) = resolver(
+            "populate-missing-from-lock",
+            DEMO,
+            Some(lock),
+            vec![
+                krate("foo", &[("1.2.0", false), ("1.3.0", false)]),
+                krate(
+                    "bar",
+                    &[("0.2.0", false), ("0.2.3", false), ("0.3.0", false)],
+                ),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        resolver.populate_default().unwrap();
+
+        assert_eq!(resolver.packages()["foo"], Version::new(1, 2, 0));
+        assert_eq!(resolver.packages()["bar"], Version::new(0, 2, 3));
+    }
+
+    /// Validator writing `lock` as the Cargo.lock of the repository at `path` when asked to generate
+    /// it, as cargo does for a repository without one.
+    struct LockfileGenerator {
+        inner: MockRepoValidator,
+        path: PathBuf,
+        lock: &'static str,
+    }
+
+    impl RepoValidator for LockfileGenerator {
+        fn set_dependency_req(
+            &mut self,
+            name: String,
+            version_req: VersionReq,
+            kind: DependencyKind,
+        ) -> Result<(), Error> {
+            self.inner.set_dependency_req(name, version_req, kind)
+        }
+
+        fn set_dependency(
+            &mut self,
+            name: String,
+            version: Version,
+            kinds: &[DependencyKind],
+        ) -> Result<(), Error> {
+            self.inner.set_dependency(name, version, kinds)
+        }
+
+        fn set_locked_version(&mut self, name: String, version: Version) -> Result<(), Error> {
+            self.inner.set_locked_version(name, version)
+        }
+
+        fn generate_lockfile(&mut self) -> Result<(), Error> {
+            std::fs::write(self.path.join("Cargo.lock"), self.lock)?;
+            Ok(())
+        }
+
+        fn run_check(&mut self, check: Check) -> Result<(), Either<ValidationError, Error>> {
+            self.inner.run_check(check)
+        }
+    }
+
+    #[test]
+    fn missing_lock_file_is_generated_to_select_the_baseline() {
+        // The generated lock file holds an older foo than the latest matching its requirement
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let (_repository, mut resolver) = resolver(
+            "generated-lock",
+            DEMO,
+            None,
+            vec![
+                krate("foo", &[("1.2.0", false), ("1.3.0", false)]),
+                krate("bar", &[("0.2.0", false), ("0.2.3", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        let path = resolver.path.clone();
+        resolver.validator = Box::new(LockfileGenerator {
+            inner: MockRepoValidator::new(|_, _| true),
+            path: path.clone(),
+            lock,
+        });
+        resolver.populate_default().unwrap();
+
+        assert_eq!(resolver.packages()["foo"], Version::new(1, 2, 0));
+        assert_eq!(resolver.packages()["bar"], Version::new(0, 2, 3));
+        assert!(path.join("Cargo.lock").exists());
+
+        // The repository is left without a lock file, as it was found
+        drop(resolver);
+        assert!(!path.join("Cargo.lock").exists());
+    }
+
+    #[test]
+    fn patched_dependency_is_not_searched() {
+        let manifest = format!("{DEMO}\n[patch.crates-io]\nfoo = {{ path = \"../foo\" }}\n");
+        let (_repository, mut resolver) = resolver(
+            "patched",
+            &manifest,
+            None,
+            vec![
+                krate("foo", &[("1.2.0", false)]),
+                krate("bar", &[("0.2.0", false), ("0.2.3", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        assert!(
+            resolver.targets[0]
+                .dependencies
+                .iter()
+                .any(|dep| dep.crate_name == "foo" && dep.patched)
+        );
+        resolver.populate_default().unwrap();
+
+        assert!(!resolver.packages().contains_key("foo"));
+        assert_eq!(resolver.packages()["bar"], Version::new(0, 2, 3));
+    }
+
+    #[test]
+    fn crate_with_only_yanked_matching_versions_is_skipped() {
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let (_repository, mut resolver) = resolver(
+            "only-yanked",
+            DEMO,
+            Some(lock),
+            vec![
+                krate("foo", &[("1.1.0", true), ("1.2.0", true), ("2.0.0", false)]),
+                krate("bar", &[("0.2.0", false), ("0.2.1", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        resolver.populate_default().unwrap();
+
+        let requirements = resolver.resolve().unwrap();
+        assert!(!requirements.contains_key("foo"));
+        assert!(requirements.contains_key("bar"));
+        assert_eq!(
+            resolver.report().skipped["foo"],
+            "No available version of 'foo' matches '^1' (2 matching versions are yanked)"
+        );
+        assert!(!resolver.report().requirements.contains_key("foo"));
+        assert!(!resolver.packages().contains_key("foo"));
+    }
+
+    #[test]
+    fn skipped_crate_without_matching_version_records_the_reason() {
+        // The locked foo is not published anymore, and no other version matches its requirement
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let (_repository, mut resolver) = resolver(
+            "unpublished-baseline",
+            DEMO,
+            Some(lock),
+            vec![
+                krate("foo", &[("2.0.0", false)]),
+                krate("bar", &[("0.2.0", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        resolver.populate_default().unwrap();
+
+        assert!(resolver.check_baseline().unwrap().is_none());
+        assert_eq!(
+            resolver.report().skipped["foo"],
+            "No available version of 'foo' matches '^1' (no published version matches)"
+        );
+    }
+
+    #[test]
+    fn yanked_baseline_is_replaced_by_the_latest_available_version() {
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let (_repository, mut resolver) = resolver(
+            "yanked-baseline",
+            DEMO,
+            Some(lock),
+            vec![
+                krate(
+                    "foo",
+                    &[
+                        ("1.1.0", false),
+                        ("1.2.0", true),
+                        ("1.3.0", false),
+                        ("2.0.0", false),
+                    ],
+                ),
+                krate("bar", &[("0.2.0", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions::default(),
+        );
+        resolver.populate_default().unwrap();
+
+        assert!(resolver.check_baseline().unwrap().is_none());
+        assert_eq!(resolver.packages()["foo"], Version::new(1, 3, 0));
+        assert!(resolver.report().skipped.is_empty());
+    }
+
+    #[test]
+    fn crate_whose_matching_versions_are_all_yanked_is_left_as_it_is() {
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.2.0\"\n";
+        let forced = std::sync::Arc::new(std::sync::Mutex::new(BTreeSet::new()));
+        let validator = MockRepoValidator::new({
+            let forced = forced.clone();
+            move |name, _| {
+                forced.lock().unwrap().insert(name.to_string());
+                true
+            }
+        });
+        let (_repository, mut resolver) = resolver(
+            "all-yanked",
+            DEMO,
+            Some(lock),
+            vec![
+                krate("foo", &[("1.1.0", true), ("1.2.0", true), ("2.0.0", false)]),
+                krate("bar", &[("0.2.0", false), ("0.2.1", false)]),
+            ],
+            validator,
+            ResolverOptions::default(),
+        );
+        let path = resolver.path.clone();
+        resolver.populate_default().unwrap();
+
+        assert!(resolver.check_baseline().unwrap().is_none());
+        assert!(resolver.report().skipped.contains_key("foo"));
+        let requirements = resolver.resolve().unwrap();
+        assert!(!requirements.contains_key("foo"));
+        assert!(requirements.contains_key("bar"));
+        resolver.write_cargo_toml_with_resolved_versions().unwrap();
+
+        // No version of foo was forced into a build, and its manifest entry and lock file entry
+        // are the ones it started with
+        assert!(!forced.lock().unwrap().contains("foo"));
+        let manifest = std::fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        let locked = std::fs::read_to_string(path.join("Cargo.lock")).unwrap();
+        assert!(manifest.contains("foo = \"1\"\n"));
+        assert_eq!(locked, lock);
+    }
+
+    /// Validator failing with an error, rather than a validation failure, whenever `name` is not at
+    /// its baseline version, or failing to pin `name` to its baseline with `unpinnable_baseline`.
+    struct ErroringValidator {
+        inner: MockRepoValidator,
+        name: &'static str,
+        baseline: Version,
+        unpinnable_baseline: bool,
+    }
+
+    impl RepoValidator for ErroringValidator {
+        fn set_dependency_req(
+            &mut self,
+            name: String,
+            version_req: VersionReq,
+            kind: DependencyKind,
+        ) -> Result<(), Error> {
+            self.inner.set_dependency_req(name, version_req, kind)
+        }
+
+        fn set_dependency(
+            &mut self,
+            name: String,
+            version: Version,
+            kinds: &[DependencyKind],
+        ) -> Result<(), Error> {
+            if self.unpinnable_baseline && name == self.name && version == self.baseline {
+                return Err(Error::Other(format!("cannot pin {}", name).into()));
+            }
+            self.inner.set_dependency(name, version, kinds)
+        }
+
+        fn set_locked_version(&mut self, name: String, version: Version) -> Result<(), Error> {
+            self.inner.set_locked_version(name, version)
+        }
+
+        fn run_check(&mut self, check: Check) -> Result<(), Either<ValidationError, Error>> {
+            if !self.unpinnable_baseline
+                && self
+                    .inner
+                    .version(self.name)
+                    .is_some_and(|version| *version != self.baseline)
+            {
+                return Err(Either::Right(Error::Other(
+                    format!("{} is not at its baseline", self.name).into(),
+                )));
+            }
+            self.inner.run_check(check)
+        }
+    }
+
+    #[test]
+    fn keep_going_pins_the_baseline_back_after_a_failed_search() {
+        let (_repository, mut resolver) = resolver(
+            "keep-going-baseline",
+            DEMO,
+            None,
+            vec![
+                krate(
+                    "foo",
+                    &[("1.0.0", false), ("1.1.0", false), ("1.2.0", false)],
+                ),
+                krate("bar", &[("0.2.0", false), ("0.2.1", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions {
+                keep_going: true,
+                ..ResolverOptions::default()
+            },
+        );
+        // bar is searched first and its first candidate errors, foo is searched next by the same
+        // validator and only passes when bar is back at its baseline
+        resolver.validator = Box::new(ErroringValidator {
+            inner: MockRepoValidator::new(|_, _| true),
+            name: "bar",
+            baseline: Version::new(0, 2, 1),
+            unpinnable_baseline: false,
+        });
+        resolver.populate_default().unwrap();
+
+        let requirements = resolver.resolve().unwrap();
+        assert_eq!(requirements["foo"], VersionReq::STAR);
+        assert!(resolver.report().errors.contains_key("bar"));
+        assert!(!resolver.report().errors.contains_key("foo"));
+    }
+
+    #[test]
+    fn search_fails_when_the_baseline_cannot_be_pinned_back() {
+        let foo = krate(
+            "foo",
+            &[("1.0.0", false), ("1.1.0", false), ("1.2.0", false)],
+        );
+        let mut job = SearchJob {
+            target: SearchTarget {
+                name: "foo",
+                baseline: Version::new(1, 1, 0),
+                information: &foo,
+                requirement: Some(req("^1.1")),
+                kinds: vec![DependencyKind::Normal],
+            },
+            validation_cache: BTreeMap::new(),
+            confirmation_cache: BTreeMap::new(),
+        };
+        let options = ResolverOptions {
+            throttle: Duration::ZERO,
+            ..ResolverOptions::default()
+        };
+
+        let mut validator = ErroringValidator {
+            inner: MockRepoValidator::new(|_, _| true),
+            name: "foo",
+            baseline: Version::new(1, 1, 0),
+            unpinnable_baseline: true,
+        };
+        let check = Check::Custom { command: &[] };
+        assert!(resolve_package(&mut job, &mut validator, check, None, &options, None).is_err());
+
+        // A candidate that could not be pinned is not cached as failing
+        assert!(!job.validation_cache.contains_key(&Version::new(1, 1, 0)));
+
+        // The search ends with the baseline pinned back
+        let mut validator = MockRepoValidator::new(|_, _| true);
+        let resolution =
+            resolve_package(&mut job, &mut validator, check, None, &options, None).unwrap();
+        assert_eq!(resolution.requirement, VersionReq::STAR);
+        assert_eq!(validator.version("foo"), Some(&Version::new(1, 1, 0)));
+    }
+
+    #[test]
+    fn prereleases_are_candidates_only_when_allowed() {
+        let foo = krate(
+            "foo",
+            &[("1.0.0", false), ("1.1.0", false), ("1.2.0-beta.1", false)],
+        );
+        let search = |baseline: &str, allow_prerelease: bool| {
+            let mut job = SearchJob {
+                target: SearchTarget {
+                    name: "foo",
+                    baseline: Version::parse(baseline).unwrap(),
+                    information: &foo,
+                    requirement: None,
+                    kinds: vec![DependencyKind::Normal],
+                },
+                validation_cache: BTreeMap::new(),
+                confirmation_cache: BTreeMap::new(),
+            };
+            let options = ResolverOptions {
+                throttle: Duration::ZERO,
+                allow_prerelease,
+                ..ResolverOptions::default()
+            };
+            // Whether the prerelease was validated
+            let prerelease_checked = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let checked = prerelease_checked.clone();
+            let mut validator = MockRepoValidator::new(move |_, version| {
+                if !version.pre.is_empty() {
+                    checked.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                true
+            });
+            let check = Check::Custom { command: &[] };
+            let resolution =
+                resolve_package(&mut job, &mut validator, check, None, &options, None).unwrap();
+            (
+                resolution.candidates,
+                prerelease_checked.load(std::sync::atomic::Ordering::Relaxed),
+            )
+        };
+
+        assert_eq!(search("1.1.0", false), (2, false));
+        assert_eq!(search("1.1.0", true), (3, true));
+        // A prerelease baseline stays a candidate, the search starts from it (the baseline is
+        // validated before the search)
+        assert_eq!(search("1.2.0-beta.1", false), (3, false));
+    }
+
+    #[test]
+    fn copy_repository_skips_artifacts_at_the_root_only() {
+        let source =
+            std::env::temp_dir().join(format!("cargo-compat-resolver-copy-{}", std::process::id()));
+        let destination = source.with_extension("copy");
+        let _ = std::fs::remove_dir_all(&source);
+        let _ = std::fs::remove_dir_all(&destination);
+        for file in [
+            "Cargo.toml",
+            "target/debug/out",
+            ".git/HEAD",
+            "src/target/mod.rs",
+        ] {
+            let path = source.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        copy_repository(&source, &destination).unwrap();
+        let copied = ["Cargo.toml", "src/target/mod.rs", "target", ".git"]
+            .map(|file| destination.join(file).exists());
+        std::fs::remove_dir_all(&source).unwrap();
+        std::fs::remove_dir_all(&destination).unwrap();
+        assert_eq!(copied, [true, true, false, false]);
+    }
+
+    #[test]
+    fn paths_outside_of_the_repository_are_detected() {
+        let root = std::env::temp_dir().join(format!(
+            "cargo-compat-resolver-outside-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("member")).unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]\n\n[workspace.dependencies]\nlocal = { path = \"member/local\" }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("member/Cargo.toml"),
+            "[package]\nname = \"member\"\n\n[dependencies]\nshared = { path = \"../../shared\" }\n\n[target.'cfg(unix)'.dependencies]\nsibling = { path = \"../sibling\" }\n",
+        )
+        .unwrap();
+
+        let outside = paths_outside_repository(
+            &root,
+            &[root.join("Cargo.toml"), root.join("member/Cargo.toml")],
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(outside, [root.parent().unwrap().join("shared")]);
+    }
+
+    #[test]
+    fn verify_reports_versions_past_the_budget_as_unverified() {
+        let (_repository, mut resolver) = resolver(
+            "verify-budget",
+            DEMO,
+            None,
+            vec![
+                krate(
+                    "foo",
+                    &[
+                        ("1.0.0", false),
+                        ("1.1.0", false),
+                        ("1.2.0", false),
+                        ("1.3.0", false),
+                    ],
+                ),
+                krate("bar", &[("0.2.0", false)]),
+            ],
+            MockRepoValidator::new(|_, _| true),
+            ResolverOptions {
+                strategy: Strategy::Verify,
+                max_comparisons: Some(2),
+                ..ResolverOptions::default()
+            },
+        );
+        resolver.populate_default().unwrap();
+        resolver.resolve().unwrap();
+
+        let report = resolver.report();
+        assert!(report.failing_versions.is_empty());
+        assert_eq!(
+            report.unverified_versions["foo"],
+            versions(&["1.2.0", "1.3.0"])
+        );
+        assert!(!report.unverified_versions.contains_key("bar"));
+    }
+}
@@ -1,37 +1,101 @@
 //! Validation layer that runs cargo build/test to verify candidate dependency sets.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
 
 use chrono::{DateTime, Utc};
 use either::Either;
 use log::{debug, warn};
-use semver::{Comparator, Op, Version, VersionReq};
+use semver::{Comparator, Op, Prerelease, Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
+use crate::crates::DependencyKind;
+
 /// Options controlling how cargo build is run.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct BuildOptions {
     pub packages: Option<Vec<String>>,
     pub features: Option<Vec<String>>,
+    /// Features enabled on a single package of the workspace, passed to cargo as `package/feature`
+    #[serde(default)]
+    pub package_features: BTreeMap<String, Vec<String>>,
+    /// Enable every feature of the selected packages
+    #[serde(default)]
+    pub all_features: bool,
+    /// Disable the default features of the selected packages
+    #[serde(default)]
+    pub no_default_features: bool,
     pub release: bool,
+    /// Number of parallel jobs passed to cargo, `None` keeps cargo's default
+    #[serde(default)]
+    pub jobs: Option<u32>,
+    /// Target triple passed to cargo, `None` builds for the host
+    #[serde(default)]
+    pub target: Option<String>,
 }
 
 impl BuildOptions {
+    /// Features given to cargo, those of a single package being prefixed by its name
+    pub fn feature_list(&self) -> Vec<String> {
+        self.features
+            .iter()
+            .flatten()
+            .cloned()
+            .chain(
+                self.package_features
+                    .iter()
+                    .flat_map(|(package, features)| {
+                        features
+                            .iter()
+                            .map(move |feature| format!("{}/{}", package, feature))
+                    }),
+            )
+            .collect()
+    }
+
     pub fn arguments(&self) -> impl Iterator<Item = String> + '_ {
+        let features = self.feature_list();
         self.packages
             .as_ref()
             .into_iter()
             .flat_map(|pkgs| pkgs.iter().map(|p| ["--package".to_string(), p.clone()]))
             .flatten()
             .chain(
-                self.features
-                    .as_ref()
+                Some(features)
+                    .filter(|features| !features.is_empty())
                     .into_iter()
-                    .flat_map(|feats| ["--features".to_string(), feats.join(",")]),
+                    .flat_map(|features| ["--features".to_string(), features.join(",")]),
             )
+            .chain(if self.all_features {
+                Some("--all-features".to_string())
+            } else {
+                None
+            })
+            .chain(if self.no_default_features {
+                Some("--no-default-features".to_string())
+            } else {
+                None
+            })
             .chain(if self.release {
                 Some("--release".to_string())
             } else {
                 None
             })
+            .chain(
+                self.jobs
+                    .into_iter()
+                    .flat_map(|jobs| ["--jobs".to_string(), jobs.to_string()]),
+            )
+            .chain(
+                self.target
+                    .iter()
+                    .flat_map(|target| ["--target".to_string(), target.clone()]),
+            )
     }
 }
 
@@ -39,30 +103,52 @@ impl BuildOptions {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TestOptions {
     pub filters: Vec<String>,
+    /// Build and run every target (library, binaries, tests, benches and examples)
+    #[serde(default)]
+    pub all_targets: bool,
+    /// Run the doc tests along with `all_targets`, which leaves them out, in a separate
+    /// `cargo test --doc` run. A plain `cargo test` already runs them
+    #[serde(default)]
+    pub doc: bool,
 }
 
 impl TestOptions {
     pub fn arguments(&self) -> impl Iterator<Item = String> + '_ {
-        std::iter::once("--".to_string())
-            .filter(|_| !self.filters.is_empty())
+        std::iter::once("--all-targets".to_string())
+            .filter(|_| self.all_targets)
+            .chain(std::iter::once("--".to_string()).filter(|_| !self.filters.is_empty()))
             .chain(
                 self.filters
                     .iter()
                     .flat_map(|f| ["--test".to_string(), f.clone()]),
             )
     }
+
+    /// Arguments of the `cargo test --doc` run following the main one, cargo does not accept `--doc`
+    /// along with `--all-targets`
+    pub fn doc_arguments(&self) -> Option<Vec<String>> {
+        (self.all_targets && self.doc).then(|| vec!["--doc".to_string()])
+    }
 }
 
-/// A check to run against the repository: either a build or a test run.
+/// A check to run against the repository: a build, a type check only (`cargo check`, no codegen), a
+/// test run or a user-provided command.
 #[derive(Clone, Copy)]
 pub enum Check<'a> {
     Build {
         build_opts: &'a BuildOptions,
     },
+    Check {
+        build_opts: &'a BuildOptions,
+    },
     RunTest {
         build_opts: &'a BuildOptions,
         test_opts: &'a TestOptions,
     },
+    /// A program and its arguments, run in the repository and failing on a non-zero exit status
+    Custom {
+        command: &'a [String],
+    },
 }
 
 /// A non-successful validation outcome with details to aid troubleshooting.
@@ -78,15 +164,109 @@ pub struct ValidationError {
     pub tests_failed: bool,
     pub build_failure: Option<BuildFailure>,
     pub runned_at: DateTime<Utc>,
+    /// The check was killed because it exceeded the timeout
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Features passed to cargo with `--features` for this check
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl ValidationError {
+    /// Short description of what failed
+    pub fn reason(&self) -> &'static str {
+        if self.timed_out {
+            "the check timed out"
+        } else if self.tests_failed {
+            "the tests failed"
+        } else {
+            "the build failed"
+        }
+    }
+}
+
+/// Readable rendering of the failure, with the cargo output as plain text.
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = self.reason();
+        let mut reason = reason.chars();
+        if let Some(first) = reason.next() {
+            write!(f, "{}{}", first.to_uppercase(), reason.as_str())?;
+        }
+        write!(
+            f,
+            " at {}",
+            self.runned_at
+                .with_timezone(&chrono::Local)
+                .format("%d/%m/%Y %H:%M:%S")
+        )?;
+        if !self.features.is_empty() {
+            write!(f, " (features: {})", self.features.join(", "))?;
+        }
+
+        match &self.build_failure {
+            Some(build_failure) if self.timed_out => write!(f, ": {}", build_failure.message),
+            Some(build_failure) => write!(
+                f,
+                ", cargo exited with code {}:\n{}",
+                build_failure.cargo_error_code,
+                build_failure.message.trim_end()
+            ),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Trait for validating repositories
-pub trait RepoValidator {
+pub trait RepoValidator: Send {
     fn clean(&mut self) {}
 
-    fn set_dependency_req(&mut self, name: String, version_req: VersionReq) -> Result<(), ()>;
+    /// Create a validator operating on a copy of the repository located at `working_dir`, used to
+    /// validate several crates concurrently. `None` when the validator cannot be duplicated.
+    fn fork(&self, _working_dir: &Path) -> Option<Box<dyn RepoValidator>> {
+        None
+    }
+
+    /// Description of the environment checks run in (e.g. the toolchain), outcomes cached under one
+    /// environment are not reused under another.
+    fn environment(&self) -> String {
+        String::new()
+    }
+
+    /// Set the requirement of the dependency declared in the `kind` table.
+    fn set_dependency_req(
+        &mut self,
+        name: String,
+        version_req: VersionReq,
+        kind: DependencyKind,
+    ) -> Result<(), crate::error::Error>;
+
+    /// Force `version` of the dependency declared in the `kinds` tables into the build.
+    fn set_dependency(
+        &mut self,
+        name: String,
+        version: Version,
+        kinds: &[DependencyKind],
+    ) -> Result<(), crate::error::Error>;
+
+    /// Pin the version of a dependency in the lock file only, leaving its manifest requirement as is.
+    fn set_locked_version(
+        &mut self,
+        name: String,
+        version: Version,
+    ) -> Result<(), crate::error::Error>;
+
+    /// Create the lock file of a repository that has none, so that the baseline versions are those
+    /// cargo selects.
+    fn generate_lockfile(&mut self) -> Result<(), crate::error::Error> {
+        Ok(())
+    }
 
-    fn set_dependency(&mut self, name: String, version: Version) -> Result<(), ()>;
+    /// Checksum recorded in the lock file for `version` of `name`, `None` when the lock file does
+    /// not hold that version or records no checksum for it.
+    fn locked_checksum(&self, _name: &str, _version: &Version) -> Option<String> {
+        None
+    }
 
     fn run_check(
         &mut self,
@@ -94,9 +274,111 @@ pub trait RepoValidator {
     ) -> Result<(), Either<ValidationError, crate::error::Error>>;
 }
 
+/// How a candidate version is forced into the build.
+///
+/// `Exact` writes `=x.y.z` into the manifest: simple, but the exact requirement also constrains the
+/// resolution of the whole graph, so an unrelated crate requiring a newer semver-compatible version
+/// of the dependency makes the candidate fail although it is not the culprit. `Precise` writes a
+/// caret requirement starting at the candidate and then pins the lock file with
+/// `cargo update --precise`: the rest of the lock file is left untouched and only genuine
+/// incompatibilities fail, at the cost of one more cargo invocation per candidate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PinStrategy {
+    Exact,
+    #[default]
+    Precise,
+}
+
+/// How dependency requirements are written into the package manifests.
+///
+/// `Cargo` runs `cargo add`, which rewrites the whole entry and may reorder or reformat the
+/// surrounding table. `TomlEdit` parses the manifest and replaces the version string of the entry in
+/// the original text, keeping the formatting, comments and key order of the manifest, so that the
+/// resulting diff is minimal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EditBackend {
+    Cargo,
+    #[default]
+    TomlEdit,
+}
+
 /// A Cargo-based implementation of RepoValidator
+#[derive(Clone)]
 pub struct CargoRepoValidator {
     cargo_command: String,
+    working_dir: Option<PathBuf>,
+    toolchain: Option<String>,
+    target_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
+    registries: BTreeMap<String, String>,
+    platforms: BTreeMap<String, String>,
+    features: BTreeMap<String, Vec<String>>,
+    no_default_features: BTreeSet<String>,
+    workspace_dependencies: BTreeSet<String>,
+    pin_strategy: PinStrategy,
+    edit_backend: EditBackend,
+    dependency_manifests: BTreeMap<String, Vec<(PathBuf, DependencyKind)>>,
+    stream_output: bool,
+    frozen: bool,
+}
+
+/// Copy everything read from `reader` to `writer` while keeping a copy of it.
+fn tee(mut reader: impl Read, mut writer: impl Write) -> Vec<u8> {
+    let mut captured = vec![];
+    let mut buffer = [0u8; 4096];
+
+    while let Ok(n) = reader.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+        captured.extend_from_slice(&buffer[..n]);
+        let _ = writer.write_all(&buffer[..n]).and_then(|_| writer.flush());
+    }
+
+    captured
+}
+
+/// Set once the run was interrupted (Ctrl-C), see [`interrupt`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop the validations: from then on, a command that fails (e.g. because it received the interrupt
+/// too) or that runs in its own process group (killed right away) reports
+/// [`Error::Interrupted`](crate::error::Error::Interrupted) instead of a failing candidate.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Release);
+}
+
+/// Whether [`interrupt`] was called.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Acquire)
+}
+
+/// Wait for `child` to exit, killing it (and its process group on unix) once `timeout` elapsed or
+/// the run is interrupted. Returns `None` when the process was killed.
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: Duration,
+) -> Result<Option<ExitStatus>, crate::error::Error> {
+    let started_at = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(crate::error::Error::AnyIoError)? {
+            return Ok(Some(status));
+        }
+        if started_at.elapsed() >= timeout || is_interrupted() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    #[cfg(unix)]
+    // SAFETY: kill(2) has no memory safety requirements, the child leads its own process group
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    Ok(None)
 }
 
 impl CargoRepoValidator {
@@ -104,14 +386,105 @@ impl CargoRepoValidator {
         &self,
         args: &[String],
     ) -> Result<std::process::Output, crate::error::Error> {
-        let elem = std::process::Command::new(self.cargo_command.as_str())
-            .args(args)
-            .output()
-            .map_err(crate::error::Error::AnyIoError)?;
+        // `--frozen` follows the subcommand, the arguments of `cargo test` may end with `-- <filters>`
+        if self.frozen
+            && let Some((subcommand, rest)) = args.split_first()
+            && !subcommand.starts_with('-')
+        {
+            let mut frozen_args = vec![subcommand.clone(), "--frozen".to_string()];
+            frozen_args.extend_from_slice(rest);
+            return self.run_command(&self.cargo_command, &frozen_args);
+        }
+
+        self.run_command(&self.cargo_command, args)
+    }
+
+    /// Run `program` in the working directory with the toolchain and timeout of the validator.
+    fn run_command(
+        &self,
+        program: &str,
+        args: &[String],
+    ) -> Result<std::process::Output, crate::error::Error> {
+        let mut command = std::process::Command::new(program);
+        command.args(args);
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
+        if let Some(toolchain) = &self.toolchain {
+            command.env("RUSTUP_TOOLCHAIN", toolchain);
+        }
+        if let Some(target_dir) = &self.target_dir {
+            command.env("CARGO_TARGET_DIR", target_dir);
+        }
+
+        let elem = if self.stream_output || self.timeout.is_some() {
+            // Output is piped to be captured, keep cargo's colors when the user watches a terminal
+            if self.stream_output
+                && std::env::var_os("CARGO_TERM_COLOR").is_none()
+                && std::io::stderr().is_terminal()
+            {
+                command.env("CARGO_TERM_COLOR", "always");
+            }
+
+            // A process group of its own lets a timeout kill cargo along with rustc and build scripts
+            #[cfg(unix)]
+            if self.timeout.is_some() {
+                std::os::unix::process::CommandExt::process_group(&mut command, 0);
+            }
+
+            let mut child = command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(crate::error::Error::AnyIoError)?;
+
+            // Both streams go to stderr, stdout may be reserved for the JSON report
+            let (stdout_sink, stderr_sink): (Box<dyn Write + Send>, Box<dyn Write + Send>) =
+                if self.stream_output {
+                    (Box::new(std::io::stderr()), Box::new(std::io::stderr()))
+                } else {
+                    (Box::new(std::io::sink()), Box::new(std::io::sink()))
+                };
+            let stdout = child.stdout.take().unwrap();
+            let stderr = child.stderr.take().unwrap();
+            let stdout = std::thread::spawn(move || tee(stdout, stdout_sink));
+            let stderr = std::thread::spawn(move || tee(stderr, stderr_sink));
+
+            let status = match self.timeout {
+                Some(timeout) => wait_with_timeout(&mut child, timeout)?,
+                None => Some(child.wait().map_err(crate::error::Error::AnyIoError)?),
+            };
+            let output = std::process::Output {
+                status: status.unwrap_or_default(),
+                stdout: stdout.join().unwrap_or_default(),
+                stderr: stderr.join().unwrap_or_default(),
+            };
+
+            if status.is_none() && is_interrupted() {
+                return Err(crate::error::Error::Interrupted);
+            }
+            if status.is_none() {
+                let timeout = self.timeout.unwrap_or_default();
+                warn!(
+                    "{} {} did not finish within {}s and was killed",
+                    program,
+                    args.join(" "),
+                    timeout.as_secs()
+                );
+                return Err(crate::error::Error::CommandTimeout {
+                    command: format!("{} {}", program, args.join(" ")),
+                    timeout,
+                });
+            }
+
+            output
+        } else {
+            command.output().map_err(crate::error::Error::AnyIoError)?
+        };
 
         debug!(
-            "Running cargo command: {} {}...{}",
-            self.cargo_command,
+            "Running command: {} {}...{}",
+            program,
             args.iter()
                 .map(|x| x.to_string())
                 .collect::<Vec<_>>()
@@ -123,17 +496,221 @@ impl CargoRepoValidator {
             }
         );
 
+        if !elem.status.success() && is_interrupted() {
+            return Err(crate::error::Error::Interrupted);
+        }
         Ok(elem)
     }
 
     pub fn new(cargo_command: Option<String>) -> Self {
         Self {
             cargo_command: cargo_command.unwrap_or_else(|| "cargo".to_string()),
+            working_dir: None,
+            toolchain: None,
+            target_dir: None,
+            timeout: None,
+            registries: BTreeMap::new(),
+            platforms: BTreeMap::new(),
+            features: BTreeMap::new(),
+            no_default_features: BTreeSet::new(),
+            workspace_dependencies: BTreeSet::new(),
+            pin_strategy: PinStrategy::default(),
+            edit_backend: EditBackend::default(),
+            dependency_manifests: BTreeMap::new(),
+            stream_output: false,
+            frozen: false,
+        }
+    }
+
+    /// Dependencies declared with `default-features = false`, they are re-added the same way
+    pub fn with_no_default_features(mut self, no_default_features: BTreeSet<String>) -> Self {
+        self.no_default_features = no_default_features;
+        self
+    }
+
+    /// Dependencies inherited from `[workspace.dependencies]`, their requirement is written once in the
+    /// workspace root manifest instead of being added to a package with `cargo add`
+    pub fn with_workspace_dependencies(mut self, workspace_dependencies: BTreeSet<String>) -> Self {
+        self.workspace_dependencies = workspace_dependencies;
+        self
+    }
+
+    /// Directory cargo is run from, the process working directory by default. Run cargo from the
+    /// repository so that its `.cargo/config.toml` (target dir, registries, build flags) applies.
+    pub fn with_working_dir(mut self, working_dir: PathBuf) -> Self {
+        self.working_dir = Some(working_dir);
+        self
+    }
+
+    /// Rustup toolchain cargo runs with (through `RUSTUP_TOOLCHAIN`), `None` keeps the default one
+    pub fn with_toolchain(mut self, toolchain: Option<String>) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    /// Pass `--frozen` to every cargo command, so that candidates requiring changes to Cargo.lock (or
+    /// network access) fail with cargo's error instead of updating it
+    pub fn with_frozen(mut self, frozen: bool) -> Self {
+        self.frozen = frozen;
+        self
+    }
+
+    /// Maximum duration of a cargo command: slower commands are killed and their check fails
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Directory cargo writes the build artifacts to (through `CARGO_TARGET_DIR`), which `clean`
+    /// then empties. `None` keeps the default `target/` directory of the repository.
+    pub fn with_target_dir(mut self, target_dir: Option<PathBuf>) -> Self {
+        self.target_dir = target_dir;
+        self
+    }
+
+    /// Version reported by `cargo --version`, failing when the configured toolchain is not installed
+    pub fn cargo_version(&self) -> Result<String, crate::error::Error> {
+        let toolchain_error = |error: String| crate::error::Error::ToolchainUnavailable {
+            toolchain: self
+                .toolchain
+                .clone()
+                .unwrap_or_else(|| "default".to_string()),
+            error,
+        };
+
+        let output = self
+            .run_cargo_command(&["--version".to_string()])
+            .map_err(|e| toolchain_error(e.to_string()))?;
+        if !output.status.success() {
+            return Err(toolchain_error(
+                String::from_utf8_lossy(&output.stderr)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            ));
         }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Cargo.lock of the working directory.
+    fn lock_file(&self) -> Result<crate::cargo::CargoLockFile, crate::error::Error> {
+        let lock_path = self
+            .working_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("Cargo.lock");
+        crate::cargo::CargoLockFile::read_from_path(&lock_path)
+    }
+
+    /// Forward cargo's output live to stderr (while still capturing it) instead of only capturing it
+    pub fn with_stream_output(mut self, stream_output: bool) -> Self {
+        self.stream_output = stream_output;
+        self
+    }
+
+    /// Strategy used by `set_dependency` to force a candidate version into the build
+    pub fn with_pin_strategy(mut self, pin_strategy: PinStrategy) -> Self {
+        self.pin_strategy = pin_strategy;
+        self
+    }
+
+    /// How `set_dependency_req` writes requirements into the package manifests
+    pub fn with_edit_backend(mut self, edit_backend: EditBackend) -> Self {
+        self.edit_backend = edit_backend;
+        self
+    }
+
+    /// Manifests declaring each dependency and the table declaring it there, relative to the working
+    /// directory. With [`EditBackend::TomlEdit`], these are the entries edited in place; dependencies
+    /// missing from them are added with `cargo add`.
+    pub fn with_dependency_manifests(
+        mut self,
+        dependency_manifests: BTreeMap<String, Vec<(PathBuf, DependencyKind)>>,
+    ) -> Self {
+        self.dependency_manifests = dependency_manifests;
+        self
+    }
+
+    /// Alternative registry (by name) of each dependency not coming from crates.io
+    pub fn with_registries(mut self, registries: BTreeMap<String, String>) -> Self {
+        self.registries = registries;
+        self
+    }
+
+    /// Platform (`cfg(..)` expression or target triple) of each dependency only declared in a
+    /// `[target.'<platform>'.*]` table
+    pub fn with_platforms(mut self, platforms: BTreeMap<String, String>) -> Self {
+        self.platforms = platforms;
+        self
+    }
+
+    /// Features each dependency is used with, enabled whenever a candidate version is added so that
+    /// versions lacking one of them fail
+    pub fn with_features(mut self, features: BTreeMap<String, Vec<String>>) -> Self {
+        self.features = features;
+        self
+    }
+}
+
+/// Whether a failed `cargo test` got to run the tests, from its stderr: cargo reports failing test
+/// binaries (or doctests) with a "test failed, to rerun pass ..." or "N targets failed:" line, and
+/// stops before that when the crate or its tests do not compile.
+fn tests_ran(stderr: &str) -> bool {
+    stderr.lines().any(|line| {
+        let Some(message) = line.trim_start().strip_prefix("error: ") else {
+            return false;
+        };
+        message.contains("failed, to rerun pass") || message.ends_with("targets failed:")
+    })
+}
+
+/// A check killed on timeout is a failed validation, other errors abort the resolution.
+fn timed_out_check(
+    error: crate::error::Error,
+    features: &[String],
+) -> Either<ValidationError, crate::error::Error> {
+    match error {
+        crate::error::Error::CommandTimeout { .. } => Either::Left(ValidationError {
+            tests_failed: false,
+            build_failure: Some(BuildFailure {
+                cargo_error_code: -1,
+                message: error.to_string(),
+            }),
+            runned_at: Utc::now(),
+            timed_out: true,
+            features: features.to_vec(),
+        }),
+        error => Either::Right(error),
     }
 }
 
 impl RepoValidator for CargoRepoValidator {
+    fn fork(&self, working_dir: &Path) -> Option<Box<dyn RepoValidator>> {
+        // Workers sharing a target directory would wait on each other's build lock
+        let target_dir = self.target_dir.as_ref().map(|target_dir| {
+            target_dir.join(working_dir.file_name().unwrap_or(working_dir.as_os_str()))
+        });
+        Some(Box::new(Self {
+            working_dir: Some(working_dir.to_path_buf()),
+            target_dir,
+            ..self.clone()
+        }))
+    }
+
+    fn environment(&self) -> String {
+        let environment = match &self.toolchain {
+            Some(toolchain) => format!("{} +{}", self.cargo_command, toolchain),
+            None => self.cargo_command.clone(),
+        };
+        if self.frozen {
+            format!("{} --frozen", environment)
+        } else {
+            environment
+        }
+    }
+
     fn clean(&mut self) {
         let _ = self
             .run_cargo_command(&["clean".to_string()])
@@ -142,36 +719,207 @@ impl RepoValidator for CargoRepoValidator {
             });
     }
 
-    fn set_dependency_req(&mut self, name: String, version_req: VersionReq) -> Result<(), ()> {
-        let output = self
-            .run_cargo_command(&["add".to_string(), format!("{}@{}", name, version_req)])
-            .inspect_err(|e| {
-                warn!(
-                    "Failed to set dependency {} to version requirement {}: {}",
-                    name, version_req, e
+    fn set_dependency_req(
+        &mut self,
+        name: String,
+        version_req: VersionReq,
+        kind: DependencyKind,
+    ) -> Result<(), crate::error::Error> {
+        if self.workspace_dependencies.contains(&name) {
+            let manifest_path = self
+                .working_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("Cargo.toml");
+            return crate::cargo::set_workspace_dependency_req(&manifest_path, &name, &version_req)
+                .inspect_err(|e| {
+                    warn!(
+                        "Failed to set workspace dependency {} to version requirement {}: {}",
+                        name, version_req, e
+                    )
+                });
+        }
+
+        let manifests = self
+            .dependency_manifests
+            .get(&name)
+            .into_iter()
+            .flatten()
+            .filter(|(_, manifest_kind)| *manifest_kind == kind)
+            .map(|(manifest_path, _)| manifest_path)
+            .collect::<Vec<_>>();
+        if self.edit_backend == EditBackend::TomlEdit && !manifests.is_empty() {
+            let working_dir = self
+                .working_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("."));
+            return manifests.into_iter().try_for_each(|manifest_path| {
+                crate::cargo::set_package_dependency_req(
+                    &working_dir.join(manifest_path),
+                    &name,
+                    &version_req,
+                    kind,
+                )
+                .inspect_err(|e| {
+                    warn!(
+                        "Failed to set dependency {} to version requirement {}: {}",
+                        name, version_req, e
+                    )
+                })
+            });
+        }
+
+        let mut args = vec!["add".to_string(), format!("{}@{}", name, version_req)];
+        args.extend(kind.cargo_add_flag().map(String::from));
+        if let Some(registry) = self.registries.get(&name) {
+            args.extend(["--registry".to_string(), registry.clone()]);
+        }
+        if let Some(platform) = self.platforms.get(&name) {
+            args.extend(["--target".to_string(), platform.clone()]);
+        }
+        if let Some(features) = self.features.get(&name) {
+            args.extend(["--features".to_string(), features.join(",")]);
+        }
+        if self.no_default_features.contains(&name) {
+            args.push("--no-default-features".to_string());
+        }
+
+        let output = self.run_cargo_command(&args).inspect_err(|e| {
+            warn!(
+                "Failed to set dependency {} to version requirement {}: {}",
+                name, version_req, e
+            )
+        })?;
+        if !output.status.success() {
+            return Err(crate::error::Error::Other(
+                format!(
+                    "cargo add {}@{} failed: {}",
+                    name,
+                    version_req,
+                    String::from_utf8_lossy(&output.stderr).trim()
                 )
+                .into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn set_dependency(
+        &mut self,
+        name: String,
+        version: Version,
+        kinds: &[DependencyKind],
+    ) -> Result<(), crate::error::Error> {
+        let op = match self.pin_strategy {
+            PinStrategy::Exact => Op::Exact,
+            PinStrategy::Precise => Op::Caret,
+        };
+        let version_req = VersionReq {
+            comparators: vec![Comparator {
+                op,
+                major: version.major,
+                minor: Some(version.minor),
+                patch: Some(version.patch),
+                pre: version.pre.clone(),
+            }],
+        };
+
+        for kind in kinds {
+            self.set_dependency_req(name.clone(), version_req.clone(), *kind)?;
+        }
+
+        // The tables share the entry of the lock file, pinned once for all of them
+        if self.pin_strategy == PinStrategy::Precise {
+            self.set_locked_version(name, version)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_locked_version(
+        &mut self,
+        name: String,
+        version: Version,
+    ) -> Result<(), crate::error::Error> {
+        // Cargo.lock may hold several versions of the dependency, the one updated is named by the
+        // version currently locked: the one compatible with the candidate, else the newest
+        let locked = self
+            .lock_file()
+            .inspect_err(|e| debug!("Cannot read the locked versions of {}: {}", name, e))
+            .map(|lock_file| {
+                lock_file
+                    .packages
+                    .into_iter()
+                    .filter(|package| package.name == name)
+                    .map(|package| package.version)
+                    .collect::<Vec<_>>()
             })
-            .map_err(|_| ())?;
+            .unwrap_or_default();
+        let compatible = VersionReq {
+            comparators: vec![Comparator {
+                op: Op::Caret,
+                major: version.major,
+                minor: (version.major == 0).then_some(version.minor),
+                patch: (version.major == 0 && version.minor == 0).then_some(version.patch),
+                pre: Prerelease::EMPTY,
+            }],
+        };
+        let spec = match locked
+            .iter()
+            .filter(|locked| compatible.matches(locked))
+            .max()
+            .or_else(|| locked.iter().max())
+        {
+            Some(locked) => format!("{}@{}", name, locked),
+            None => name.clone(),
+        };
+
+        let output = self.run_cargo_command(&[
+            "update".to_string(),
+            "--package".to_string(),
+            spec.clone(),
+            "--precise".to_string(),
+            version.to_string(),
+        ])?;
         if !output.status.success() {
-            return Err(());
+            return Err(crate::error::Error::Other(
+                format!(
+                    "cargo update --package {} --precise {} failed: {}",
+                    spec,
+                    version,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into(),
+            ));
         }
 
         Ok(())
     }
 
-    fn set_dependency(&mut self, name: String, version: Version) -> Result<(), ()> {
-        self.set_dependency_req(
-            name,
-            VersionReq {
-                comparators: vec![Comparator {
-                    op: Op::Exact,
-                    major: version.major,
-                    minor: Some(version.minor),
-                    patch: Some(version.patch),
-                    pre: version.pre,
-                }],
-            },
-        )
+    fn locked_checksum(&self, name: &str, version: &Version) -> Option<String> {
+        self.lock_file()
+            .inspect_err(|e| debug!("Cannot read the checksum of {} {}: {}", name, version, e))
+            .ok()?
+            .packages
+            .into_iter()
+            .find(|package| package.name == name && &package.version == version)?
+            .checksum
+    }
+
+    fn generate_lockfile(&mut self) -> Result<(), crate::error::Error> {
+        let output = self.run_cargo_command(&["generate-lockfile".to_string()])?;
+        if !output.status.success() {
+            return Err(crate::error::Error::Other(
+                format!(
+                    "cargo generate-lockfile failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into(),
+            ));
+        }
+
+        Ok(())
     }
 
     fn run_check(
@@ -179,13 +927,26 @@ impl RepoValidator for CargoRepoValidator {
         check: Check,
     ) -> Result<(), Either<ValidationError, crate::error::Error>> {
         let mut args = vec![];
+        let features = match check {
+            Check::Build { build_opts }
+            | Check::Check { build_opts }
+            | Check::RunTest { build_opts, .. } => build_opts.feature_list(),
+            Check::Custom { .. } => vec![],
+        };
 
         match check {
-            Check::Build { build_opts } => {
-                args.push("build".to_string());
+            Check::Build { build_opts } | Check::Check { build_opts } => {
+                let subcommand = if matches!(check, Check::Check { .. }) {
+                    "check"
+                } else {
+                    "build"
+                };
+                args.push(subcommand.to_string());
                 args.extend(build_opts.arguments());
 
-                let output = self.run_cargo_command(&args).map_err(Either::Right)?;
+                let output = self
+                    .run_cargo_command(&args)
+                    .map_err(|e| timed_out_check(e, &features))?;
                 let status = output.status.code().unwrap_or(1);
 
                 if status != 0 {
@@ -198,6 +959,8 @@ impl RepoValidator for CargoRepoValidator {
                         tests_failed: false,
                         build_failure: Some(build_failure),
                         runned_at: Utc::now(),
+                        timed_out: false,
+                        features,
                     };
 
                     return Err(Either::Left(validation_error));
@@ -213,14 +976,72 @@ impl RepoValidator for CargoRepoValidator {
                 args.extend(build_opts.arguments());
                 args.extend(test_runner.arguments());
 
-                let output = self.run_cargo_command(&args).map_err(Either::Right)?;
+                let mut output = self
+                    .run_cargo_command(&args)
+                    .map_err(|e| timed_out_check(e, &features))?;
+                if output.status.success()
+                    && let Some(doc_arguments) = test_runner.doc_arguments()
+                {
+                    let mut args = vec!["test".to_string()];
+                    args.extend(build_opts.arguments());
+                    args.extend(doc_arguments);
+                    output = self
+                        .run_cargo_command(&args)
+                        .map_err(|e| timed_out_check(e, &features))?;
+                }
+                let status = output.status.code().unwrap_or(1);
+
+                if status != 0 {
+                    // cargo test also fails when the tests do not compile, which is a build failure
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let validation_error = if tests_ran(&stderr) {
+                        ValidationError {
+                            tests_failed: true,
+                            build_failure: None,
+                            runned_at: Utc::now(),
+                            timed_out: false,
+                            features,
+                        }
+                    } else {
+                        ValidationError {
+                            tests_failed: false,
+                            build_failure: Some(BuildFailure {
+                                cargo_error_code: status,
+                                message: stderr.to_string(),
+                            }),
+                            runned_at: Utc::now(),
+                            timed_out: false,
+                            features,
+                        }
+                    };
+
+                    return Err(Either::Left(validation_error));
+                }
+
+                Ok(())
+            }
+            Check::Custom { command } => {
+                let Some((program, args)) = command.split_first() else {
+                    return Err(Either::Right(crate::error::Error::Other(
+                        "The check command is empty".into(),
+                    )));
+                };
+
+                let output = self
+                    .run_command(program, args)
+                    .map_err(|e| timed_out_check(e, &features))?;
                 let status = output.status.code().unwrap_or(1);
 
                 if status != 0 {
                     let validation_error = ValidationError {
-                        tests_failed: true,
-                        build_failure: None,
+                        tests_failed: false,
+                        build_failure: Some(BuildFailure {
+                            cargo_error_code: status,
+                            message: String::from_utf8_lossy(&output.stderr).to_string(),
+                        }),
                         runned_at: Utc::now(),
+                        timed_out: false,
+                        features,
                     };
 
                     return Err(Either::Left(validation_error));
@@ -231,3 +1052,346 @@ impl RepoValidator for CargoRepoValidator {
         }
     }
 }
+
+/// Predicate deciding whether a version of a dependency builds, given its crate name.
+#[cfg(any(test, feature = "testing"))]
+pub type VersionPredicate = std::sync::Arc<dyn Fn(&str, &Version) -> bool + Send + Sync>;
+
+/// A RepoValidator that never runs cargo: a check passes when every version forced into the build
+/// is accepted by a predicate, so that the resolver can be driven deterministically (e.g. with
+/// "versions >=1.2, <2.0 of foo are valid"). A dependency only set to a requirement, as done when
+/// restoring the baseline, is considered to build.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone)]
+pub struct MockRepoValidator {
+    predicate: VersionPredicate,
+    versions: BTreeMap<String, Version>,
+    checks: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MockRepoValidator {
+    pub fn new(predicate: impl Fn(&str, &Version) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            predicate: std::sync::Arc::new(predicate),
+            versions: BTreeMap::new(),
+            checks: Default::default(),
+        }
+    }
+
+    /// Accept the versions of each dependency matching its requirement in `valid`, a dependency
+    /// missing from it accepts every version.
+    pub fn with_valid_reqs(valid: BTreeMap<String, VersionReq>) -> Self {
+        Self::new(move |name, version| valid.get(name).is_none_or(|req| req.matches(version)))
+    }
+
+    /// Version of `name` currently forced into the build.
+    pub fn version(&self, name: &str) -> Option<&Version> {
+        self.versions.get(name)
+    }
+
+    /// Number of checks run so far, shared with the forks of this validator.
+    pub fn checks_run(&self) -> usize {
+        self.checks.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl RepoValidator for MockRepoValidator {
+    fn fork(&self, _working_dir: &Path) -> Option<Box<dyn RepoValidator>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn environment(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn set_dependency_req(
+        &mut self,
+        name: String,
+        _version_req: VersionReq,
+        _kind: DependencyKind,
+    ) -> Result<(), crate::error::Error> {
+        self.versions.remove(&name);
+        Ok(())
+    }
+
+    fn set_dependency(
+        &mut self,
+        name: String,
+        version: Version,
+        _kinds: &[DependencyKind],
+    ) -> Result<(), crate::error::Error> {
+        self.versions.insert(name, version);
+        Ok(())
+    }
+
+    fn set_locked_version(
+        &mut self,
+        name: String,
+        version: Version,
+    ) -> Result<(), crate::error::Error> {
+        self.versions.insert(name, version);
+        Ok(())
+    }
+
+    fn run_check(
+        &mut self,
+        check: Check,
+    ) -> Result<(), Either<ValidationError, crate::error::Error>> {
+        self.checks
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let rejected = self
+            .versions
+            .iter()
+            .filter(|(name, version)| !(self.predicate)(name, version))
+            .map(|(name, version)| format!("{}@{}", name, version))
+            .collect::<Vec<_>>();
+        if rejected.is_empty() {
+            return Ok(());
+        }
+
+        Err(Either::Left(ValidationError {
+            tests_failed: matches!(check, Check::RunTest { .. }),
+            build_failure: Some(BuildFailure {
+                cargo_error_code: 101,
+                message: format!("Rejected by the mock validator: {}", rejected.join(", ")),
+            }),
+            runned_at: Utc::now(),
+            timed_out: false,
+            features: vec![],
+        }))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Directory named after `test` holding `lock` as Cargo.lock and a fake cargo recording its
+    /// arguments, one invocation per line, in `cargo.log`.
+    fn fake_cargo_repository(test: &str, lock: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-compat-validator-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Cargo.lock"), lock).unwrap();
+
+        let cargo = write_script(&dir.join("cargo.sh"), "echo \"$*\" >> cargo.log");
+        (dir, cargo)
+    }
+
+    /// Write an executable shell script running `body` at `path`, returning its path.
+    fn write_script(path: &Path, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn precise_strategy_updates_the_locked_version() {
+        let lock = "version = 4\n\n\
+            [[package]]\nname = \"foo\"\nversion = \"0.3.1\"\n\n\
+            [[package]]\nname = \"foo\"\nversion = \"1.4.0\"\n";
+        let (dir, cargo) = fake_cargo_repository("precise", lock);
+        let mut validator = CargoRepoValidator::new(Some(cargo))
+            .with_working_dir(dir.clone())
+            .with_pin_strategy(PinStrategy::Precise);
+
+        validator
+            .set_dependency(
+                "foo".to_string(),
+                Version::new(1, 2, 0),
+                &[DependencyKind::Normal],
+            )
+            .unwrap();
+        validator
+            .set_locked_version("foo".to_string(), Version::new(0, 3, 0))
+            .unwrap();
+
+        let log = std::fs::read_to_string(dir.join("cargo.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                "add foo@^1.2.0",
+                "update --package foo@1.4.0 --precise 1.2.0",
+                "update --package foo@0.3.1 --precise 0.3.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn precise_update_of_a_dependency_missing_from_the_lock_file() {
+        let (dir, cargo) = fake_cargo_repository("precise-missing", "version = 4\n");
+        let mut validator = CargoRepoValidator::new(Some(cargo)).with_working_dir(dir.clone());
+
+        validator
+            .set_locked_version("foo".to_string(), Version::new(1, 2, 0))
+            .unwrap();
+
+        let log = std::fs::read_to_string(dir.join("cargo.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(log.trim(), "update --package foo --precise 1.2.0");
+    }
+
+    #[test]
+    fn repository_target_dir_applies_unless_one_is_given() {
+        let (dir, _) = fake_cargo_repository("target-dir", "version = 4\n");
+        std::fs::create_dir_all(dir.join(".cargo")).unwrap();
+        std::fs::write(
+            dir.join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"local-target\"\n",
+        )
+        .unwrap();
+        let worker = dir.join("worker-1");
+        std::fs::create_dir_all(&worker).unwrap();
+        let log = dir.join("env.log");
+        let cargo = write_script(
+            &dir.join("env.sh"),
+            &format!(
+                "echo \"$PWD ${{CARGO_TARGET_DIR:-unset}}\" >> {}",
+                log.display()
+            ),
+        );
+
+        // Run from the repository without CARGO_TARGET_DIR, its .cargo/config.toml picks the target dir
+        let mut validator =
+            CargoRepoValidator::new(Some(cargo.clone())).with_working_dir(dir.clone());
+        validator
+            .set_locked_version("foo".to_string(), Version::new(1, 2, 0))
+            .unwrap();
+        // The copy of a worker holds the same configuration, its relative target dir stays in the copy
+        let mut forked = validator.fork(&worker).unwrap();
+        forked
+            .set_locked_version("foo".to_string(), Version::new(1, 2, 0))
+            .unwrap();
+        // A given target dir overrides it, with a subdirectory per worker
+        let shared = dir.join("shared-target");
+        let validator = CargoRepoValidator::new(Some(cargo))
+            .with_working_dir(dir.clone())
+            .with_target_dir(Some(shared.clone()));
+        let mut forked = validator.fork(&worker).unwrap();
+        forked
+            .set_locked_version("foo".to_string(), Version::new(1, 2, 0))
+            .unwrap();
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                format!("{} unset", dir.display()),
+                format!("{} unset", worker.display()),
+                format!("{} {}", worker.display(), shared.join("worker-1").display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn candidate_is_written_to_each_table_and_locked_once() {
+        let lock = "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"1.4.0\"\n";
+        let (dir, cargo) = fake_cargo_repository("kinds", lock);
+        let mut validator = CargoRepoValidator::new(Some(cargo)).with_working_dir(dir.clone());
+
+        validator
+            .set_dependency(
+                "foo".to_string(),
+                Version::new(1, 2, 0),
+                &[DependencyKind::Dev],
+            )
+            .unwrap();
+        validator
+            .set_dependency(
+                "foo".to_string(),
+                Version::new(1, 3, 0),
+                &[DependencyKind::Normal, DependencyKind::Build],
+            )
+            .unwrap();
+
+        let log = std::fs::read_to_string(dir.join("cargo.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                "add foo@^1.2.0 --dev",
+                "update --package foo@1.4.0 --precise 1.2.0",
+                "add foo@^1.3.0",
+                "add foo@^1.3.0 --build",
+                "update --package foo@1.4.0 --precise 1.3.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_without_default_features_is_added_without_them() {
+        let (dir, cargo) = fake_cargo_repository("no-default-features", "version = 4\n");
+        let mut validator = CargoRepoValidator::new(Some(cargo))
+            .with_working_dir(dir.clone())
+            .with_pin_strategy(PinStrategy::Exact)
+            .with_no_default_features(BTreeSet::from(["foo".to_string()]));
+
+        for name in ["foo", "bar"] {
+            validator
+                .set_dependency(
+                    name.to_string(),
+                    Version::new(1, 2, 0),
+                    &[DependencyKind::Normal],
+                )
+                .unwrap();
+        }
+
+        let log = std::fs::read_to_string(dir.join("cargo.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            ["add foo@=1.2.0 --no-default-features", "add bar@=1.2.0"]
+        );
+    }
+
+    #[test]
+    fn platform_specific_dependency_is_added_to_its_target_table() {
+        let (dir, cargo) = fake_cargo_repository("platform", "version = 4\n");
+        let mut validator = CargoRepoValidator::new(Some(cargo))
+            .with_working_dir(dir.clone())
+            .with_pin_strategy(PinStrategy::Exact)
+            .with_platforms(BTreeMap::from([(
+                "foo".to_string(),
+                "cfg(unix)".to_string(),
+            )]));
+
+        validator
+            .set_dependency(
+                "foo".to_string(),
+                Version::new(1, 2, 0),
+                &[DependencyKind::Normal],
+            )
+            .unwrap();
+
+        let log = std::fs::read_to_string(dir.join("cargo.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(log.trim(), "add foo@=1.2.0 --target cfg(unix)");
+    }
+
+    #[test]
+    fn failing_tests_are_told_apart_from_tests_not_compiling() {
+        let test_failed = "    Finished `test` profile [unoptimized + debuginfo] target(s) in 0.40s\n\
+             Running unittests src/lib.rs (target/debug/deps/demo-0123456789abcdef)\n\
+             error: test failed, to rerun pass `--lib`\n";
+        assert!(tests_ran(test_failed));
+
+        let several_failed = "error: 2 targets failed:\n    `--lib`\n    `--test integration`\n";
+        assert!(tests_ran(several_failed));
+
+        let not_compiling = "   Compiling demo v0.1.0 (/tmp/demo)\n\
+             error[E0425]: cannot find value `x` in this scope\n\
+             error: could not compile `demo` (lib test) due to 1 previous error\n";
+        assert!(!tests_ran(not_compiling));
+    }
+}